@@ -7,9 +7,13 @@
 
 use super::*;
 
+use cocoa::foundation::NSInteger;
+use objc::runtime::Object;
 use objc_foundation::{NSString, INSString};
 use block::Block;
 
+use std::fmt;
+
 #[repr(u32)]
 #[allow(non_camel_case_types)]
 pub enum MTLCommandBufferStatus {
@@ -21,8 +25,12 @@ pub enum MTLCommandBufferStatus {
     Error = 5,
 }
 
+/// Domain of the `NSError` of a command buffer that failed on the GPU
+pub const MTLCommandBufferErrorDomain: &str = "MTLCommandBufferErrorDomain";
+
 #[repr(u32)]
 #[allow(non_camel_case_types)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum MTLCommandBufferError {
     None = 0,
     Internal = 1,
@@ -36,7 +44,55 @@ pub enum MTLCommandBufferError {
     DeviceRemoved = 11,
 }
 
-type _MTLCommandBufferHandler = Block<(MTLCommandBuffer), ()>;
+impl MTLCommandBufferError {
+    /// The error with the code of an `NSError` in `MTLCommandBufferErrorDomain`, `None` for codes of newer
+    /// versions of Metal.
+    pub fn from_code(code: NSInteger) -> Option<MTLCommandBufferError> {
+        match code {
+            0 => Some(MTLCommandBufferError::None),
+            1 => Some(MTLCommandBufferError::Internal),
+            2 => Some(MTLCommandBufferError::Timeout),
+            3 => Some(MTLCommandBufferError::PageFault),
+            4 => Some(MTLCommandBufferError::Blacklisted),
+            7 => Some(MTLCommandBufferError::NotPermitted),
+            8 => Some(MTLCommandBufferError::OutOfMemory),
+            9 => Some(MTLCommandBufferError::InvalidResource),
+            10 => Some(MTLCommandBufferError::Memoryless),
+            11 => Some(MTLCommandBufferError::DeviceRemoved),
+            _ => None,
+        }
+    }
+}
+
+/// The `error` of a command buffer. `kind` is set for errors of `MTLCommandBufferErrorDomain` with a known
+/// code, the domain and code of other errors are kept as they are.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommandBufferError {
+    pub kind: Option<MTLCommandBufferError>,
+    pub domain: String,
+    pub code: NSInteger,
+    pub description: String,
+}
+
+impl CommandBufferError {
+    pub fn new(domain: &str, code: NSInteger, description: &str) -> CommandBufferError {
+        let kind = if domain == MTLCommandBufferErrorDomain { MTLCommandBufferError::from_code(code) } else { None };
+        CommandBufferError { kind, domain: domain.to_string(), code, description: description.to_string() }
+    }
+}
+
+impl fmt::Display for CommandBufferError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            Some(kind) => write!(f, "{} ({:?})", self.description, kind),
+            None => write!(f, "{} ({} {})", self.description, self.domain, self.code),
+        }
+    }
+}
+
+impl std::error::Error for CommandBufferError {}
+
+pub type CommandBufferHandler<'a> = Block<(&'a CommandBufferRef,), ()>;
 
 pub enum MTLCommandBuffer {}
 
@@ -91,6 +147,49 @@ impl CommandBufferRef {
         }
     }
 
+    /// The handler runs on a thread of Metal's once the GPU has executed the command buffer. Metal copies the
+    /// block, pass a heap block such as `ConcreteBlock::new(..).copy()`.
+    pub fn add_completed_handler(&self, block: &CommandBufferHandler) {
+        unsafe {
+            msg_send![self, addCompletedHandler:block]
+        }
+    }
+
+    /// Like `add_completed_handler`, the handler runs once the command buffer is scheduled to execute on the GPU.
+    pub fn add_scheduled_handler(&self, block: &CommandBufferHandler) {
+        unsafe {
+            msg_send![self, addScheduledHandler:block]
+        }
+    }
+
+    /// The error the command buffer failed with, `None` unless its status is `Error`.
+    pub fn error(&self) -> Option<CommandBufferError> {
+        unsafe {
+            let error: *mut Object = msg_send![self, error];
+            if error.is_null() {
+                return None;
+            }
+            let domain: &NSString = msg_send![error, domain];
+            let code: NSInteger = msg_send![error, code];
+            let description: &NSString = msg_send![error, localizedDescription];
+            Some(CommandBufferError::new(domain.as_str(), code, description.as_str()))
+        }
+    }
+
+    /// Host time in seconds at which the GPU started executing the command buffer, zero before it completed.
+    pub fn gpu_start_time(&self) -> f64 {
+        unsafe {
+            msg_send![self, GPUStartTime]
+        }
+    }
+
+    /// Host time in seconds at which the GPU finished executing the command buffer, zero before it completed.
+    pub fn gpu_end_time(&self) -> f64 {
+        unsafe {
+            msg_send![self, GPUEndTime]
+        }
+    }
+
     pub fn wait_until_scheduled(&self) {
         unsafe {
             msg_send![self, waitUntilScheduled]
@@ -121,3 +220,34 @@ impl CommandBufferRef {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_command_buffer_error_codes() {
+        assert_eq!(MTLCommandBufferError::from_code(2), Some(MTLCommandBufferError::Timeout));
+        assert_eq!(MTLCommandBufferError::from_code(11), Some(MTLCommandBufferError::DeviceRemoved));
+        for &(code, error) in [(0, MTLCommandBufferError::None), (3, MTLCommandBufferError::PageFault),
+                               (7, MTLCommandBufferError::NotPermitted), (10, MTLCommandBufferError::Memoryless)].iter() {
+            assert_eq!(MTLCommandBufferError::from_code(code), Some(error));
+            assert_eq!(error as NSInteger, code);
+        }
+        // Codes in between and of newer versions of Metal
+        assert_eq!(MTLCommandBufferError::from_code(5), None);
+        assert_eq!(MTLCommandBufferError::from_code(12), None);
+        assert_eq!(MTLCommandBufferError::from_code(-1), None);
+    }
+
+    #[test]
+    fn keeps_errors_of_other_domains() {
+        let error = CommandBufferError::new(MTLCommandBufferErrorDomain, 8, "Out of memory");
+        assert_eq!(error.kind, Some(MTLCommandBufferError::OutOfMemory));
+        assert_eq!(error.to_string(), "Out of memory (OutOfMemory)");
+
+        let error = CommandBufferError::new("NSPOSIXErrorDomain", 8, "Exec format error");
+        assert_eq!(error.kind, None);
+        assert_eq!(error.to_string(), "Exec format error (NSPOSIXErrorDomain 8)");
+    }
+}
@@ -0,0 +1,72 @@
+// Adaptive sampling. Next to the running mean of the colour in the output image every pixel keeps the running mean
+// of the squared luminance of its samples, which gives the variance of the mean. Once the error of the mean is small
+// compared to the brightness of the pixel it stops taking samples, and the passes go on until the samples of the
+// budget are spent on the noisy pixels. accumulateImage in tracing.metal updates the statistics like accumulate.
+
+use crate::emitters::luminance;
+use crate::types::PixelStatistics;
+
+// Samples a pixel takes before its error is trusted
+pub const MIN_SAMPLES: u32 = 16;
+// No pixel takes more than this many times the average number of samples of the budget
+pub const MAX_SAMPLES_FACTOR: usize = 4;
+// Darker pixels count as this bright for their relative error
+const MIN_LUMINANCE: f32 = 1e-4;
+
+// Adds a sample of 'color' to a pixel whose colour is the running mean 'mean' of the samples before
+pub fn accumulate(mean: &mut [f32; 4], statistics: &mut PixelStatistics, color: [f32; 3], sample_index: u32, threshold: f32)
+{
+    let mut output_color = [color[0], color[1], color[2], 1.0];
+    let mut luminance_squared = luminance(color) * luminance(color);
+    if sample_index > 0 {
+        let t = sample_index as f32 / (sample_index + 1) as f32;
+        for (output, stored) in output_color.iter_mut().zip(mean.iter()) {
+            *output += (stored - *output) * t;
+        }
+        luminance_squared += (statistics.luminance_squared - luminance_squared) * t;
+    }
+    *mean = output_color;
+    statistics.luminance_squared = luminance_squared;
+    statistics.sample_count = sample_index + 1;
+    let error = relative_error(luminance([mean[0], mean[1], mean[2]]), statistics);
+    statistics.converged = (threshold > 0.0 && statistics.sample_count >= MIN_SAMPLES && error < threshold) as u32;
+}
+
+// Variance of the mean luminance of a pixel, from the unbiased sample variance of its samples
+pub fn variance_of_mean(mean_luminance: f32, statistics: &PixelStatistics) -> f32
+{
+    let n = statistics.sample_count as f32;
+    if statistics.sample_count < 2 {
+        return f32::INFINITY;
+    }
+    (statistics.luminance_squared - mean_luminance * mean_luminance).max(0.0) / (n - 1.0)
+}
+
+// Standard error of the mean luminance relative to its square root, which follows how visible the noise is after the
+// display transfer function better than the error relative to the luminance itself
+pub fn relative_error(mean_luminance: f32, statistics: &PixelStatistics) -> f32
+{
+    variance_of_mean(mean_luminance, statistics).sqrt() / mean_luminance.max(MIN_LUMINANCE).sqrt()
+}
+
+// Whether another pass is needed to spend a budget of 'samples' per pixel on average
+pub fn needs_samples(statistics: &[PixelStatistics], samples: usize) -> bool
+{
+    let taken: usize = statistics.iter().map(|pixel| pixel.sample_count as usize).sum();
+    let most = statistics.iter().map(|pixel| pixel.sample_count as usize).max().unwrap_or(0);
+    statistics.iter().any(|pixel| pixel.converged == 0) && taken < samples * statistics.len() && most < MAX_SAMPLES_FACTOR * samples
+}
+
+pub fn mean_sample_count(statistics: &[PixelStatistics]) -> f32
+{
+    statistics.iter().map(|pixel| pixel.sample_count as f32).sum::<f32>() / statistics.len() as f32
+}
+
+// The variance of the mean luminance, the sample count and one for converged pixels in the colour channels
+pub fn statistics_image(image: &[[f32; 4]], statistics: &[PixelStatistics]) -> Vec<[f32; 4]>
+{
+    image.iter().zip(statistics.iter()).map(|(pixel, statistics)| {
+        let variance = variance_of_mean(luminance([pixel[0], pixel[1], pixel[2]]), statistics);
+        [if variance.is_finite() { variance } else { 0.0 }, statistics.sample_count as f32, statistics.converged.min(1) as f32, 1.0]
+    }).collect()
+}
@@ -0,0 +1,273 @@
+
+// Scattering functions of the materials, the same computations as isSpecular, evaluateBsdf, bsdfPdf and
+// sampleBsdf in tracing.metal. Directions point away from the surface and 'normal' is the geometric normal of the
+// triangle. Apart from glass the materials scatter the same on both sides of a triangle.
+//
+// Diffuse materials are Lambertian with an optional glossy coat given by the specular colour, metals are
+// conductors and both use a GGX (Trowbridge-Reitz) microfacet distribution for the glossy reflection.
+
+use cgmath::*;
+use std::f32::consts::PI;
+
+use crate::types::*;
+
+// Metals smoother than this are perfect mirrors, coats are clamped to it
+pub const MIN_ROUGHNESS: f32 = 0.001;
+
+#[derive(Copy, Clone, Debug)]
+pub struct BsdfSample
+{
+    pub direction: Vector3<f32>,
+    // BSDF times cosine divided by the pdf
+    pub weight: Vector3<f32>,
+    // Solid angle density of the direction, for specular samples the probability of the chosen event
+    pub pdf: f32,
+    pub specular: bool
+}
+
+// True if the material only scatters into discrete directions which next event estimation cannot hit
+pub fn is_specular(material: &Material) -> bool
+{
+    match material.material_type {
+        MaterialType::Diffuse => false,
+        MaterialType::Metal => material.roughness < MIN_ROUGHNESS,
+        MaterialType::Dielectric => true
+    }
+}
+
+// BSDF value for light arriving from 'wi' and leaving towards 'wo', zero for specular materials
+pub fn evaluate(material: &Material, normal: Vector3<f32>, wo: Vector3<f32>, wi: Vector3<f32>) -> Vector3<f32>
+{
+    let normal = if wo.dot(normal) < 0.0 { -normal } else { normal };
+    let cos_theta_o = wo.dot(normal);
+    let cos_theta_i = wi.dot(normal);
+    if is_specular(material) || cos_theta_o <= 0.0 || cos_theta_i <= 0.0 {
+        return vec3(0.0, 0.0, 0.0);
+    }
+
+    let specular = Vector3::from(material.specular);
+    match material.material_type {
+        MaterialType::Metal => microfacet_reflection(specular, material.roughness, normal, wo, wi),
+        _ => {
+            let diffuse = Vector3::from(material.diffuse) / PI;
+            if specular == vec3(0.0, 0.0, 0.0) {
+                return diffuse;
+            }
+            // Light reflected by the coat on the way in or out does not reach the diffuse base
+            let transmission = (vec3(1.0, 1.0, 1.0) - fresnel_schlick(specular, cos_theta_o))
+                .mul_element_wise(vec3(1.0, 1.0, 1.0) - fresnel_schlick(specular, cos_theta_i));
+            diffuse.mul_element_wise(transmission) + microfacet_reflection(specular, material.roughness.max(MIN_ROUGHNESS), normal, wo, wi)
+        }
+    }
+}
+
+// Solid angle density with which sample() chooses 'wi', zero for specular materials
+pub fn pdf(material: &Material, normal: Vector3<f32>, wo: Vector3<f32>, wi: Vector3<f32>) -> f32
+{
+    let normal = if wo.dot(normal) < 0.0 { -normal } else { normal };
+    let cos_theta_i = wi.dot(normal);
+    if is_specular(material) || wo.dot(normal) <= 0.0 || cos_theta_i <= 0.0 {
+        return 0.0;
+    }
+
+    match material.material_type {
+        MaterialType::Metal => microfacet_reflection_pdf(material.roughness, normal, wo, wi),
+        _ => {
+            let probability = coat_probability(material);
+            let mut pdf = (1.0 - probability) * cos_theta_i / PI;
+            if probability > 0.0 {
+                pdf += probability * microfacet_reflection_pdf(material.roughness.max(MIN_ROUGHNESS), normal, wo, wi);
+            }
+            pdf
+        }
+    }
+}
+
+// Samples an incoming direction with the first two random numbers and chooses between the lobes with the
+// third. None if the sampled direction is absorbed.
+pub fn sample(material: &Material, normal: Vector3<f32>, wo: Vector3<f32>, u: Vector3<f32>) -> Option<BsdfSample>
+{
+    if material.material_type == MaterialType::Dielectric {
+        return Some(sample_dielectric(material.ior, normal, wo, u.z));
+    }
+
+    let normal = if wo.dot(normal) < 0.0 { -normal } else { normal };
+    let cos_theta_o = wo.dot(normal);
+    if cos_theta_o <= 0.0 {
+        return None;
+    }
+
+    let specular = Vector3::from(material.specular);
+    if is_specular(material) {
+        return Some(BsdfSample { direction: reflect(-wo, normal), weight: fresnel_schlick(specular, cos_theta_o), pdf: 1.0, specular: true });
+    }
+
+    let direction = match material.material_type {
+        MaterialType::Metal => reflect(-wo, sample_ggx_visible_normal(material.roughness, normal, wo, u.truncate())),
+        _ => {
+            if u.z < coat_probability(material) {
+                reflect(-wo, sample_ggx_visible_normal(material.roughness.max(MIN_ROUGHNESS), normal, wo, u.truncate()))
+            } else {
+                sample_cosine_hemisphere(normal, u.truncate())
+            }
+        }
+    };
+
+    let cos_theta_i = direction.dot(normal);
+    let pdf = pdf(material, normal, wo, direction);
+    if cos_theta_i <= 0.0 || pdf <= 0.0 {
+        return None;
+    }
+    Some(BsdfSample { direction, weight: evaluate(material, normal, wo, direction) * (cos_theta_i / pdf), pdf, specular: false })
+}
+
+// Smooth glass, chooses between reflection and refraction proportional to the Fresnel reflectance which
+// then cancels in the weight
+fn sample_dielectric(ior: f32, normal: Vector3<f32>, wo: Vector3<f32>, u: f32) -> BsdfSample
+{
+    let entering = wo.dot(normal) > 0.0;
+    let normal = if entering { normal } else { -normal };
+    let (eta_i, eta_t) = if entering { (1.0, ior) } else { (ior, 1.0) };
+    let reflectance = fresnel_dielectric(wo.dot(normal), eta_i, eta_t);
+    if u < reflectance {
+        BsdfSample { direction: reflect(-wo, normal), weight: vec3(1.0, 1.0, 1.0), pdf: reflectance, specular: true }
+    } else {
+        BsdfSample { direction: refract(-wo, normal, eta_i / eta_t), weight: vec3(1.0, 1.0, 1.0), pdf: 1.0 - reflectance, specular: true }
+    }
+}
+
+// Probability of sampling the glossy coat of a diffuse material rather than the diffuse base
+fn coat_probability(material: &Material) -> f32
+{
+    let specular = max_component(Vector3::from(material.specular));
+    let diffuse = (1.0 - specular) * max_component(Vector3::from(material.diffuse));
+    if specular + diffuse > 0.0 { specular / (specular + diffuse) } else { 0.0 }
+}
+
+fn microfacet_reflection(f0: Vector3<f32>, alpha: f32, normal: Vector3<f32>, wo: Vector3<f32>, wi: Vector3<f32>) -> Vector3<f32>
+{
+    let half_vector = (wo + wi).normalize();
+    let cos_theta_o = wo.dot(normal);
+    let cos_theta_i = wi.dot(normal);
+    let distribution = ggx_distribution(half_vector.dot(normal), alpha);
+    let masking = smith_g2(cos_theta_o, cos_theta_i, alpha);
+    fresnel_schlick(f0, wi.dot(half_vector)) * (distribution * masking / (4.0 * cos_theta_o * cos_theta_i))
+}
+
+// Density of reflecting 'wo' about a visible normal, the Jacobian of the reflection cancels the half vector cosine
+fn microfacet_reflection_pdf(alpha: f32, normal: Vector3<f32>, wo: Vector3<f32>, wi: Vector3<f32>) -> f32
+{
+    let half_vector = (wo + wi).normalize();
+    let cos_theta_o = wo.dot(normal);
+    smith_g1(cos_theta_o, alpha) * ggx_distribution(half_vector.dot(normal), alpha) / (4.0 * cos_theta_o)
+}
+
+pub fn ggx_distribution(cos_theta_h: f32, alpha: f32) -> f32
+{
+    if cos_theta_h <= 0.0 {
+        return 0.0;
+    }
+    let alpha2 = alpha * alpha;
+    let d = cos_theta_h * cos_theta_h * (alpha2 - 1.0) + 1.0;
+    alpha2 / (PI * d * d)
+}
+
+fn smith_lambda(cos_theta: f32, alpha: f32) -> f32
+{
+    let cos2 = cos_theta * cos_theta;
+    let tan2 = (1.0 - cos2).max(0.0) / cos2;
+    0.5 * ((1.0 + alpha * alpha * tan2).sqrt() - 1.0)
+}
+
+pub fn smith_g1(cos_theta: f32, alpha: f32) -> f32
+{
+    1.0 / (1.0 + smith_lambda(cos_theta, alpha))
+}
+
+// Height correlated masking and shadowing
+pub fn smith_g2(cos_theta_o: f32, cos_theta_i: f32, alpha: f32) -> f32
+{
+    1.0 / (1.0 + smith_lambda(cos_theta_o, alpha) + smith_lambda(cos_theta_i, alpha))
+}
+
+// Microfacet normal distributed proportional to its visible projected area seen from 'wo' (Heitz 2018)
+pub fn sample_ggx_visible_normal(alpha: f32, normal: Vector3<f32>, wo: Vector3<f32>, u: Vector2<f32>) -> Vector3<f32>
+{
+    let (tangent, bitangent) = orthonormal_basis(normal);
+    let local_wo = vec3(wo.dot(tangent), wo.dot(bitangent), wo.dot(normal));
+
+    // Stretch to the hemisphere configuration
+    let vh = vec3(alpha * local_wo.x, alpha * local_wo.y, local_wo.z).normalize();
+    let length2 = vh.x * vh.x + vh.y * vh.y;
+    let t1 = if length2 > 0.0 { vec3(-vh.y, vh.x, 0.0) / length2.sqrt() } else { vec3(1.0, 0.0, 0.0) };
+    let t2 = vh.cross(t1);
+
+    // Uniform point on the disk, warped to the projected visible half of the hemisphere
+    let r = u.x.sqrt();
+    let phi = 2.0 * PI * u.y;
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + vh.z);
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+    let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+
+    // Unstretch
+    let local_h = vec3(alpha * nh.x, alpha * nh.y, nh.z.max(0.0)).normalize();
+    (local_h.x * tangent + local_h.y * bitangent + local_h.z * normal).normalize()
+}
+
+// Orthonormal basis around a unit vector (Duff et al. 2017)
+pub fn orthonormal_basis(n: Vector3<f32>) -> (Vector3<f32>, Vector3<f32>)
+{
+    let sign = if n.z >= 0.0 { 1.0 } else { -1.0 };
+    let a = -1.0 / (sign + n.z);
+    let b = n.x * n.y * a;
+    (vec3(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x), vec3(b, sign + n.y * n.y * a, -n.y))
+}
+
+pub fn sample_cosine_hemisphere(normal: Vector3<f32>, smp: Vector2<f32>) -> Vector3<f32>
+{
+    let r = smp.x.sqrt();
+    let phi = 2.0 * PI * smp.y;
+    let (tangent, bitangent) = orthonormal_basis(normal);
+    (r * phi.cos() * tangent + r * phi.sin() * bitangent + (1.0 - smp.x).max(0.0).sqrt() * normal).normalize()
+}
+
+// Schlick's approximation for a conductor with the given reflectance at normal incidence
+pub fn fresnel_schlick(f0: Vector3<f32>, cos_theta: f32) -> Vector3<f32>
+{
+    f0 + (vec3(1.0, 1.0, 1.0) - f0) * (1.0 - cos_theta).max(0.0).powi(5)
+}
+
+// Unpolarized Fresnel reflectance of a dielectric interface, one on total internal reflection
+pub fn fresnel_dielectric(cos_theta_i: f32, eta_i: f32, eta_t: f32) -> f32
+{
+    let sin_theta_t = eta_i / eta_t * (1.0 - cos_theta_i * cos_theta_i).max(0.0).sqrt();
+    if sin_theta_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_theta_t = (1.0 - sin_theta_t * sin_theta_t).max(0.0).sqrt();
+    let parallel = (eta_t * cos_theta_i - eta_i * cos_theta_t) / (eta_t * cos_theta_i + eta_i * cos_theta_t);
+    let perpendicular = (eta_i * cos_theta_i - eta_t * cos_theta_t) / (eta_i * cos_theta_i + eta_t * cos_theta_t);
+    0.5 * (parallel * parallel + perpendicular * perpendicular)
+}
+
+// The reflect and refract functions of the Metal standard library
+pub fn reflect(incoming: Vector3<f32>, normal: Vector3<f32>) -> Vector3<f32>
+{
+    incoming - 2.0 * normal.dot(incoming) * normal
+}
+
+pub fn refract(incoming: Vector3<f32>, normal: Vector3<f32>, eta: f32) -> Vector3<f32>
+{
+    let cos_theta = normal.dot(incoming);
+    let k = 1.0 - eta * eta * (1.0 - cos_theta * cos_theta);
+    if k < 0.0 {
+        return vec3(0.0, 0.0, 0.0);
+    }
+    eta * incoming - (eta * cos_theta + k.sqrt()) * normal
+}
+
+fn max_component(v: Vector3<f32>) -> f32
+{
+    v.x.max(v.y.max(v.z))
+}
@@ -0,0 +1,122 @@
+
+use cgmath::*;
+use std::f32::consts::PI;
+
+use crate::types::*;
+
+// Limits of the interactive controls, in degrees
+const MAX_ELEVATION: f32 = 89.0;
+const MIN_FOV: f32 = 5.0;
+const MAX_FOV: f32 = 170.0;
+
+// Thin lens camera, a zero aperture radius gives a pinhole camera. The ray generation below is the same
+// computation as generateRays in tracing.metal, which receives the camera as a CameraData uniform.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Camera
+{
+    pub eye: Vector3<f32>,
+    pub target: Vector3<f32>,
+    pub up: Vector3<f32>,
+    // Vertical field of view in degrees
+    pub vertical_fov: f32,
+    // Width divided by height
+    pub aspect: f32,
+    pub aperture_radius: f32,
+    // Distance along the view direction to the plane in focus
+    pub focus_distance: f32
+}
+
+impl Camera {
+
+    // Pinhole camera focused at the target
+    pub fn new(eye: Vector3<f32>, target: Vector3<f32>, up: Vector3<f32>, vertical_fov: f32, aspect: f32) -> Camera
+    {
+        Camera {eye, target, up, vertical_fov, aspect, aperture_radius: 0.0, focus_distance: (target - eye).magnitude()}
+    }
+
+    // Turns the eye around the target, by 'yaw' about the up direction and by 'pitch' towards it. The eye stops short
+    // of the up direction, along which the view would have no right direction.
+    pub fn orbit(&mut self, yaw: Rad<f32>, pitch: Rad<f32>)
+    {
+        let up = self.up.normalize();
+        let offset = self.eye - self.target;
+        let distance = offset.magnitude();
+        let max_elevation = Rad::from(Deg(MAX_ELEVATION)).0;
+        let elevation = (offset.dot(up) / distance).clamp(-1.0, 1.0).asin();
+        let elevation = (elevation + pitch.0).clamp(-max_elevation, max_elevation);
+        let horizontal = Quaternion::from_axis_angle(up, yaw).rotate_vector((offset - offset.dot(up) * up).normalize());
+        self.eye = self.target + distance * (elevation.cos() * horizontal + elevation.sin() * up);
+    }
+
+    // Moves the eye and the target together by 'offset' along the right, up and forward directions of the view
+    pub fn fly(&mut self, offset: Vector3<f32>)
+    {
+        let forward = (self.target - self.eye).normalize();
+        let right = forward.cross(self.up).normalize();
+        let up = right.cross(forward);
+        let translation = offset.x * right + offset.y * up + offset.z * forward;
+        self.eye += translation;
+        self.target += translation;
+    }
+
+    // Scales the distance of the eye to the target, the focus distance along with it so that what was in focus
+    // stays in focus
+    pub fn zoom(&mut self, factor: f32)
+    {
+        self.eye = self.target + factor * (self.eye - self.target);
+        self.focus_distance *= factor;
+    }
+
+    // Widens the field of view by 'degrees', a negative angle narrows it
+    pub fn change_fov(&mut self, degrees: f32)
+    {
+        self.vertical_fov = (self.vertical_fov + degrees).clamp(MIN_FOV, MAX_FOV);
+    }
+
+    pub fn data(&self) -> CameraData
+    {
+        let forward = (self.target - self.eye).normalize();
+        let right = forward.cross(self.up).normalize();
+        let up = right.cross(forward);
+        let tan_half_fov = (Deg(self.vertical_fov) / 2.0).tan();
+
+        CameraData {origin: self.eye.into(), forward: forward.into(), right: (right * tan_half_fov * self.aspect).into(),
+            up: (up * tan_half_fov).into(), lens_radius: self.aperture_radius, focus_distance: self.focus_distance}
+    }
+
+    // Ray through the given pixel offset by the jitter in [0, 1)^2 and leaving the lens at the point given
+    // by the lens sample in [0, 1)^2. Pixel row 0 is the bottom row of the image.
+    pub fn generate_ray(&self, pixel: (usize, usize), size: (usize, usize), jitter: Vector2<f32>, lens_sample: Vector2<f32>) -> Ray
+    {
+        self.data().generate_ray(pixel, size, jitter, lens_sample)
+    }
+}
+
+impl Default for Camera
+{
+    // The camera that used to be hard-coded in generateRays
+    fn default() -> Self
+    {
+        Camera::new(vec3(0.0, 1.0, 2.1), vec3(0.0, 1.0, 1.1), vec3(0.0, 1.0, 0.0), 90.0, 4.0 / 3.0)
+    }
+}
+
+impl CameraData {
+
+    pub fn generate_ray(&self, pixel: (usize, usize), size: (usize, usize), jitter: Vector2<f32>, lens_sample: Vector2<f32>) -> Ray
+    {
+        let right = Vector3::from(self.right);
+        let up = Vector3::from(self.up);
+        let uv = vec2((pixel.0 as f32 + jitter.x) / size.0 as f32, (pixel.1 as f32 + jitter.y) / size.1 as f32) * 2.0 - vec2(1.0, 1.0);
+        let direction = Vector3::from(self.forward) + uv.x * right + uv.y * up;
+
+        // The pinhole ray hits the focal plane at the distance along 'forward' times the unnormalized direction
+        let focus_point = Vector3::from(self.origin) + self.focus_distance * direction;
+        let radius = self.lens_radius * lens_sample.x.sqrt();
+        let angle = 2.0 * PI * lens_sample.y;
+        let origin = Vector3::from(self.origin) + radius * angle.cos() * right.normalize() + radius * angle.sin() * up.normalize();
+
+        Ray {origin: origin.into(), min_distance: EPSILON, direction: (focus_point - origin).normalize().into(), max_distance: f32::INFINITY,
+            color: [0.0; 3], throughput: [1.0; 3], bsdf_pdf: 0.0, normal: [0.0; 3], sample_index: 0}
+    }
+}
@@ -0,0 +1,319 @@
+
+use std::fmt;
+use std::str::FromStr;
+use std::path::PathBuf;
+
+use cgmath::*;
+
+use crate::camera::Camera;
+use crate::output::ImageFormat;
+use crate::scene::DEFAULT_SCENE_PATH;
+use crate::settings::RenderSettings;
+use crate::types::{LightSampling, EmitterSelection, SamplerType};
+
+pub const USAGE: &str = "Usage: metal-ray-tracing-rs [OPTIONS] [SCENE]
+
+Arguments:
+  [SCENE]                 OBJ file to render [default: ../../Data/3D models/cornellbox/cornellbox.obj]
+
+Options:
+  --width <PIXELS>        Image width [default: 800]
+  --height <PIXELS>       Image height [default: 600]
+  --samples <COUNT>       Number of samples per pixel [default: 1000]
+  --adaptive-threshold <ERROR>
+                          Stop sampling pixels whose relative error falls below the threshold and spend
+                          their samples on noisier pixels [default: 0, sample all pixels equally]
+  --seed <SEED>           Seed for the random numbers, the same seed and number of samples render the
+                          same image [default: 0]
+  --sampler <TYPE>        Numbers paths are sampled with, 'independent' random numbers, the 'sobol'
+                          sequence or 'blue-noise' [default: sobol]
+  --max-depth <BOUNCES>   Maximum number of bounces along a path [default: 8]
+  --light-sampling <MODE> Direct light from 'emitter' sampling, 'bsdf' sampling or both combined with
+                          the 'balance' or 'power' heuristic [default: power]
+  --emitter-selection <MODE>
+                          Pick emitters by their 'power' or from the light 'tree' by their estimated
+                          contribution [default: tree]
+  --environment <FILE>    Equirectangular .hdr or .exr image lighting rays that leave the scene
+  --sky <R,G,B>           Constant radiance of rays that leave the scene without an environment map
+                          [default: 0,0,0]
+  --eye <X,Y,Z>           Camera position [default: 0,1,2.1]
+  --target <X,Y,Z>        Point the camera looks at [default: 0,1,1.1]
+  --up <X,Y,Z>            Camera up direction [default: 0,1,0]
+  --fov <DEGREES>         Vertical field of view [default: 90]
+  --aperture <RADIUS>     Lens radius for depth of field [default: 0, a pinhole camera]
+  --focus-distance <DIST> Distance to the plane in focus [default: distance from eye to target]
+  --output <FILE>         Write the rendered image to a .png, .pfm or .exr file
+  --aov-output <FILE>     Write the variance of the mean, the sample count and the convergence of every
+                          pixel to the colour channels of a .pfm or .exr file
+  --backend <BACKEND>     Ray tracing backend, 'metal' or 'cpu' [default: metal on macOS, cpu elsewhere]
+  --headless              Render without opening a window
+  -h, --help              Print this help";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Backend
+{
+    Metal,
+    Cpu
+}
+
+impl Default for Backend
+{
+    fn default() -> Self
+    {
+        if cfg!(target_os = "macos") { Backend::Metal } else { Backend::Cpu }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options
+{
+    pub scene: PathBuf,
+    pub width: usize,
+    pub height: usize,
+    pub samples: usize,
+    pub settings: RenderSettings,
+    pub environment: Option<PathBuf>,
+    pub sky: [f32; 3],
+    // The aspect ratio is given by the image size
+    pub camera: Camera,
+    pub output: Option<PathBuf>,
+    // Per pixel statistics of the adaptive sampling
+    pub aov_output: Option<PathBuf>,
+    pub backend: Backend,
+    pub headless: bool
+}
+
+impl Default for Options
+{
+    fn default() -> Self
+    {
+        Options { scene: PathBuf::from(DEFAULT_SCENE_PATH), width: 800, height: 600, samples: 1000, settings: RenderSettings::default(), environment: None, sky: [0.0; 3],
+            camera: Camera::default(), output: None, aov_output: None,
+            backend: Backend::default(), headless: false }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CliError
+{
+    HelpRequested,
+    UnknownArgument(String),
+    MissingValue(String),
+    InvalidValue { option: String, value: String, expected: String },
+    Invalid(String)
+}
+
+impl fmt::Display for CliError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self {
+            CliError::HelpRequested => write!(f, "{}", USAGE),
+            CliError::UnknownArgument(argument) => write!(f, "unexpected argument '{}'", argument),
+            CliError::MissingValue(option) => write!(f, "option '{}' requires a value", option),
+            CliError::InvalidValue { option, value, expected } =>
+                write!(f, "invalid value '{}' for '{}', expected {}", value, option, expected),
+            CliError::Invalid(message) => write!(f, "{}", message)
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+// Parses the arguments following the program name. Options may be given as '--name value' or '--name=value'.
+pub fn parse<I, S>(args: I) -> Result<Options, CliError>
+    where I: IntoIterator<Item = S>, S: Into<String>
+{
+    let mut options = Options::default();
+    let mut scene = None;
+    let mut focus_distance = None;
+
+    let mut args = args.into_iter().map(|arg| arg.into());
+    while let Some(arg) = args.next() {
+        let (name, inline_value) = match arg.find('=') {
+            Some(i) if arg.starts_with("--") => (arg[..i].to_string(), Some(arg[i + 1..].to_string())),
+            _ => (arg.clone(), None)
+        };
+
+        let mut value = || inline_value.clone().or_else(|| args.next()).ok_or_else(|| CliError::MissingValue(name.clone()));
+        match name.as_str() {
+            "-h" | "--help" => return Err(CliError::HelpRequested),
+            "--width" => options.width = parse_count(&name, &value()?, 1)?,
+            "--height" => options.height = parse_count(&name, &value()?, 1)?,
+            "--samples" => options.samples = parse_count(&name, &value()?, 1)?,
+            "--seed" => {
+                let value = value()?;
+                options.settings.seed = value.parse().map_err(|_| invalid_value(&name, &value, "an unsigned integer"))?;
+            },
+            "--sampler" => {
+                let value = value()?;
+                options.settings.sampler = match value.to_lowercase().as_str() {
+                    "independent" => SamplerType::Independent,
+                    "sobol" => SamplerType::Sobol,
+                    "blue-noise" => SamplerType::BlueNoise,
+                    _ => return Err(invalid_value(&name, &value, "'independent', 'sobol' or 'blue-noise'"))
+                };
+            },
+            "--adaptive-threshold" => {
+                let value = value()?;
+                options.settings.adaptive_threshold = match value.parse::<f32>() {
+                    Ok(threshold) if threshold >= 0.0 && threshold.is_finite() => threshold,
+                    _ => return Err(invalid_value(&name, &value, "a non-negative number"))
+                };
+            },
+            "--max-depth" => options.settings.max_depth = parse_count(&name, &value()?, 1)?,
+            "--light-sampling" => {
+                let value = value()?;
+                options.settings.light_sampling = match value.to_lowercase().as_str() {
+                    "emitter" => LightSampling::Emitter,
+                    "bsdf" => LightSampling::Bsdf,
+                    "balance" => LightSampling::MisBalance,
+                    "power" => LightSampling::MisPower,
+                    _ => return Err(invalid_value(&name, &value, "'emitter', 'bsdf', 'balance' or 'power'"))
+                };
+            },
+            "--emitter-selection" => {
+                let value = value()?;
+                options.settings.emitter_selection = match value.to_lowercase().as_str() {
+                    "power" => EmitterSelection::Power,
+                    "tree" => EmitterSelection::LightTree,
+                    _ => return Err(invalid_value(&name, &value, "'power' or 'tree'"))
+                };
+            },
+            "--environment" => options.environment = Some(PathBuf::from(value()?)),
+            "--sky" => {
+                let value = value()?;
+                let sky = parse_vector(&name, &value)?;
+                if sky.x < 0.0 || sky.y < 0.0 || sky.z < 0.0 {
+                    return Err(invalid_value(&name, &value, "three non-negative numbers"));
+                }
+                options.sky = sky.into();
+            },
+            "--eye" => options.camera.eye = parse_vector(&name, &value()?)?,
+            "--target" => options.camera.target = parse_vector(&name, &value()?)?,
+            "--up" => options.camera.up = parse_vector(&name, &value()?)?,
+            "--fov" => {
+                let value = value()?;
+                options.camera.vertical_fov = match value.parse::<f32>() {
+                    Ok(fov) if fov > 0.0 && fov < 180.0 => fov,
+                    _ => return Err(invalid_value(&name, &value, "an angle in degrees between 0 and 180"))
+                };
+            },
+            "--aperture" => {
+                let value = value()?;
+                options.camera.aperture_radius = match value.parse::<f32>() {
+                    Ok(radius) if radius >= 0.0 && radius.is_finite() => radius,
+                    _ => return Err(invalid_value(&name, &value, "a non-negative number"))
+                };
+            },
+            "--focus-distance" => {
+                let value = value()?;
+                focus_distance = match value.parse::<f32>() {
+                    Ok(distance) if distance > 0.0 && distance.is_finite() => Some(distance),
+                    _ => return Err(invalid_value(&name, &value, "a positive number"))
+                };
+            },
+            "--output" => options.output = Some(PathBuf::from(value()?)),
+            "--aov-output" => options.aov_output = Some(PathBuf::from(value()?)),
+            "--backend" => {
+                let value = value()?;
+                options.backend = match value.to_lowercase().as_str() {
+                    "metal" => Backend::Metal,
+                    "cpu" => Backend::Cpu,
+                    _ => return Err(invalid_value(&name, &value, "'metal' or 'cpu'"))
+                };
+            },
+            "--headless" => {
+                if inline_value.is_some() {
+                    return Err(CliError::UnknownArgument(arg));
+                }
+                options.headless = true;
+            },
+            _ if arg.starts_with('-') && arg.len() > 1 => return Err(CliError::UnknownArgument(arg)),
+            _ if scene.is_none() => scene = Some(PathBuf::from(arg)),
+            _ => return Err(CliError::UnknownArgument(arg))
+        }
+    }
+
+    if let Some(scene) = scene {
+        options.scene = scene;
+    }
+    options.camera.focus_distance = focus_distance.unwrap_or_else(|| (options.camera.target - options.camera.eye).magnitude());
+    validate(&options)?;
+    Ok(options)
+}
+
+fn validate(options: &Options) -> Result<(), CliError>
+{
+    if !options.scene.is_file() {
+        return Err(CliError::Invalid(format!("scene file '{}' does not exist", options.scene.display())));
+    }
+    if let Some(environment) = &options.environment {
+        if !environment.is_file() {
+            return Err(CliError::Invalid(format!("environment map '{}' does not exist", environment.display())));
+        }
+        let extension = environment.extension().and_then(|extension| extension.to_str()).map(|extension| extension.to_lowercase());
+        if extension.as_deref() != Some("hdr") && extension.as_deref() != Some("exr") {
+            return Err(CliError::Invalid(format!("unsupported environment map format of '{}', expected .hdr or .exr", environment.display())));
+        }
+        if options.sky != [0.0; 3] {
+            return Err(CliError::Invalid("'--sky' cannot be combined with '--environment'".to_string()));
+        }
+    }
+    if options.backend == Backend::Metal && !cfg!(target_os = "macos") {
+        return Err(CliError::Invalid("the metal backend is only available on macOS, use '--backend cpu'".to_string()));
+    }
+    if !options.headless && !cfg!(target_os = "macos") {
+        return Err(CliError::Invalid("the interactive viewer is only available on macOS, use '--headless'".to_string()));
+    }
+    if !options.headless && options.backend == Backend::Cpu {
+        return Err(CliError::Invalid("the interactive viewer requires the metal backend, use '--headless' with '--backend cpu'".to_string()));
+    }
+    let view_direction = options.camera.target - options.camera.eye;
+    if view_direction.magnitude2() == 0.0 {
+        return Err(CliError::Invalid("the camera target must differ from the eye position".to_string()));
+    }
+    if view_direction.cross(options.camera.up).magnitude2() == 0.0 {
+        return Err(CliError::Invalid("the camera up direction must not be parallel to the view direction".to_string()));
+    }
+    if let Some(output) = &options.output {
+        if !options.headless {
+            return Err(CliError::Invalid("'--output' requires '--headless'".to_string()));
+        }
+        if ImageFormat::from_path(output).is_none() {
+            return Err(CliError::Invalid(format!("unsupported output format of '{}', expected .png, .pfm or .exr", output.display())));
+        }
+    }
+    if let Some(aov_output) = &options.aov_output {
+        if !options.headless {
+            return Err(CliError::Invalid("'--aov-output' requires '--headless'".to_string()));
+        }
+        if !matches!(ImageFormat::from_path(aov_output), Some(ImageFormat::Pfm) | Some(ImageFormat::Exr)) {
+            return Err(CliError::Invalid(format!("unsupported AOV output format of '{}', expected .pfm or .exr", aov_output.display())));
+        }
+    }
+    Ok(())
+}
+
+fn parse_count<T: FromStr + PartialOrd + fmt::Display>(option: &str, value: &str, min: T) -> Result<T, CliError>
+{
+    match value.parse::<T>() {
+        Ok(count) if count >= min => Ok(count),
+        _ => Err(invalid_value(option, value, &format!("an integer of at least {}", min)))
+    }
+}
+
+fn parse_vector(option: &str, value: &str) -> Result<Vector3<f32>, CliError>
+{
+    let components: Vec<f32> = value.split(',').map(|c| c.trim().parse::<f32>()).collect::<Result<_, _>>()
+        .map_err(|_| invalid_value(option, value, "three comma separated numbers"))?;
+    match components[..] {
+        [x, y, z] if components.iter().all(|c| c.is_finite()) => Ok(vec3(x, y, z)),
+        _ => Err(invalid_value(option, value, "three comma separated numbers"))
+    }
+}
+
+fn invalid_value(option: &str, value: &str, expected: &str) -> CliError
+{
+    CliError::InvalidValue { option: option.to_string(), value: value.to_string(), expected: expected.to_string() }
+}
@@ -0,0 +1,216 @@
+// Differences between rendered images for regression tests. rmse is the plain root mean squared error. flip is a
+// simplified version of the FLIP perceptual error (Andersson et al. 2020): both images are clamped to the display
+// range, filtered like the eye does at a typical viewing distance and compared by colour, and by the edges and
+// points of their luminance. The pixels are in the layout of the output texture.
+
+use cgmath::*;
+
+// Pixels per degree of visual angle for a 0.7 m viewing distance from a 0.7 m wide monitor with 3840 pixels
+pub const PIXELS_PER_DEGREE: f32 = 67.0;
+
+// Exponent of the colour difference and the point where the difference is compressed, see the FLIP paper
+const COLOR_EXPONENT: f32 = 0.7;
+const COMPRESSION_START: f32 = 0.4;
+const COMPRESSION_VALUE: f32 = 0.95;
+// Exponent of the feature difference
+const FEATURE_EXPONENT: f32 = 0.5;
+
+// Reference white of the sRGB primaries
+const WHITE: [f32; 3] = [0.950_428_5, 1.0, 1.088_900_4];
+
+pub fn rmse(image: &[[f32; 4]], reference: &[[f32; 4]]) -> f32
+{
+    assert_eq!(image.len(), reference.len());
+    let squared_error: f32 = image.iter().zip(reference.iter())
+        .map(|(a, b)| (0..3).map(|i| (a[i] - b[i]) * (a[i] - b[i])).sum::<f32>())
+        .sum();
+    (squared_error / (3 * image.len()) as f32).sqrt()
+}
+
+// Averages blocks of size x size pixels, which removes most of the noise of a render but keeps its bias
+pub fn downsample(pixels: &[[f32; 4]], width: usize, height: usize, size: usize) -> Vec<[f32; 4]>
+{
+    assert!(width.is_multiple_of(size) && height.is_multiple_of(size));
+    let (columns, rows) = (width / size, height / size);
+    let mut result = vec![[0.0; 4]; columns * rows];
+    for y in 0..height {
+        for x in 0..width {
+            let block = &mut result[x / size + columns * (y / size)];
+            for i in 0..4 {
+                block[i] += pixels[x + y * width][i] / (size * size) as f32;
+            }
+        }
+    }
+    result
+}
+
+// Perceived difference of every pixel between zero and one
+pub fn flip(image: &[[f32; 4]], reference: &[[f32; 4]], width: usize, height: usize) -> Vec<f32>
+{
+    assert_eq!(image.len(), width * height);
+    assert_eq!(reference.len(), width * height);
+
+    let filtered_image = spatial_filter(image, width, height);
+    let filtered_reference = spatial_filter(reference, width, height);
+    let color_max = hunt_hyab(linear_rgb_to_lab(vec3(0.0, 1.0, 0.0)), linear_rgb_to_lab(vec3(0.0, 0.0, 1.0))).powf(COLOR_EXPONENT);
+
+    let features_image = features(image, width, height);
+    let features_reference = features(reference, width, height);
+
+    (0..image.len()).map(|i| {
+        let color = hunt_hyab(linear_rgb_to_lab(filtered_image[i]), linear_rgb_to_lab(filtered_reference[i])).powf(COLOR_EXPONENT);
+        let color_error = if color < COMPRESSION_START * color_max {
+            COMPRESSION_VALUE / (COMPRESSION_START * color_max) * color
+        } else {
+            COMPRESSION_VALUE + (color - COMPRESSION_START * color_max) / (color_max - COMPRESSION_START * color_max) * (1.0 - COMPRESSION_VALUE)
+        };
+        let feature = (features_image[i].0 - features_reference[i].0).abs().max((features_image[i].1 - features_reference[i].1).abs());
+        let feature_error = (feature / std::f32::consts::SQRT_2).powf(FEATURE_EXPONENT);
+        color_error.min(1.0).powf(1.0 - feature_error.min(1.0))
+    }).collect()
+}
+
+// Errors between zero and one coloured from black over purple and orange to light yellow, close to the magma map
+pub fn error_heatmap(errors: &[f32]) -> Vec<[f32; 4]>
+{
+    const STOPS: [[f32; 3]; 5] = [[0.001, 0.000, 0.014], [0.232, 0.060, 0.438], [0.716, 0.215, 0.475], [0.994, 0.624, 0.427], [0.987, 0.991, 0.750]];
+    errors.iter().map(|&error| {
+        let t = error.clamp(0.0, 1.0) * (STOPS.len() - 1) as f32;
+        let index = (t as usize).min(STOPS.len() - 2);
+        let f = t - index as f32;
+        let srgb = Vector3::from(STOPS[index]) * (1.0 - f) + Vector3::from(STOPS[index + 1]) * f;
+        [srgb_to_linear(srgb.x), srgb_to_linear(srgb.y), srgb_to_linear(srgb.z), 1.0]
+    }).collect()
+}
+
+// Blurs the opponent colour channels by the contrast sensitivity of the eye, approximated by a single gaussian
+// for each channel, and returns linear RGB in the display range
+fn spatial_filter(pixels: &[[f32; 4]], width: usize, height: usize) -> Vec<Vector3<f32>>
+{
+    let opponent: Vec<Vector3<f32>> = pixels.iter().map(|pixel| xyz_to_ycxcz(linear_rgb_to_xyz(clamp_display(pixel)))).collect();
+    let mut filtered = vec![vec3(0.0, 0.0, 0.0); pixels.len()];
+    for (channel, &b) in [0.0047f32, 0.0053, 0.04].iter().enumerate() {
+        let kernel = gaussian((b / (2.0 * std::f32::consts::PI * std::f32::consts::PI)).sqrt() * PIXELS_PER_DEGREE);
+        let values: Vec<f32> = opponent.iter().map(|value| value[channel]).collect();
+        for (result, value) in filtered.iter_mut().zip(convolve(&values, width, height, &kernel, &kernel)) {
+            result[channel] = value;
+        }
+    }
+    filtered.into_iter().map(|value| {
+        let rgb = xyz_to_linear_rgb(ycxcz_to_xyz(value));
+        vec3(rgb.x.clamp(0.0, 1.0), rgb.y.clamp(0.0, 1.0), rgb.z.clamp(0.0, 1.0))
+    }).collect()
+}
+
+// Strength of edges and points in the luminance of every pixel
+fn features(pixels: &[[f32; 4]], width: usize, height: usize) -> Vec<(f32, f32)>
+{
+    let luminance: Vec<f32> = pixels.iter().map(|pixel| (xyz_to_ycxcz(linear_rgb_to_xyz(clamp_display(pixel))).x + 16.0) / 116.0).collect();
+    let sigma = 0.5 * 0.082 * PIXELS_PER_DEGREE;
+    let radius = (3.0 * sigma).ceil() as i32;
+    let smooth = gaussian(sigma);
+    let first: Vec<f32> = (-radius..=radius).map(|x| -x as f32 * (-(x * x) as f32 / (2.0 * sigma * sigma)).exp()).collect();
+    let second: Vec<f32> = (-radius..=radius).map(|x| ((x * x) as f32 / (sigma * sigma) - 1.0) * (-(x * x) as f32 / (2.0 * sigma * sigma)).exp()).collect();
+    let first = normalize_parts(&first);
+    let second = normalize_parts(&second);
+
+    let edges_x = convolve(&luminance, width, height, &first, &smooth);
+    let edges_y = convolve(&luminance, width, height, &smooth, &first);
+    let points_x = convolve(&luminance, width, height, &second, &smooth);
+    let points_y = convolve(&luminance, width, height, &smooth, &second);
+    (0..luminance.len()).map(|i| {
+        (vec2(edges_x[i], edges_y[i]).magnitude(), vec2(points_x[i], points_y[i]).magnitude())
+    }).collect()
+}
+
+fn gaussian(sigma: f32) -> Vec<f32>
+{
+    let radius = (3.0 * sigma).ceil() as i32;
+    let weights: Vec<f32> = (-radius..=radius).map(|x| (-(x * x) as f32 / (2.0 * sigma * sigma)).exp()).collect();
+    let sum: f32 = weights.iter().sum();
+    weights.iter().map(|weight| weight / sum).collect()
+}
+
+// Scales the positive weights to sum to one and the negative weights to sum to minus one
+fn normalize_parts(weights: &[f32]) -> Vec<f32>
+{
+    let positive: f32 = weights.iter().filter(|&&weight| weight > 0.0).sum();
+    let negative: f32 = -weights.iter().filter(|&&weight| weight < 0.0).sum::<f32>();
+    weights.iter().map(|&weight| if weight > 0.0 { weight / positive } else { weight / negative }).collect()
+}
+
+// Separable convolution with odd sized kernels, the image is extended by its border pixels
+fn convolve(values: &[f32], width: usize, height: usize, horizontal: &[f32], vertical: &[f32]) -> Vec<f32>
+{
+    let clamp = |value: isize, size: usize| value.clamp(0, size as isize - 1) as usize;
+    let (h_radius, v_radius) = ((horizontal.len() / 2) as isize, (vertical.len() / 2) as isize);
+    let mut rows = vec![0.0; values.len()];
+    for y in 0..height {
+        for x in 0..width {
+            rows[x + y * width] = horizontal.iter().enumerate()
+                .map(|(i, weight)| weight * values[clamp(x as isize + i as isize - h_radius, width) + y * width]).sum();
+        }
+    }
+    let mut result = vec![0.0; values.len()];
+    for y in 0..height {
+        for x in 0..width {
+            result[x + y * width] = vertical.iter().enumerate()
+                .map(|(i, weight)| weight * rows[x + clamp(y as isize + i as isize - v_radius, height) * width]).sum();
+        }
+    }
+    result
+}
+
+fn clamp_display(pixel: &[f32; 4]) -> Vector3<f32>
+{
+    let clamp = |value: f32| if value.is_nan() { 0.0 } else { value.clamp(0.0, 1.0) };
+    vec3(clamp(pixel[0]), clamp(pixel[1]), clamp(pixel[2]))
+}
+
+fn linear_rgb_to_xyz(rgb: Vector3<f32>) -> Vector3<f32>
+{
+    vec3(0.412_456_4 * rgb.x + 0.357_576_1 * rgb.y + 0.180_437_5 * rgb.z,
+         0.212_672_9 * rgb.x + 0.715_152_2 * rgb.y + 0.072_175 * rgb.z,
+         0.019_333_9 * rgb.x + 0.119_192 * rgb.y + 0.950_304_1 * rgb.z)
+}
+
+fn xyz_to_linear_rgb(xyz: Vector3<f32>) -> Vector3<f32>
+{
+    vec3(3.240_454_2 * xyz.x - 1.537_138_5 * xyz.y - 0.498_531_4 * xyz.z,
+         -0.969_266 * xyz.x + 1.876_010_8 * xyz.y + 0.041_556 * xyz.z,
+         0.055_643_4 * xyz.x - 0.204_025_9 * xyz.y + 1.057_225_2 * xyz.z)
+}
+
+// Opponent colour space in which FLIP filters, linear in XYZ
+fn xyz_to_ycxcz(xyz: Vector3<f32>) -> Vector3<f32>
+{
+    let (x, y, z) = (xyz.x / WHITE[0], xyz.y / WHITE[1], xyz.z / WHITE[2]);
+    vec3(116.0 * y - 16.0, 500.0 * (x - y), 200.0 * (y - z))
+}
+
+fn ycxcz_to_xyz(ycxcz: Vector3<f32>) -> Vector3<f32>
+{
+    let y = (ycxcz.x + 16.0) / 116.0;
+    vec3((ycxcz.y / 500.0 + y) * WHITE[0], y * WHITE[1], (y - ycxcz.z / 200.0) * WHITE[2])
+}
+
+fn linear_rgb_to_lab(rgb: Vector3<f32>) -> Vector3<f32>
+{
+    let xyz = linear_rgb_to_xyz(rgb);
+    let f = |t: f32| if t > 216.0 / 24389.0 { t.cbrt() } else { (24389.0 / 27.0 * t + 16.0) / 116.0 };
+    let (x, y, z) = (f(xyz.x / WHITE[0]), f(xyz.y / WHITE[1]), f(xyz.z / WHITE[2]));
+    vec3(116.0 * y - 16.0, 500.0 * (x - y), 200.0 * (y - z))
+}
+
+// Distance in the Hunt adjusted L*a*b* space, where colours get more saturated with their lightness
+fn hunt_hyab(a: Vector3<f32>, b: Vector3<f32>) -> f32
+{
+    let hunt = |lab: Vector3<f32>| vec3(lab.x, 0.01 * lab.x * lab.y, 0.01 * lab.x * lab.z);
+    let (a, b) = (hunt(a), hunt(b));
+    (a.x - b.x).abs() + vec2(a.y - b.y, a.z - b.z).magnitude()
+}
+
+fn srgb_to_linear(value: f32) -> f32
+{
+    if value <= 0.040_45 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) }
+}
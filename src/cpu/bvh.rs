@@ -0,0 +1,185 @@
+
+use cgmath::*;
+use crate::types::*;
+
+const MAX_PRIMITIVES_IN_LEAF: usize = 4;
+
+// Bounding volume hierarchy over the scene triangles. Stands in for the MPS
+// TriangleAccelerationStructure and RayIntersector on the CPU and reports intersections in the same
+// format (distancePrimitiveIndexCoordinates, negative distance on a miss).
+pub struct Bvh
+{
+    nodes: Vec<Node>,
+    primitive_indices: Vec<u32>,
+    triangles: Vec<[Vector3<f32>; 3]>
+}
+
+struct Node
+{
+    min: Vector3<f32>,
+    max: Vector3<f32>,
+    // For leaves the primitive range, for inner nodes 'start' is the index of the second child
+    // (the first child always follows its parent).
+    start: usize,
+    count: usize
+}
+
+impl Bvh {
+
+    pub fn new(vertex_data: &[f32], index_data: &[u32]) -> Bvh
+    {
+        let vertex = |i: u32| {
+            let i = 3 * i as usize;
+            vec3(vertex_data[i], vertex_data[i + 1], vertex_data[i + 2])
+        };
+        let triangles: Vec<[Vector3<f32>; 3]> = index_data.chunks(3)
+            .map(|indices| [vertex(indices[0]), vertex(indices[1]), vertex(indices[2])])
+            .collect();
+
+        let mut bvh = Bvh { nodes: Vec::new(), primitive_indices: (0..triangles.len() as u32).collect(), triangles };
+        if !bvh.triangles.is_empty() {
+            let centroids: Vec<Vector3<f32>> = bvh.triangles.iter().map(|t| (t[0] + t[1] + t[2]) / 3.0).collect();
+            bvh.build(&centroids, 0, centroids.len());
+        }
+        bvh
+    }
+
+    fn build(&mut self, centroids: &[Vector3<f32>], start: usize, end: usize) -> usize
+    {
+        let mut min = vec3(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+        let mut max = -min;
+        let mut centroid_min = min;
+        let mut centroid_max = max;
+        for &primitive_index in self.primitive_indices[start..end].iter() {
+            for p in self.triangles[primitive_index as usize].iter() {
+                min = component_min(min, *p);
+                max = component_max(max, *p);
+            }
+            centroid_min = component_min(centroid_min, centroids[primitive_index as usize]);
+            centroid_max = component_max(centroid_max, centroids[primitive_index as usize]);
+        }
+
+        let node_index = self.nodes.len();
+        self.nodes.push(Node { min, max, start, count: end - start });
+        if end - start <= MAX_PRIMITIVES_IN_LEAF {
+            return node_index;
+        }
+
+        // Split at the median centroid along the largest axis
+        let extent = centroid_max - centroid_min;
+        let axis = if extent.x > extent.y && extent.x > extent.z { 0 } else if extent.y > extent.z { 1 } else { 2 };
+        let middle = (start + end) / 2;
+        self.primitive_indices[start..end].sort_by(|a, b| {
+            centroids[*a as usize][axis].partial_cmp(&centroids[*b as usize][axis]).unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        self.build(centroids, start, middle);
+        let second_child = self.build(centroids, middle, end);
+        self.nodes[node_index].start = second_child;
+        self.nodes[node_index].count = 0;
+        node_index
+    }
+
+    pub fn intersect_nearest(&self, ray: &Ray) -> Intersection
+    {
+        self.intersect(ray.origin.into(), ray.direction.into(), ray.min_distance, ray.max_distance, false)
+    }
+
+    pub fn intersect_any(&self, ray: &ShadowRay) -> Intersection
+    {
+        self.intersect(ray.origin.into(), ray.direction.into(), ray.min_distance, ray.max_distance, true)
+    }
+
+    // Like the MPS intersector, rays with a negative max distance never intersect anything
+    fn intersect(&self, origin: Vector3<f32>, direction: Vector3<f32>, min_distance: f32, max_distance: f32, any: bool) -> Intersection
+    {
+        let mut result = Intersection::default();
+        if self.nodes.is_empty() || max_distance < 0.0 {
+            return result;
+        }
+
+        let inverse_direction = vec3(1.0 / direction.x, 1.0 / direction.y, 1.0 / direction.z);
+        let mut max_distance = max_distance;
+
+        let mut stack = vec![0];
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            if !intersect_box(node, origin, inverse_direction, min_distance, max_distance) {
+                continue;
+            }
+
+            if node.count == 0 {
+                stack.push(node.start);
+                stack.push(node_index + 1);
+                continue;
+            }
+
+            for &primitive_index in self.primitive_indices[node.start..node.start + node.count].iter() {
+                let triangle = &self.triangles[primitive_index as usize];
+                if let Some((distance, u, v)) = intersect_triangle(triangle, origin, direction) {
+                    if distance >= min_distance && distance <= max_distance {
+                        max_distance = distance;
+                        // MPS reports the weights of the first and second vertex
+                        result = Intersection { distance, primitive_index, coordinates: [1.0 - u - v, u] };
+                        if any {
+                            return result;
+                        }
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+fn component_min(a: Vector3<f32>, b: Vector3<f32>) -> Vector3<f32>
+{
+    vec3(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z))
+}
+
+fn component_max(a: Vector3<f32>, b: Vector3<f32>) -> Vector3<f32>
+{
+    vec3(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z))
+}
+
+fn intersect_box(node: &Node, origin: Vector3<f32>, inverse_direction: Vector3<f32>, min_distance: f32, max_distance: f32) -> bool
+{
+    let mut t_min = min_distance;
+    let mut t_max = max_distance;
+    for axis in 0..3 {
+        let t0 = (node.min[axis] - origin[axis]) * inverse_direction[axis];
+        let t1 = (node.max[axis] - origin[axis]) * inverse_direction[axis];
+        // f32::min/max ignore the NaN produced by a zero direction component on a slab boundary
+        t_min = t_min.max(t0.min(t1));
+        t_max = t_max.min(t0.max(t1));
+    }
+    t_min <= t_max
+}
+
+// Möller–Trumbore without back-face culling, returns the distance and the barycentric weights of
+// the second and third vertex.
+fn intersect_triangle(triangle: &[Vector3<f32>; 3], origin: Vector3<f32>, direction: Vector3<f32>) -> Option<(f32, f32, f32)>
+{
+    let edge1 = triangle[1] - triangle[0];
+    let edge2 = triangle[2] - triangle[0];
+    let p = direction.cross(edge2);
+    let determinant = edge1.dot(p);
+    if determinant.abs() < 1e-12 {
+        return None;
+    }
+    let inverse_determinant = 1.0 / determinant;
+
+    let s = origin - triangle[0];
+    let u = s.dot(p) * inverse_determinant;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let q = s.cross(edge1);
+    let v = direction.dot(q) * inverse_determinant;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    Some((edge2.dot(q) * inverse_determinant, u, v))
+}
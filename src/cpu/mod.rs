@@ -0,0 +1,370 @@
+
+// CPU reference implementation of the ray tracing pipeline in tracing.metal. Every pass mirrors the
+// kernel of the same name so that the output image can be compared with the GPU output texture.
+
+use cgmath::*;
+
+use crate::types::*;
+use crate::adaptive;
+use crate::bsdf;
+use crate::emitters::{self, EmitterDistribution, sample_alias_table};
+use crate::lights::{self, LightSample};
+use crate::random;
+use crate::sampler;
+use crate::scene::Scene;
+use crate::camera::Camera;
+use crate::settings::RenderSettings;
+
+mod bvh;
+pub use self::bvh::Bvh;
+
+pub struct CpuRayTracer {
+    bvh: Bvh,
+
+    rays: Vec<Ray>,
+    shadow_rays: Vec<ShadowRay>,
+    intersections: Vec<Intersection>,
+    scene: Scene,
+    emitters: EmitterDistribution,
+    camera: Camera,
+    settings: RenderSettings,
+
+    output_image: Vec<[f32; 4]>,
+    statistics: Vec<PixelStatistics>,
+    output_image_size: (usize, usize)
+}
+
+impl CpuRayTracer {
+
+    pub fn new(scene: Scene, width: usize, height: usize, settings: &RenderSettings) -> CpuRayTracer
+    {
+        let bvh = Bvh::new(&scene.vertices, &scene.indices);
+        let emitters = EmitterDistribution::new(&scene);
+
+        let mut val = CpuRayTracer {bvh, rays: Vec::new(), shadow_rays: Vec::new(), intersections: Vec::new(), scene, emitters,
+            camera: Camera::default(), settings: *settings, output_image: Vec::new(), statistics: Vec::new(),
+            output_image_size: (0, 0)};
+        val.resize(width, height);
+        val
+    }
+
+    // The camera aspect ratio follows the image size
+    pub fn set_camera(&mut self, camera: &Camera)
+    {
+        self.camera = *camera;
+        self.camera.aspect = self.output_image_size.0 as f32 / self.output_image_size.1 as f32;
+    }
+
+    pub fn camera(&self) -> &Camera
+    {
+        &self.camera
+    }
+
+    pub fn resize(&mut self, width: usize, height: usize)
+    {
+        self.output_image_size = (width, height);
+        self.camera.aspect = width as f32 / height as f32;
+        let ray_count = width * height;
+
+        self.output_image = vec![[0.0; 4]; ray_count];
+        self.statistics = vec![PixelStatistics::default(); ray_count];
+        self.rays = vec![Ray::default(); ray_count];
+        self.shadow_rays = vec![ShadowRay::default(); ray_count];
+        self.intersections = vec![Intersection::default(); ray_count];
+    }
+
+    // Same sequence of passes as RayTracer::encode_into
+    pub fn render(&mut self, ray_number: usize)
+    {
+        let app_data = ApplicationData {ray_number: ray_number as u32, seed: random::hash_seed(self.settings.seed),
+            sampler: self.settings.sampler, emitter_triangles_count: self.scene.emitter_triangles.len() as u32,
+            max_depth: self.settings.max_depth, russian_roulette_depth: self.settings.russian_roulette_depth, light_sampling: self.settings.light_sampling,
+            emitter_selection: self.settings.emitter_selection,
+            environment_width: self.scene.environment.as_ref().map_or(0, |environment| environment.width as u32),
+            environment_height: self.scene.environment.as_ref().map_or(0, |environment| environment.height as u32),
+            environment_probability: self.emitters.environment_probability, lights_count: self.scene.lights.len() as u32,
+            light_probability: self.emitters.light_probability, adaptive_threshold: self.settings.adaptive_threshold,
+            image_width: self.output_image_size.0 as u32, image_height: self.output_image_size.1 as u32};
+
+        let (width, height) = self.output_image_size;
+        let camera = self.camera.data();
+        for y in 0..height {
+            for x in 0..width {
+                // Converged pixels only start a path again when the render restarts
+                let statistics = &self.statistics[x + y * width];
+                if app_data.ray_number > 0 && statistics.converged != 0 {
+                    self.rays[x + y * width] = Ray::default();
+                    continue;
+                }
+                let sample_index = if app_data.ray_number > 0 { statistics.sample_count } else { 0 };
+                let jitter = random_sample(&app_data, (x, y), sample_index, 0);
+                let lens_sample = random_sample(&app_data, (x, y), sample_index, 1);
+                let ray = camera.generate_ray((x, y), (width, height), jitter.truncate(), lens_sample.truncate());
+                self.rays[x + y * width] = Ray {sample_index, ..ray};
+            }
+        }
+
+        for bounce in 0..app_data.max_depth {
+            for (ray, intersection) in self.rays.iter().zip(self.intersections.iter_mut()) {
+                *intersection = self.bvh.intersect_nearest(ray);
+            }
+
+            for y in 0..height {
+                for x in 0..width {
+                    let ray_index = x + y * width;
+                    handle_intersection(&mut self.rays[ray_index], &mut self.shadow_rays[ray_index], &self.intersections[ray_index], &self.scene,
+                                        &self.emitters, &app_data, (x, y), bounce);
+                }
+            }
+
+            for (shadow_ray, intersection) in self.shadow_rays.iter().zip(self.intersections.iter_mut()) {
+                *intersection = self.bvh.intersect_any(shadow_ray);
+            }
+
+            for ((ray, shadow_ray), intersection) in self.rays.iter_mut().zip(self.shadow_rays.iter()).zip(self.intersections.iter()) {
+                handle_shadow(ray, shadow_ray, intersection);
+            }
+        }
+
+        for ((pixel, statistics), ray) in self.output_image.iter_mut().zip(self.statistics.iter_mut()).zip(self.rays.iter()) {
+            accumulate(pixel, statistics, ray, &app_data);
+        }
+    }
+
+    // RGBA32F pixels in the same layout as RayTracer::output_texture, that is row 0 is the bottom row
+    pub fn output_image(&self) -> &[[f32; 4]]
+    {
+        &self.output_image
+    }
+
+    pub fn output_image_size(&self) -> (usize, usize)
+    {
+        self.output_image_size
+    }
+
+    // Samples taken by every pixel in the layout of the output image
+    pub fn pixel_statistics(&self) -> &[PixelStatistics]
+    {
+        &self.statistics
+    }
+}
+
+fn random_sample(app_data: &ApplicationData, coordinates: (usize, usize), sample_index: u32, layer: u32) -> Vector3<f32>
+{
+    sampler::sample3(app_data.sampler, app_data.seed, (coordinates.0 as u32, coordinates.1 as u32), sample_index, layer)
+}
+
+fn sample_light(scene: &Scene, emitters: &EmitterDistribution, app_data: &ApplicationData, origin: Vector3<f32>, normal: Vector3<f32>,
+                light_sample: Vector3<f32>) -> Option<LightSample>
+{
+    let environment_probability = app_data.environment_probability;
+    if light_sample.x < environment_probability {
+        let environment = scene.environment.as_ref()?;
+        let (direction, pdf) = environment.sample(vec2(light_sample.y, light_sample.z))?;
+        return Some(LightSample {direction, distance: f32::INFINITY, radiance: environment.radiance(direction), pdf: environment_probability * pdf,
+            delta: false});
+    }
+    let light_probability = app_data.light_probability;
+    if light_sample.x < environment_probability + light_probability {
+        let xi = (light_sample.x - environment_probability) / light_probability;
+        let light_index = sample_alias_table(&emitters.light_table, xi);
+        let light = lights::sample(&scene.lights[light_index], origin, vec2(light_sample.y, light_sample.z))?;
+        return Some(LightSample {pdf: light_probability * emitters.light_table[light_index].pdf * light.pdf, ..light});
+    }
+    if app_data.emitter_triangles_count == 0 {
+        return None;
+    }
+
+    let triangle_probability = 1.0 - environment_probability - light_probability;
+    let xi = (light_sample.x - environment_probability - light_probability) / triangle_probability;
+    let (emitter_index, light_pdf) = emitters.sample(app_data.emitter_selection, origin, normal, xi)?;
+    let light = emitters::sample_triangle(scene, &scene.emitter_triangles[emitter_index], origin, vec2(light_sample.y, light_sample.z))?;
+    Some(LightSample {pdf: triangle_probability * light_pdf * light.pdf, ..light})
+}
+
+// Weight of a sample with density 'pdf' combined with a strategy of density 'other_pdf'
+fn mis_weight(pdf: f32, other_pdf: f32, light_sampling: LightSampling) -> f32
+{
+    match light_sampling {
+        LightSampling::MisPower => pdf * pdf / (pdf * pdf + other_pdf * other_pdf),
+        _ => pdf / (pdf + other_pdf)
+    }
+}
+
+// Weight of emission hit by a BSDF sampled ray against next event estimation sampling it with density 'light_pdf'
+fn emission_weight(ray: &Ray, light_pdf: f32, light_sampling: LightSampling) -> f32
+{
+    if ray.bsdf_pdf == 0.0 {
+        return 1.0;
+    }
+    match light_sampling {
+        LightSampling::Emitter => 0.0,
+        LightSampling::Bsdf => 1.0,
+        _ => mis_weight(ray.bsdf_pdf, light_pdf, light_sampling)
+    }
+}
+
+// handleIntersections
+#[allow(clippy::too_many_arguments)]
+fn handle_intersection(ray: &mut Ray, shadow_ray: &mut ShadowRay, intersection: &Intersection, scene: &Scene, emitters: &EmitterDistribution,
+                       app_data: &ApplicationData, coordinates: (usize, usize), bounce: u32)
+{
+    shadow_ray.max_distance = -1.0;
+    if ray.max_distance < 0.0 {
+        return;
+    }
+
+    // Sphere lights are not part of the acceleration structure, rays are tested against all of them
+    let ray_origin = Vector3::from(ray.origin);
+    let direction = Vector3::from(ray.direction);
+    let triangle_distance = if intersection.distance < 0.0 { f32::INFINITY } else { intersection.distance };
+    let sphere_hit = scene.lights.iter().enumerate()
+        .filter_map(|(index, light)| lights::intersect_sphere(light, ray_origin, direction).map(|distance| (index, distance)))
+        .filter(|&(_, distance)| distance < triangle_distance)
+        .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+    if let Some((light_index, _)) = sphere_hit {
+        let light = &scene.lights[light_index];
+        let light_pdf = app_data.light_probability * emitters.light_table[light_index].pdf * lights::pdf(light, ray_origin);
+        let radiance = Vector3::from(ray.throughput).mul_element_wise(Vector3::from(light.color)) * emission_weight(ray, light_pdf, app_data.light_sampling);
+        ray.color = (Vector3::from(ray.color) + radiance).into();
+        ray.max_distance = -1.0;
+        return;
+    }
+
+    // Light from the environment and the directional lights, weighted like emitters hit by BSDF sampling
+    if intersection.distance < 0.0 {
+        let mut radiance = Vector3::zero();
+        if let Some(environment) = &scene.environment {
+            let light_pdf = app_data.environment_probability * environment.pdf(direction);
+            radiance += environment.radiance(direction) * emission_weight(ray, light_pdf, app_data.light_sampling);
+        }
+        for (light_index, light) in scene.lights.iter().enumerate() {
+            if let Some(light_radiance) = lights::directional_radiance(light, direction) {
+                let light_pdf = app_data.light_probability * emitters.light_table[light_index].pdf * lights::pdf(light, ray_origin);
+                radiance += light_radiance * emission_weight(ray, light_pdf, app_data.light_sampling);
+            }
+        }
+        ray.color = (Vector3::from(ray.color) + Vector3::from(ray.throughput).mul_element_wise(radiance)).into();
+        ray.max_distance = -1.0;
+        return;
+    }
+
+    let triangle = &scene.triangles[intersection.primitive_index as usize];
+    let material = &scene.materials[triangle.material_index as usize];
+
+    // Find intersection point
+    let [a, b, c] = scene.triangle_vertices(intersection.primitive_index);
+    let weights = intersection.coordinates;
+    let intersection_point = weights[0] * a + weights[1] * b + (1.0 - weights[0] - weights[1]) * c;
+
+    // Find normal, emitters only emit on the side the geometric normal points to
+    let geometric_normal = (b - a).cross(c - a).normalize();
+    let wo = -Vector3::from(ray.direction);
+    let throughput = Vector3::from(ray.throughput);
+    let front_facing = geometric_normal.dot(wo) > 0.0;
+    let normal = if front_facing { geometric_normal } else { -geometric_normal };
+
+    // Emission hit by BSDF sampling, weighted against the chance of next event estimation sampling the same point
+    if front_facing && material.emissive != [0.0; 3] {
+        let weight = if ray.bsdf_pdf == 0.0 {
+            1.0
+        } else {
+            match app_data.light_sampling {
+                LightSampling::Emitter => 0.0,
+                LightSampling::Bsdf => 1.0,
+                _ => {
+                    // Emitter points are sampled uniformly by area within the picked triangle
+                    let emitter_area = 0.5 * (b - a).cross(c - a).magnitude();
+                    let light_pdf = emitters.pdf(app_data.emitter_selection, Vector3::from(ray.origin), Vector3::from(ray.normal),
+                                                 intersection.primitive_index);
+                    let triangle_probability = 1.0 - app_data.environment_probability - app_data.light_probability;
+                    let light_sample_pdf = triangle_probability * light_pdf * emitters::point_sample_pdf(emitter_area, intersection.distance, geometric_normal.dot(wo));
+                    mis_weight(ray.bsdf_pdf, light_sample_pdf, app_data.light_sampling)
+                }
+            }
+        };
+        let emission = throughput.mul_element_wise(Vector3::from(material.emissive)) * weight;
+        ray.color = (Vector3::from(ray.color) + emission).into();
+    }
+
+    // Sample light, specular surfaces only reflect the single direction next event estimation cannot hit. The path
+    // is not continued after the last bounce, so there emitter sampling is the only strategy for every mode.
+    let last_bounce = bounce + 1 >= app_data.max_depth;
+    let light_sampling = if last_bounce { LightSampling::Emitter } else { app_data.light_sampling };
+    let light_sample = random_sample(app_data, coordinates, ray.sample_index, CAMERA_RANDOM_LAYERS + BOUNCE_RANDOM_LAYERS * bounce);
+    let origin = intersection_point + SURFACE_OFFSET * normal;
+    let light = if !bsdf::is_specular(material) && light_sampling != LightSampling::Bsdf {
+        sample_light(scene, emitters, app_data, origin, normal, light_sample)
+    } else {
+        None
+    };
+    if let Some(light) = light {
+        let cos_surface = light.direction.dot(normal);
+        if cos_surface > 0.0 {
+            // Find color
+            let material_bsdf = bsdf::evaluate(material, geometric_normal, wo, light.direction);
+            let weight = if light.delta || light_sampling == LightSampling::Emitter {
+                1.0
+            } else {
+                mis_weight(light.pdf, bsdf::pdf(material, geometric_normal, wo, light.direction), light_sampling)
+            };
+
+            // Set shadow ray
+            let color = throughput.mul_element_wise(light.radiance).mul_element_wise(material_bsdf);
+            shadow_ray.color = (color * (weight * cos_surface / light.pdf)).into();
+            shadow_ray.origin = origin.into();
+            shadow_ray.direction = light.direction.into();
+            shadow_ray.min_distance = EPSILON;
+            shadow_ray.max_distance = light.distance;
+        }
+    }
+
+    // Continue the path
+    let bsdf_sample = random_sample(app_data, coordinates, ray.sample_index, CAMERA_RANDOM_LAYERS + BOUNCE_RANDOM_LAYERS * bounce + 1);
+    let termination_sample = random_sample(app_data, coordinates, ray.sample_index, CAMERA_RANDOM_LAYERS + BOUNCE_RANDOM_LAYERS * bounce + 2);
+    let sample = match bsdf::sample(material, geometric_normal, wo, bsdf_sample) {
+        Some(sample) => sample,
+        None => {
+            ray.max_distance = -1.0;
+            return;
+        }
+    };
+    let throughput = throughput.mul_element_wise(sample.weight);
+
+    // Russian roulette
+    let mut continuation_probability = 1.0;
+    if bounce >= app_data.russian_roulette_depth {
+        continuation_probability = throughput.x.max(throughput.y.max(throughput.z)).min(0.95);
+    }
+    if last_bounce || termination_sample.x >= continuation_probability {
+        ray.max_distance = -1.0;
+        return;
+    }
+
+    ray.throughput = (throughput / continuation_probability).into();
+    ray.bsdf_pdf = if sample.specular { 0.0 } else { sample.pdf };
+    ray.normal = normal.into();
+    // Refracted rays start below the surface
+    let offset = if sample.direction.dot(normal) < 0.0 { -SURFACE_OFFSET } else { SURFACE_OFFSET };
+    ray.origin = (intersection_point + offset * normal).into();
+    ray.direction = sample.direction.into();
+    ray.min_distance = EPSILON;
+    ray.max_distance = f32::INFINITY;
+}
+
+// handleShadows
+fn handle_shadow(ray: &mut Ray, shadow_ray: &ShadowRay, intersection: &Intersection)
+{
+    if shadow_ray.max_distance >= 0.0 && intersection.distance < 0.0 {
+        ray.color = (Vector3::from(ray.color) + Vector3::from(shadow_ray.color)).into();
+    }
+}
+
+// accumulateImage
+fn accumulate(pixel: &mut [f32; 4], statistics: &mut PixelStatistics, ray: &Ray, app_data: &ApplicationData)
+{
+    if app_data.ray_number > 0 && statistics.converged != 0 {
+        return;
+    }
+    adaptive::accumulate(pixel, statistics, ray.color, ray.sample_index, app_data.adaptive_threshold);
+}
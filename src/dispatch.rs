@@ -0,0 +1,21 @@
+// Grid of the compute kernels in tracing.metal. Every pixel is a thread and the threads are dispatched in whole
+// thread groups, so for sizes that are not a multiple of the thread group size the last groups reach past the right
+// and bottom edges of the image. The kernels get the image size from ApplicationData instead of the grid and those
+// threads return right away.
+
+pub const THREADS_PER_THREAD_GROUP: (usize, usize) = (8, 8);
+
+// Thread groups covering every pixel of an image of 'size'
+pub fn thread_groups_count(size: (usize, usize)) -> (usize, usize)
+{
+    (size.0.div_ceil(THREADS_PER_THREAD_GROUP.0), size.1.div_ceil(THREADS_PER_THREAD_GROUP.1))
+}
+
+// Index of the ray and pixel of the thread at 'coordinates' like the kernels compute it, none past the edges
+pub fn ray_index(coordinates: (usize, usize), size: (usize, usize)) -> Option<usize>
+{
+    if coordinates.0 >= size.0 || coordinates.1 >= size.1 {
+        return None;
+    }
+    Some(coordinates.0 + coordinates.1 * size.0)
+}
@@ -0,0 +1,218 @@
+// Selection of the emitter triangle for next event estimation. Emitters are chosen either proportionally to their
+// power, the area times the luminance of the emission, in constant time with Vose's alias method, or by their
+// estimated contribution at the shading point from the light tree. Analytic lights have an alias table of their
+// own. The tables are uploaded as they are and read by sampleAliasTable in tracing.metal.
+
+use cgmath::*;
+
+use crate::types::*;
+use crate::scene::Scene;
+use crate::light_tree::LightTree;
+use crate::lights::{self, LightSample};
+
+pub const NO_EMITTER: u32 = u32::MAX;
+
+pub struct EmitterDistribution
+{
+    // One entry per emitter triangle
+    pub alias_table: Vec<AliasEntry>,
+    pub light_tree: LightTree,
+    // Index into the emitter triangles for every triangle of the scene, NO_EMITTER for triangles that do not emit
+    pub triangle_emitters: Vec<u32>,
+    // One entry per analytic light
+    pub light_table: Vec<AliasEntry>,
+    // Probabilities of sampling the environment or an analytic light instead of an emitter triangle, split evenly
+    // between the kinds of light the scene has
+    pub environment_probability: f32,
+    pub light_probability: f32
+}
+
+// The emitter arrays the Metal kernels read, with a single unused element in those that are empty for the scene.
+// Metal cannot create buffers of length zero, and the kernels never read the element as the counts in
+// ApplicationData are zero, for example in scenes lit only by the environment.
+pub struct EmitterBufferData
+{
+    pub emitter_triangles: Vec<EmitterTriangle>,
+    pub alias_table: Vec<AliasEntry>,
+    pub triangle_emitters: Vec<u32>,
+    pub light_tree_nodes: Vec<LightTreeNode>,
+    pub emitter_trails: Vec<u32>,
+    pub lights: Vec<Light>,
+    pub light_table: Vec<AliasEntry>
+}
+
+impl EmitterDistribution {
+
+    pub fn new(scene: &Scene) -> EmitterDistribution
+    {
+        let weights: Vec<f32> = scene.emitter_triangles.iter().map(|emitter| emitter.area * luminance(emitter.emissive)).collect();
+        let alias_table = build_alias_table(&weights);
+
+        let mut triangle_emitters = vec![NO_EMITTER; scene.triangles.len()];
+        for (index, emitter) in scene.emitter_triangles.iter().enumerate() {
+            triangle_emitters[emitter.primitive_index as usize] = index as u32;
+        }
+
+        let scene_radius = scene_radius(scene);
+        let light_weights: Vec<f32> = scene.lights.iter().map(|light| lights::power(light, scene_radius)).collect();
+        let light_table = build_alias_table(&light_weights);
+
+        let has_environment = scene.environment.as_ref().is_some_and(|environment| !environment.is_black());
+        let has_lights = scene.lights.iter().any(|light| light.color != [0.0; 3]);
+        let kinds = [has_environment, has_lights, !scene.emitter_triangles.is_empty()].iter().filter(|&&kind| kind).count();
+        let share = if kinds > 0 { 1.0 / kinds as f32 } else { 0.0 };
+
+        EmitterDistribution { alias_table, light_tree: LightTree::new(scene), triangle_emitters, light_table,
+            environment_probability: if has_environment { share } else { 0.0 }, light_probability: if has_lights { share } else { 0.0 } }
+    }
+
+    // Probability of sampling an emitter triangle rather than the environment or an analytic light
+    pub fn triangle_probability(&self) -> f32
+    {
+        1.0 - self.environment_probability - self.light_probability
+    }
+
+    pub fn buffer_data(&self, scene: &Scene) -> EmitterBufferData
+    {
+        let unused_alias_entry = AliasEntry {probability: 1.0, alias: 0, pdf: 0.0};
+        let unused_node = LightTreeNode {bounds_min: [0.0; 3], power: 0.0, bounds_max: [0.0; 3], cos_theta_o: 1.0, axis: [0.0, 0.0, 1.0],
+            cos_theta_e: 1.0, second_child: 0, emitter_index: NO_EMITTER};
+        EmitterBufferData {
+            emitter_triangles: padded(&scene.emitter_triangles, EmitterTriangle {primitive_index: 0, emissive: [0.0; 3], area: 0.0}),
+            alias_table: padded(&self.alias_table, unused_alias_entry),
+            triangle_emitters: padded(&self.triangle_emitters, NO_EMITTER),
+            light_tree_nodes: padded(&self.light_tree.nodes, unused_node),
+            emitter_trails: padded(&self.light_tree.emitter_trails, 0),
+            lights: padded(&scene.lights, Light::default()),
+            light_table: padded(&self.light_table, unused_alias_entry)
+        }
+    }
+
+    // Index of the emitter triangle picked by the uniform random number 'xi' for a shading point and the
+    // probability it was picked with
+    pub fn sample(&self, selection: EmitterSelection, point: Vector3<f32>, normal: Vector3<f32>, xi: f32) -> Option<(usize, f32)>
+    {
+        match selection {
+            EmitterSelection::Power if !self.alias_table.is_empty() => {
+                let index = sample_alias_table(&self.alias_table, xi);
+                Some((index, self.alias_table[index].pdf))
+            },
+            EmitterSelection::Power => None,
+            EmitterSelection::LightTree => self.light_tree.sample(point, normal, xi)
+        }
+    }
+
+    // Probability of picking the emitter that 'primitive_index' belongs to, zero for triangles that do not emit
+    pub fn pdf(&self, selection: EmitterSelection, point: Vector3<f32>, normal: Vector3<f32>, primitive_index: u32) -> f32
+    {
+        match self.triangle_emitters.get(primitive_index as usize) {
+            Some(&index) if index != NO_EMITTER => match selection {
+                EmitterSelection::Power => self.alias_table[index as usize].pdf,
+                EmitterSelection::LightTree => self.light_tree.pdf(point, normal, index as usize)
+            },
+            _ => 0.0
+        }
+    }
+}
+
+// Uniform point on the emitter triangle with two uniform random numbers, None if 'point' sees the back of the
+// triangle. The density is in solid angle at 'point' and leaves out the probability of picking the triangle.
+pub fn sample_triangle(scene: &Scene, emitter: &EmitterTriangle, point: Vector3<f32>, u: Vector2<f32>) -> Option<LightSample>
+{
+    let weights = barycentric(u);
+    let [a, b, c] = scene.triangle_vertices(emitter.primitive_index);
+    let position = weights.x * a + weights.y * b + weights.z * c;
+    let normal = (b - a).cross(c - a).normalize();
+    let distance = (position - point).magnitude();
+    let direction = (position - point) / distance;
+
+    let cos_theta = -direction.dot(normal);
+    if cos_theta <= 0.0 {
+        return None;
+    }
+    Some(LightSample {direction, distance: distance - SURFACE_OFFSET, radiance: Vector3::from(emitter.emissive),
+        pdf: point_sample_pdf(emitter.area, distance, cos_theta), delta: false})
+}
+
+// Converts the density of points sampled uniformly by area on an emitter to solid angle at a point 'distance' away,
+// where 'cos_theta' is the cosine between the emitter normal and the direction to the point. pointSamplePdf in
+// tracing.metal.
+pub fn point_sample_pdf(area: f32, distance: f32, cos_theta: f32) -> f32
+{
+    distance * distance / (area * cos_theta)
+}
+
+// Barycentric coordinates uniformly distributed over a triangle
+fn barycentric(u: Vector2<f32>) -> Vector3<f32>
+{
+    let r1 = u.x.sqrt();
+    vec3(1.0 - r1, r1 * (1.0 - u.y), r1 * u.y)
+}
+
+// Half the diagonal of the bounding box of the triangles
+fn scene_radius(scene: &Scene) -> f32
+{
+    if scene.vertices.is_empty() {
+        return 0.0;
+    }
+    let mut bounds_min = [f32::INFINITY; 3];
+    let mut bounds_max = [f32::NEG_INFINITY; 3];
+    for vertex in scene.vertices.chunks(3) {
+        for axis in 0..3 {
+            bounds_min[axis] = bounds_min[axis].min(vertex[axis]);
+            bounds_max[axis] = bounds_max[axis].max(vertex[axis]);
+        }
+    }
+    0.5 * (Vector3::from(bounds_max) - Vector3::from(bounds_min)).magnitude()
+}
+
+pub fn luminance(color: [f32; 3]) -> f32
+{
+    0.2126 * color[0] + 0.7152 * color[1] + 0.0722 * color[2]
+}
+
+fn padded<T: Clone>(data: &[T], unused: T) -> Vec<T>
+{
+    if data.is_empty() { vec![unused] } else { data.to_vec() }
+}
+
+// Splits the weights into equally likely columns holding at most two outcomes each. Weights that are all zero
+// select uniformly.
+pub fn build_alias_table(weights: &[f32]) -> Vec<AliasEntry>
+{
+    let count = weights.len();
+    let total: f64 = weights.iter().map(|&weight| f64::from(weight.max(0.0))).sum();
+    let normalized: Vec<f64> = weights.iter().map(|&weight| {
+        if total > 0.0 { f64::from(weight.max(0.0)) / total } else { 1.0 / count as f64 }
+    }).collect();
+
+    // Probability mass of every column scaled so that a full column is one
+    let mut scaled: Vec<f64> = normalized.iter().map(|p| p * count as f64).collect();
+    let mut small: Vec<usize> = (0..count).filter(|&i| scaled[i] < 1.0).collect();
+    let mut large: Vec<usize> = (0..count).filter(|&i| scaled[i] >= 1.0).collect();
+
+    let mut table: Vec<AliasEntry> = (0..count).map(|i| AliasEntry { probability: 1.0, alias: i as u32, pdf: normalized[i] as f32 }).collect();
+    while let (Some(&less), Some(&more)) = (small.last(), large.last()) {
+        small.pop();
+        large.pop();
+        table[less].probability = scaled[less] as f32;
+        table[less].alias = more as u32;
+
+        scaled[more] -= 1.0 - scaled[less];
+        if scaled[more] < 1.0 {
+            small.push(more);
+        } else {
+            large.push(more);
+        }
+    }
+    // Whatever is left over fills its column up to rounding errors
+    table
+}
+
+pub fn sample_alias_table(table: &[AliasEntry], xi: f32) -> usize
+{
+    let scaled = xi * table.len() as f32;
+    let column = (scaled as usize).min(table.len() - 1);
+    let entry = &table[column];
+    if scaled - (column as f32) < entry.probability { column } else { entry.alias as usize }
+}
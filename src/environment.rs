@@ -0,0 +1,168 @@
+// Distant light of the rays that leave the scene, given by an equirectangular image or a constant colour. Next
+// event estimation samples the image with a piecewise constant density proportional to the luminance of its
+// pixels. environmentPixel, which looks up the radiance, environmentPdf and sampleEnvironment in tracing.metal mirror
+// this module.
+
+use cgmath::*;
+use std::f32::consts::PI;
+use std::path::Path;
+
+use crate::emitters::luminance;
+use crate::scene::SceneError;
+
+#[derive(Clone, Debug)]
+pub struct EnvironmentMap
+{
+    pub width: usize,
+    pub height: usize,
+    // Rows from the top, which is straight up along +y, the centre of the image lies along -z
+    pub pixels: Vec<[f32; 3]>,
+    // Density of every pixel with respect to the area of the image scaled to the unit square
+    pub pixel_pdfs: Vec<f32>,
+    // Cumulative distribution over the columns of every row, width + 1 values per row
+    pub conditional_cdfs: Vec<f32>,
+    // Cumulative distribution over the rows, height + 1 values
+    pub marginal_cdf: Vec<f32>
+}
+
+impl EnvironmentMap {
+
+    pub fn new(width: usize, height: usize, pixels: Vec<[f32; 3]>) -> EnvironmentMap
+    {
+        assert_eq!(pixels.len(), width * height);
+
+        // Rows near the poles cover less solid angle
+        let mut function = Vec::with_capacity(pixels.len());
+        for row in 0..height {
+            let sin_theta = (PI * (row as f32 + 0.5) / height as f32).sin();
+            function.extend(pixels[row * width..(row + 1) * width].iter().map(|&pixel| (luminance(pixel) * sin_theta).max(0.0)));
+        }
+
+        let mut conditional_cdfs = Vec::with_capacity(height * (width + 1));
+        let mut row_sums = Vec::with_capacity(height);
+        for row in function.chunks(width) {
+            row_sums.push(row.iter().sum::<f32>());
+            conditional_cdfs.extend(cumulative_distribution(row));
+        }
+        let marginal_cdf = cumulative_distribution(&row_sums);
+
+        let integral = row_sums.iter().sum::<f32>() / (width * height) as f32;
+        let pixel_pdfs = function.iter().map(|&f| if integral > 0.0 { f / integral } else { 0.0 }).collect();
+        EnvironmentMap { width, height, pixels, pixel_pdfs, conditional_cdfs, marginal_cdf }
+    }
+
+    // The same radiance from every direction
+    pub fn constant(color: [f32; 3]) -> EnvironmentMap
+    {
+        EnvironmentMap::new(1, 1, vec![color])
+    }
+
+    // Equirectangular .hdr or .exr image
+    pub fn load(path: &Path) -> Result<EnvironmentMap, SceneError>
+    {
+        let image = image::open(path).map_err(SceneError::Environment)?.into_rgb32f();
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let pixels = image.pixels().map(|pixel| pixel.0).collect();
+        Ok(EnvironmentMap::new(width, height, pixels))
+    }
+
+    // Black images cannot be sampled and light nothing
+    pub fn is_black(&self) -> bool
+    {
+        self.pixel_pdfs.iter().all(|&pdf| pdf == 0.0)
+    }
+
+    pub fn radiance(&self, direction: Vector3<f32>) -> Vector3<f32>
+    {
+        Vector3::from(self.pixels[self.pixel_index(direction_to_uv(direction))])
+    }
+
+    // Solid angle density of sample returning the direction
+    pub fn pdf(&self, direction: Vector3<f32>) -> f32
+    {
+        let sin_theta = (1.0 - direction.y * direction.y).max(0.0).sqrt();
+        if sin_theta == 0.0 {
+            return 0.0;
+        }
+        self.pixel_pdfs[self.pixel_index(direction_to_uv(direction))] / (2.0 * PI * PI * sin_theta)
+    }
+
+    // Direction towards the environment and its solid angle density from two uniform random numbers
+    pub fn sample(&self, u: Vector2<f32>) -> Option<(Vector3<f32>, f32)>
+    {
+        if self.is_black() {
+            return None;
+        }
+
+        let row = find_interval(&self.marginal_cdf, u.y);
+        let v = (row as f32 + interval_offset(&self.marginal_cdf, row, u.y)) / self.height as f32;
+        let conditional_cdf = &self.conditional_cdfs[row * (self.width + 1)..(row + 1) * (self.width + 1)];
+        let column = find_interval(conditional_cdf, u.x);
+        let u = (column as f32 + interval_offset(conditional_cdf, column, u.x)) / self.width as f32;
+
+        let direction = uv_to_direction(vec2(u, v));
+        let sin_theta = (1.0 - direction.y * direction.y).max(0.0).sqrt();
+        if sin_theta == 0.0 {
+            return None;
+        }
+        Some((direction, self.pixel_pdfs[column + row * self.width] / (2.0 * PI * PI * sin_theta)))
+    }
+
+    fn pixel_index(&self, uv: Vector2<f32>) -> usize
+    {
+        let column = ((uv.x * self.width as f32) as usize).min(self.width - 1);
+        let row = ((uv.y * self.height as f32) as usize).min(self.height - 1);
+        column + row * self.width
+    }
+}
+
+// Image coordinates in the unit square with v from the top
+pub fn direction_to_uv(direction: Vector3<f32>) -> Vector2<f32>
+{
+    let phi = direction.x.atan2(-direction.z);
+    let theta = direction.y.clamp(-1.0, 1.0).acos();
+    vec2(0.5 + phi / (2.0 * PI), theta / PI)
+}
+
+pub fn uv_to_direction(uv: Vector2<f32>) -> Vector3<f32>
+{
+    let phi = 2.0 * PI * (uv.x - 0.5);
+    let theta = PI * uv.y;
+    vec3(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos())
+}
+
+// Normalized running sums starting at zero, uniform if all values are zero
+fn cumulative_distribution(function: &[f32]) -> Vec<f32>
+{
+    let total: f32 = function.iter().sum();
+    let mut cdf = Vec::with_capacity(function.len() + 1);
+    cdf.push(0.0);
+    let mut sum = 0.0;
+    for (i, f) in function.iter().enumerate() {
+        sum += f;
+        cdf.push(if total > 0.0 { sum / total } else { (i + 1) as f32 / function.len() as f32 });
+    }
+    cdf[function.len()] = 1.0;
+    cdf
+}
+
+// Last interval of the cumulative distribution starting at or below u, by binary search
+fn find_interval(cdf: &[f32], u: f32) -> usize
+{
+    let (mut first, mut last) = (0, cdf.len() - 2);
+    while first < last {
+        let middle = (first + last).div_ceil(2);
+        if cdf[middle] <= u {
+            first = middle;
+        } else {
+            last = middle - 1;
+        }
+    }
+    first
+}
+
+fn interval_offset(cdf: &[f32], index: usize, u: f32) -> f32
+{
+    let width = cdf[index + 1] - cdf[index];
+    if width > 0.0 { (u - cdf[index]) / width } else { 0.0 }
+}
@@ -0,0 +1,87 @@
+// Data the CPU writes for every frame, like the ApplicationData and CameraData uniforms, has a copy per frame that
+// may be in flight. Before the CPU writes the copy of a new frame it waits on a semaphore, which the completion of
+// the command buffer of the frame that last used the copy signals again. Every frame taken from the ring has to be
+// committed, otherwise the ring runs out of frames and the next frame waits forever.
+//
+// Data that every frame reads and writes in turn, like the pixel statistics of the adaptive sampling, has a single
+// copy. The CPU reads it only after waiting until no frame is in flight.
+
+use std::sync::{Arc, Condvar, Mutex};
+
+pub const MAX_FRAMES_IN_FLIGHT: usize = 3;
+
+pub struct Semaphore
+{
+    count: Mutex<usize>,
+    signalled: Condvar
+}
+
+impl Semaphore
+{
+    pub fn new(count: usize) -> Semaphore
+    {
+        Semaphore {count: Mutex::new(count), signalled: Condvar::new()}
+    }
+
+    // Blocks until the count is positive and decrements it
+    pub fn wait(&self)
+    {
+        let mut count = self.count.lock().unwrap();
+        while *count == 0 {
+            count = self.signalled.wait(count).unwrap();
+        }
+        *count -= 1;
+    }
+
+    pub fn signal(&self)
+    {
+        *self.count.lock().unwrap() += 1;
+        self.signalled.notify_one();
+    }
+}
+
+pub struct FrameRing<T>
+{
+    frames: Vec<T>,
+    index: usize,
+    available: Arc<Semaphore>
+}
+
+impl<T> FrameRing<T>
+{
+    pub fn new(frames: Vec<T>) -> FrameRing<T>
+    {
+        let available = Arc::new(Semaphore::new(frames.len()));
+        FrameRing {index: frames.len() - 1, frames, available}
+    }
+
+    // Waits until the GPU is done with the oldest frame and makes it the current one. The returned semaphore has to be
+    // signalled once the GPU is done with the frame, from the completed handler of its command buffer.
+    pub fn next_frame(&mut self) -> Arc<Semaphore>
+    {
+        self.available.wait();
+        self.index = (self.index + 1) % self.frames.len();
+        self.available.clone()
+    }
+
+    // Waits until the GPU is done with every frame taken from the ring
+    pub fn wait_until_idle(&self)
+    {
+        for _ in 0..self.frames.len() {
+            self.available.wait();
+        }
+        for _ in 0..self.frames.len() {
+            self.available.signal();
+        }
+    }
+
+    pub fn current(&self) -> &T
+    {
+        &self.frames[self.index]
+    }
+
+    pub fn current_index(&self) -> usize
+    {
+        self.index
+    }
+}
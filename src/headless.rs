@@ -0,0 +1,119 @@
+
+use std::fmt;
+use std::time::Instant;
+
+use metal_ray_tracing_rs::adaptive;
+use metal_ray_tracing_rs::cli::{Backend, Options};
+use metal_ray_tracing_rs::cpu::CpuRayTracer;
+use metal_ray_tracing_rs::output::{self, OutputError};
+use metal_ray_tracing_rs::scene::Scene;
+use metal_ray_tracing_rs::types::PixelStatistics;
+
+#[derive(Debug)]
+pub enum RenderError
+{
+    // A command buffer of the given sample failed on the GPU
+    #[cfg(target_os = "macos")]
+    Gpu { sample: usize, error: metal::CommandBufferError },
+    Output(OutputError)
+}
+
+impl fmt::Display for RenderError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self {
+            #[cfg(target_os = "macos")]
+            RenderError::Gpu { sample, error } => write!(f, "ray tracing sample {} failed on the GPU: {}", sample, error),
+            RenderError::Output(error) => write!(f, "{}", error)
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+impl From<OutputError> for RenderError
+{
+    fn from(error: OutputError) -> Self
+    {
+        RenderError::Output(error)
+    }
+}
+
+pub fn run(options: &Options, scene: Scene) -> Result<(), RenderError>
+{
+    let start = Instant::now();
+    let (pixels, statistics) = match options.backend {
+        Backend::Cpu => render_cpu(options, scene)?,
+        Backend::Metal => render_metal(options, &scene)?
+    };
+    println!("Finished ray tracing {:.1} samples per pixel with seed {} in {:.2?}", adaptive::mean_sample_count(&statistics),
+             options.settings.seed, start.elapsed());
+
+    if let Some(path) = &options.output {
+        output::write_image(path, &pixels, options.width, options.height)?;
+        println!("Wrote {}", path.display());
+    }
+    if let Some(path) = &options.aov_output {
+        output::write_image(path, &adaptive::statistics_image(&pixels, &statistics), options.width, options.height)?;
+        println!("Wrote {}", path.display());
+    }
+    Ok(())
+}
+
+// Without an adaptive threshold no pixel converges and the passes stop after the samples of the options
+fn render_cpu(options: &Options, scene: Scene) -> Result<(Vec<[f32; 4]>, Vec<PixelStatistics>), RenderError>
+{
+    let mut raytracer = CpuRayTracer::new(scene, options.width, options.height, &options.settings);
+    raytracer.set_camera(&options.camera);
+    let mut ray_number = 0;
+    while ray_number == 0 || adaptive::needs_samples(raytracer.pixel_statistics(), options.samples) {
+        raytracer.render(ray_number);
+        report_progress(ray_number);
+        ray_number += 1;
+    }
+    Ok((raytracer.output_image().to_vec(), raytracer.pixel_statistics().to_vec()))
+}
+
+#[cfg(target_os = "macos")]
+fn render_metal(options: &Options, scene: &Scene) -> Result<(Vec<[f32; 4]>, Vec<PixelStatistics>), RenderError>
+{
+    use metal::Device;
+    use metal_ray_tracing_rs::raytracer::RayTracer;
+
+    let device = Device::system_default();
+    let command_queue = device.new_command_queue();
+    let mut raytracer = RayTracer::new(&device, scene, options.width, options.height, &options.settings);
+    raytracer.set_camera(&options.camera);
+    let mut ray_number = 0;
+    let mut statistics = Vec::new();
+    let mut gpu_time = 0.0;
+    while ray_number == 0 || adaptive::needs_samples(&statistics, options.samples) {
+        let command_buffer = command_queue.new_command_buffer();
+        raytracer.encode_into(ray_number, command_buffer);
+        command_buffer.commit();
+        command_buffer.wait_until_completed();
+        if let Some(error) = command_buffer.error() {
+            return Err(RenderError::Gpu { sample: ray_number + 1, error });
+        }
+        gpu_time += command_buffer.gpu_end_time() - command_buffer.gpu_start_time();
+        statistics = raytracer.read_pixel_statistics();
+        report_progress(ray_number);
+        ray_number += 1;
+    }
+    println!("The GPU ray traced for {:.2?}", std::time::Duration::from_secs_f64(gpu_time));
+    Ok((raytracer.read_output_image(&device, &command_queue), statistics))
+}
+
+#[cfg(not(target_os = "macos"))]
+fn render_metal(_options: &Options, _scene: &Scene) -> Result<(Vec<[f32; 4]>, Vec<PixelStatistics>), RenderError>
+{
+    unreachable!("the metal backend is rejected by the command line validation on this platform")
+}
+
+fn report_progress(ray_number: usize)
+{
+    if (ray_number + 1).is_multiple_of(10) {
+        println!("Ray number: {}", ray_number + 1);
+    }
+}
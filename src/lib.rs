@@ -0,0 +1,22 @@
+
+pub mod types;
+pub mod adaptive;
+pub mod random;
+pub mod sampler;
+pub mod settings;
+pub mod scene;
+pub mod bsdf;
+pub mod emitters;
+pub mod light_tree;
+pub mod environment;
+pub mod lights;
+pub mod camera;
+pub mod cpu;
+pub mod dispatch;
+pub mod frames;
+pub mod cli;
+pub mod output;
+pub mod compare;
+
+#[cfg(target_os = "macos")]
+pub mod raytracer;
@@ -0,0 +1,263 @@
+// Light tree over the emitter triangles following "Importance Sampling of Many Lights with Adaptive Tree
+// Splitting" (Estevez and Kulla 2018) in the form used by pbrt-v4. Traversing the tree picks emitters
+// proportionally to a conservative estimate of their contribution at the shading point, so emitters that are
+// far away or face away from it are rarely sampled. lightTreeImportance, sampleLightTree and lightTreePdf in
+// tracing.metal mirror this module.
+
+use cgmath::*;
+use std::cmp::Ordering;
+
+use crate::types::*;
+use crate::scene::Scene;
+use crate::emitters::{luminance, NO_EMITTER};
+
+// Largest float below one, keeps the rescaled random number from selecting a branch of probability zero
+const ONE_MINUS_EPSILON: f32 = 1.0 - f32::EPSILON / 2.0;
+
+pub struct LightTree
+{
+    // Depth first, the root is the first node
+    pub nodes: Vec<LightTreeNode>,
+    // Branches taken from the root to the leaf of every emitter triangle, bit i is set where the second child
+    // is taken at depth i
+    pub emitter_trails: Vec<u32>
+}
+
+impl LightTree {
+
+    pub fn new(scene: &Scene) -> LightTree
+    {
+        let leaves: Vec<LightTreeNode> = scene.emitter_triangles.iter().enumerate()
+            .map(|(index, emitter)| emitter_bounds(scene, index as u32, emitter)).collect();
+
+        let mut tree = LightTree { nodes: Vec::with_capacity(2 * leaves.len()), emitter_trails: vec![0; leaves.len()] };
+        let mut emitters: Vec<usize> = (0..leaves.len()).collect();
+        if !emitters.is_empty() {
+            tree.build(&leaves, &mut emitters, 0, 0);
+        }
+        tree
+    }
+
+    // Splitting at the median keeps the tree balanced, so the trails of up to 2^32 emitters fit into 32 bits
+    fn build(&mut self, leaves: &[LightTreeNode], emitters: &mut [usize], trail: u32, depth: u32)
+    {
+        if emitters.len() == 1 {
+            self.nodes.push(leaves[emitters[0]]);
+            self.emitter_trails[emitters[0]] = trail;
+            return;
+        }
+
+        // Split along the largest extent of the emitter centroids
+        let centroid = |index: usize| (Vector3::from(leaves[index].bounds_min) + Vector3::from(leaves[index].bounds_max)) * 0.5;
+        let mut centroid_min = centroid(emitters[0]);
+        let mut centroid_max = centroid_min;
+        for &index in emitters.iter() {
+            let c = centroid(index);
+            for axis in 0..3 {
+                centroid_min[axis] = centroid_min[axis].min(c[axis]);
+                centroid_max[axis] = centroid_max[axis].max(c[axis]);
+            }
+        }
+        let extent = centroid_max - centroid_min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z { 0 } else if extent.y >= extent.z { 1 } else { 2 };
+        emitters.sort_by(|&a, &b| centroid(a)[axis].partial_cmp(&centroid(b)[axis]).unwrap_or(Ordering::Equal));
+
+        let node_index = self.nodes.len();
+        let bounds = emitters[1..].iter().fold(leaves[emitters[0]], |bounds, &index| union(&bounds, &leaves[index]));
+        self.nodes.push(LightTreeNode { emitter_index: NO_EMITTER, ..bounds });
+
+        let (first, second) = emitters.split_at_mut(emitters.len() / 2);
+        self.build(leaves, first, trail, depth + 1);
+        self.nodes[node_index].second_child = self.nodes.len() as u32;
+        self.build(leaves, second, trail | (1 << depth), depth + 1);
+    }
+
+    // Emitter triangle index and the probability it was picked with, None if no emitter can contribute
+    pub fn sample(&self, point: Vector3<f32>, normal: Vector3<f32>, xi: f32) -> Option<(usize, f32)>
+    {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let mut u = xi;
+        let mut pdf = 1.0;
+        let mut node_index = 0;
+        loop {
+            let node = &self.nodes[node_index];
+            if node.emitter_index != NO_EMITTER {
+                return if importance(node, point, normal) > 0.0 { Some((node.emitter_index as usize, pdf)) } else { None };
+            }
+
+            let first = importance(&self.nodes[node_index + 1], point, normal);
+            let second = importance(&self.nodes[node.second_child as usize], point, normal);
+            if first == 0.0 && second == 0.0 {
+                return None;
+            }
+            let first_probability = first / (first + second);
+            if u < first_probability {
+                u = (u / first_probability).min(ONE_MINUS_EPSILON);
+                pdf *= first_probability;
+                node_index += 1;
+            } else {
+                u = ((u - first_probability) / (1.0 - first_probability)).min(ONE_MINUS_EPSILON);
+                pdf *= 1.0 - first_probability;
+                node_index = node.second_child as usize;
+            }
+        }
+    }
+
+    // Probability that sample picks the emitter triangle at the shading point
+    pub fn pdf(&self, point: Vector3<f32>, normal: Vector3<f32>, emitter_index: usize) -> f32
+    {
+        let mut trail = self.emitter_trails[emitter_index];
+        let mut pdf = 1.0;
+        let mut node_index = 0;
+        loop {
+            let node = &self.nodes[node_index];
+            if node.emitter_index != NO_EMITTER {
+                return if importance(node, point, normal) > 0.0 { pdf } else { 0.0 };
+            }
+
+            let first = importance(&self.nodes[node_index + 1], point, normal);
+            let second = importance(&self.nodes[node.second_child as usize], point, normal);
+            if first == 0.0 && second == 0.0 {
+                return 0.0;
+            }
+            let first_probability = first / (first + second);
+            if trail & 1 == 0 {
+                pdf *= first_probability;
+                node_index += 1;
+            } else {
+                pdf *= 1.0 - first_probability;
+                node_index = node.second_child as usize;
+            }
+            trail >>= 1;
+        }
+    }
+}
+
+// Upper bound of the light the emitters below the node send towards a point with the given normal, zero only
+// if none of them can light it
+pub fn importance(node: &LightTreeNode, point: Vector3<f32>, normal: Vector3<f32>) -> f32
+{
+    if node.power == 0.0 {
+        return 0.0;
+    }
+
+    let bounds_min = Vector3::from(node.bounds_min);
+    let bounds_max = Vector3::from(node.bounds_max);
+    let center = (bounds_min + bounds_max) * 0.5;
+    let to_point = point - center;
+    let distance2 = to_point.magnitude2();
+    // Avoids the singularity of points close to the emitters
+    let clamped_distance2 = distance2.max(0.5 * (bounds_max - bounds_min).magnitude());
+
+    // The emitters may face the point from any direction inside their bounding sphere
+    let radius2 = (bounds_max - center).magnitude2();
+    if distance2 <= radius2 {
+        return node.power / clamped_distance2;
+    }
+
+    // Angle the bounding sphere subtends
+    let sin_theta_b = (radius2 / distance2).sqrt();
+    let cos_theta_b = (1.0 - radius2 / distance2).sqrt();
+
+    // Smallest angle between the direction towards the point and a normal in the cone
+    let wi = to_point / distance2.sqrt();
+    let cos_theta_w = Vector3::from(node.axis).dot(wi);
+    let sin_theta_w = (1.0 - cos_theta_w * cos_theta_w).max(0.0).sqrt();
+    let sin_theta_o = (1.0 - node.cos_theta_o * node.cos_theta_o).max(0.0).sqrt();
+    let cos_theta_x = cos_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, node.cos_theta_o);
+    let sin_theta_x = sin_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, node.cos_theta_o);
+    let cos_theta = cos_sub_clamped(sin_theta_x, cos_theta_x, sin_theta_b, cos_theta_b);
+    if cos_theta <= node.cos_theta_e {
+        return 0.0;
+    }
+
+    // Largest cosine at the shading point, both sides as light may be transmitted
+    let cos_theta_i = wi.dot(normal).abs();
+    let sin_theta_i = (1.0 - cos_theta_i * cos_theta_i).max(0.0).sqrt();
+    let cos_theta_i_b = cos_sub_clamped(sin_theta_i, cos_theta_i, sin_theta_b, cos_theta_b);
+
+    (node.power * cos_theta * cos_theta_i_b / clamped_distance2).max(0.0)
+}
+
+// cos(max(0, a - b)) from the sines and cosines of a and b
+fn cos_sub_clamped(sin_a: f32, cos_a: f32, sin_b: f32, cos_b: f32) -> f32
+{
+    if cos_a > cos_b { 1.0 } else { cos_a * cos_b + sin_a * sin_b }
+}
+
+// sin(max(0, a - b)) from the sines and cosines of a and b
+fn sin_sub_clamped(sin_a: f32, cos_a: f32, sin_b: f32, cos_b: f32) -> f32
+{
+    if cos_a > cos_b { 0.0 } else { sin_a * cos_b - cos_a * sin_b }
+}
+
+// Leaf of a triangle, which emits within 90 degrees of its normal
+fn emitter_bounds(scene: &Scene, emitter_index: u32, emitter: &EmitterTriangle) -> LightTreeNode
+{
+    let [a, b, c] = scene.triangle_vertices(emitter.primitive_index);
+    let mut bounds_min = a;
+    let mut bounds_max = a;
+    for vertex in [b, c].iter() {
+        for axis in 0..3 {
+            bounds_min[axis] = bounds_min[axis].min(vertex[axis]);
+            bounds_max[axis] = bounds_max[axis].max(vertex[axis]);
+        }
+    }
+    let normal = (b - a).cross(c - a);
+    let axis = if normal.magnitude2() > 0.0 { normal.normalize() } else { vec3(0.0, 0.0, 1.0) };
+
+    LightTreeNode { bounds_min: bounds_min.into(), power: emitter.area * luminance(emitter.emissive), bounds_max: bounds_max.into(),
+        cos_theta_o: 1.0, axis: axis.into(), cos_theta_e: 0.0, second_child: 0, emitter_index }
+}
+
+// Bounds of both nodes, the normal cone is the smallest one containing both cones
+fn union(a: &LightTreeNode, b: &LightTreeNode) -> LightTreeNode
+{
+    if a.power == 0.0 {
+        return *b;
+    }
+    if b.power == 0.0 {
+        return *a;
+    }
+
+    let mut bounds_min = a.bounds_min;
+    let mut bounds_max = a.bounds_max;
+    for axis in 0..3 {
+        bounds_min[axis] = bounds_min[axis].min(b.bounds_min[axis]);
+        bounds_max[axis] = bounds_max[axis].max(b.bounds_max[axis]);
+    }
+    let (axis, cos_theta_o) = union_cones((Vector3::from(a.axis), a.cos_theta_o), (Vector3::from(b.axis), b.cos_theta_o));
+
+    LightTreeNode { bounds_min, power: a.power + b.power, bounds_max, cos_theta_o, axis: axis.into(),
+        cos_theta_e: a.cos_theta_e.min(b.cos_theta_e), second_child: 0, emitter_index: NO_EMITTER }
+}
+
+fn union_cones(a: (Vector3<f32>, f32), b: (Vector3<f32>, f32)) -> (Vector3<f32>, f32)
+{
+    use std::f32::consts::PI;
+
+    let theta_a = a.1.clamp(-1.0, 1.0).acos();
+    let theta_b = b.1.clamp(-1.0, 1.0).acos();
+    let theta_d = a.0.dot(b.0).clamp(-1.0, 1.0).acos();
+    if (theta_d + theta_b).min(PI) <= theta_a {
+        return a;
+    }
+    if (theta_d + theta_a).min(PI) <= theta_b {
+        return b;
+    }
+
+    // Rotate the axis of a towards b so that the new cone touches the far sides of both
+    let theta_o = 0.5 * (theta_a + theta_d + theta_b);
+    if theta_o >= PI {
+        return (a.0, -1.0);
+    }
+    let rotation_axis = a.0.cross(b.0);
+    if rotation_axis.magnitude2() == 0.0 {
+        return (a.0, -1.0);
+    }
+    let rotation = Basis3::from_axis_angle(rotation_axis.normalize(), Rad(theta_o - theta_a));
+    (rotation.rotate_vector(a.0), theta_o.cos())
+}
@@ -0,0 +1,156 @@
+// Point, spot, directional and sphere lights described in the scene file. Next event estimation picks one of them
+// proportionally to its power and samples a direction towards it. Directional lights with an angular radius and
+// sphere lights cover a solid angle, so BSDF sampled rays hit them as well. Sphere lights are not part of the
+// acceleration structure and do not cast shadows. sampleLight, lightPdf, directionalRadiance and
+// intersectSphereLight in tracing.metal mirror this module.
+
+use cgmath::*;
+use std::f32::consts::PI;
+
+use crate::types::*;
+use crate::bsdf::orthonormal_basis;
+use crate::emitters::luminance;
+
+// Direction towards a light, an emitter triangle or the environment
+pub struct LightSample
+{
+    pub direction: Vector3<f32>,
+    // Length of the shadow ray
+    pub distance: f32,
+    // Radiance arriving from the direction, for delta lights the irradiance perpendicular to it
+    pub radiance: Vector3<f32>,
+    // Solid angle density, for delta lights only the probability of picking the light
+    pub pdf: f32,
+    // Lights that BSDF samples cannot hit, their samples are not weighted by MIS
+    pub delta: bool
+}
+
+// Luminance of the power lights are picked by. Directional lights are assumed to light a disk the size of the scene.
+pub fn power(light: &Light, scene_radius: f32) -> f32
+{
+    let color = luminance(light.color);
+    match light.light_type {
+        LightType::Point => 4.0 * PI * color,
+        LightType::Spot => 2.0 * PI * color * (1.0 - 0.5 * (light.cos_inner + light.cos_outer)),
+        LightType::Directional => PI * scene_radius * scene_radius * color,
+        LightType::Sphere => 4.0 * PI * PI * light.radius * light.radius * color
+    }
+}
+
+// Direction from 'point' towards the light with two uniform random numbers, None if the light does not reach it
+pub fn sample(light: &Light, point: Vector3<f32>, u: Vector2<f32>) -> Option<LightSample>
+{
+    let position = Vector3::from(light.position);
+    let color = Vector3::from(light.color);
+    match light.light_type {
+        LightType::Point | LightType::Spot => {
+            let to_light = position - point;
+            let distance = to_light.magnitude();
+            if distance == 0.0 {
+                return None;
+            }
+            let direction = to_light / distance;
+            let falloff = if light.light_type == LightType::Spot { spot_falloff(light, -direction) } else { 1.0 };
+            if falloff == 0.0 {
+                return None;
+            }
+            Some(LightSample { direction, distance: distance - SURFACE_OFFSET, radiance: color * (falloff / (distance * distance)), pdf: 1.0,
+                delta: true })
+        },
+        LightType::Directional => {
+            let axis = -Vector3::from(light.direction);
+            if light.cos_outer >= 1.0 {
+                return Some(LightSample { direction: axis, distance: f32::INFINITY, radiance: color, pdf: 1.0, delta: true });
+            }
+            // The irradiance is spread evenly over the cone
+            let pdf = cone_pdf(1.0 - light.cos_outer);
+            Some(LightSample { direction: sample_cone(axis, 1.0 - light.cos_outer, u), distance: f32::INFINITY, radiance: color * pdf, pdf,
+                delta: false })
+        },
+        LightType::Sphere => {
+            // Uniform within the cone of directions the sphere covers
+            let one_minus_cos_theta_max = sphere_cone(light, point)?;
+            let direction = sample_cone((position - point).normalize(), one_minus_cos_theta_max, u);
+            // Directions on the edge of the cone may miss the sphere by rounding errors, they touch it at the tangent point
+            let distance = intersect_sphere(light, point, direction)
+                .unwrap_or_else(|| ((position - point).magnitude2() - light.radius * light.radius).sqrt());
+            Some(LightSample { direction, distance: distance - SURFACE_OFFSET, radiance: color, pdf: cone_pdf(one_minus_cos_theta_max),
+                delta: false })
+        }
+    }
+}
+
+// Solid angle density of sample returning a direction from 'point' that hits the light, zero for delta lights
+pub fn pdf(light: &Light, point: Vector3<f32>) -> f32
+{
+    match light.light_type {
+        LightType::Directional if light.cos_outer < 1.0 => cone_pdf(1.0 - light.cos_outer),
+        LightType::Sphere => sphere_cone(light, point).map_or(0.0, cone_pdf),
+        _ => 0.0
+    }
+}
+
+// Radiance of a directional light with an angular radius seen along 'direction', None outside its cone
+pub fn directional_radiance(light: &Light, direction: Vector3<f32>) -> Option<Vector3<f32>>
+{
+    if light.light_type != LightType::Directional || light.cos_outer >= 1.0 || -direction.dot(Vector3::from(light.direction)) < light.cos_outer {
+        return None;
+    }
+    Some(Vector3::from(light.color) * cone_pdf(1.0 - light.cos_outer))
+}
+
+// Distance along the ray to the surface of a sphere light, None if it misses the sphere or starts inside it
+pub fn intersect_sphere(light: &Light, origin: Vector3<f32>, direction: Vector3<f32>) -> Option<f32>
+{
+    if light.light_type != LightType::Sphere {
+        return None;
+    }
+    let to_origin = origin - Vector3::from(light.position);
+    let b = to_origin.dot(direction);
+    let c = to_origin.magnitude2() - light.radius * light.radius;
+    let discriminant = b * b - c;
+    if c <= 0.0 || b >= 0.0 || discriminant < 0.0 {
+        return None;
+    }
+    Some(-b - discriminant.sqrt())
+}
+
+// Smooth transition from the outer to the inner cone, 'direction' points away from the light
+fn spot_falloff(light: &Light, direction: Vector3<f32>) -> f32
+{
+    let cos_theta = direction.dot(Vector3::from(light.direction));
+    if light.cos_inner <= light.cos_outer {
+        return if cos_theta >= light.cos_outer { 1.0 } else { 0.0 };
+    }
+    let t = ((cos_theta - light.cos_outer) / (light.cos_inner - light.cos_outer)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// One minus the cosine of the half angle of the cone the sphere covers seen from the point, written so that it
+// stays accurate for small and distant spheres. None from inside the sphere.
+fn sphere_cone(light: &Light, point: Vector3<f32>) -> Option<f32>
+{
+    let distance2 = (Vector3::from(light.position) - point).magnitude2();
+    let radius2 = light.radius * light.radius;
+    if distance2 <= radius2 {
+        return None;
+    }
+    let sin2_theta_max = radius2 / distance2;
+    Some(sin2_theta_max / (1.0 + (1.0 - sin2_theta_max).sqrt()))
+}
+
+fn cone_pdf(one_minus_cos_theta_max: f32) -> f32
+{
+    1.0 / (2.0 * PI * one_minus_cos_theta_max)
+}
+
+// Uniform direction within the cone around the unit axis
+fn sample_cone(axis: Vector3<f32>, one_minus_cos_theta_max: f32, u: Vector2<f32>) -> Vector3<f32>
+{
+    let one_minus_cos_theta = u.x * one_minus_cos_theta_max;
+    let cos_theta = 1.0 - one_minus_cos_theta;
+    let sin_theta = (one_minus_cos_theta * (2.0 - one_minus_cos_theta)).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.y;
+    let (tangent, bitangent) = orthonormal_basis(axis);
+    (sin_theta * phi.cos() * tangent + sin_theta * phi.sin() * bitangent + cos_theta * axis).normalize()
+}
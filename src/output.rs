@@ -0,0 +1,150 @@
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::Path;
+
+// Writing of accumulated RGBA32F images. The pixels are expected in the layout of the output
+// texture, that is row 0 is the bottom row of the image. Portable float maps can be read back
+// in the same layout.
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ImageFormat
+{
+    Png,
+    Pfm,
+    Exr
+}
+
+impl ImageFormat {
+
+    pub fn from_path(path: &Path) -> Option<ImageFormat>
+    {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "png" => Some(ImageFormat::Png),
+            "pfm" => Some(ImageFormat::Pfm),
+            "exr" => Some(ImageFormat::Exr),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum OutputError
+{
+    UnsupportedFormat(String),
+    Io(io::Error),
+    Encoding(image::ImageError)
+}
+
+impl fmt::Display for OutputError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self {
+            OutputError::UnsupportedFormat(path) => write!(f, "unsupported image format of '{}', expected .png, .pfm or .exr", path),
+            OutputError::Io(error) => write!(f, "failed to write image: {}", error),
+            OutputError::Encoding(error) => write!(f, "failed to encode image: {}", error)
+        }
+    }
+}
+
+impl std::error::Error for OutputError {}
+
+impl From<io::Error> for OutputError
+{
+    fn from(error: io::Error) -> Self
+    {
+        OutputError::Io(error)
+    }
+}
+
+impl From<image::ImageError> for OutputError
+{
+    fn from(error: image::ImageError) -> Self
+    {
+        OutputError::Encoding(error)
+    }
+}
+
+pub fn write_image(path: &Path, pixels: &[[f32; 4]], width: usize, height: usize) -> Result<(), OutputError>
+{
+    assert_eq!(pixels.len(), width * height);
+    match ImageFormat::from_path(path) {
+        Some(ImageFormat::Png) => {
+            let data = top_down_rows(pixels, width).flat_map(|pixel| {
+                [linear_to_srgb(pixel[0]), linear_to_srgb(pixel[1]), linear_to_srgb(pixel[2])]
+            }).collect();
+            let image = image::RgbImage::from_raw(width as u32, height as u32, data).unwrap();
+            image.save_with_format(path, image::ImageFormat::Png)?;
+        },
+        Some(ImageFormat::Exr) => {
+            let data = top_down_rows(pixels, width).flat_map(|pixel| pixel.iter().copied()).collect();
+            let image = image::Rgba32FImage::from_raw(width as u32, height as u32, data).unwrap();
+            image.save_with_format(path, image::ImageFormat::OpenExr)?;
+        },
+        Some(ImageFormat::Pfm) => {
+            let mut writer = BufWriter::new(File::create(path)?);
+            write_pfm(&mut writer, pixels, width, height)?;
+            writer.flush()?;
+        },
+        None => return Err(OutputError::UnsupportedFormat(path.display().to_string()))
+    }
+    Ok(())
+}
+
+// Portable float map, the colour variant stores little endian RGB rows from the bottom up.
+pub fn write_pfm<W: Write>(writer: &mut W, pixels: &[[f32; 4]], width: usize, height: usize) -> io::Result<()>
+{
+    write!(writer, "PF\n{} {}\n-1.0\n", width, height)?;
+    for pixel in pixels {
+        for value in pixel[..3].iter() {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+// Colour portable float map in either byte order, returns the pixels with an alpha of one and the size
+pub fn read_pfm<R: BufRead>(reader: &mut R) -> io::Result<(Vec<[f32; 4]>, usize, usize)>
+{
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, format!("invalid pfm: {}", message));
+    let mut header = Vec::new();
+    for _ in 0..3 {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        header.push(line.trim().to_string());
+    }
+    if header[0] != "PF" {
+        return Err(invalid("expected a colour image starting with 'PF'"));
+    }
+    let size: Vec<usize> = header[1].split_whitespace().filter_map(|value| value.parse().ok()).collect();
+    if size.len() != 2 {
+        return Err(invalid("expected the width and height"));
+    }
+    let little_endian = match header[2].parse::<f32>() {
+        Ok(scale) if scale != 0.0 => scale < 0.0,
+        _ => return Err(invalid("expected a non-zero scale"))
+    };
+
+    let mut bytes = vec![0; size[0] * size[1] * 12];
+    reader.read_exact(&mut bytes)?;
+    let values: Vec<f32> = bytes.chunks(4).map(|b| {
+        let b = [b[0], b[1], b[2], b[3]];
+        if little_endian { f32::from_le_bytes(b) } else { f32::from_be_bytes(b) }
+    }).collect();
+    Ok((values.chunks(3).map(|rgb| [rgb[0], rgb[1], rgb[2], 1.0]).collect(), size[0], size[1]))
+}
+
+// Clamps to [0, 1] and applies the sRGB transfer function
+pub fn linear_to_srgb(value: f32) -> u8
+{
+    let value = if value.is_nan() { 0.0 } else { value.clamp(0.0, 1.0) };
+    let srgb = if value <= 0.003_130_8 { 12.92 * value } else { 1.055 * value.powf(1.0 / 2.4) - 0.055 };
+    (srgb * 255.0).round() as u8
+}
+
+fn top_down_rows(pixels: &[[f32; 4]], width: usize) -> impl Iterator<Item = &[f32; 4]>
+{
+    pixels.chunks(width).rev().flatten()
+}
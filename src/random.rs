@@ -0,0 +1,45 @@
+// Counter based random numbers. Every pixel, sample and dimension hashes to its own random numbers without any
+// state, so pixels far apart and the dimensions of a path are independent and the numbers do not depend on the
+// order pixels are rendered in. pcg4d and random3 in tracing.metal are the twins of the functions here.
+
+use cgmath::*;
+
+// Permuted congruential hash of four integers at once (Jarzynski and Olano 2020). It is a bijection, so different
+// inputs never share their random numbers.
+pub fn pcg4d(v: [u32; 4]) -> [u32; 4]
+{
+    let mut v = v.map(|x| x.wrapping_mul(1664525).wrapping_add(1013904223));
+    mix(&mut v);
+    for x in v.iter_mut() {
+        *x ^= *x >> 16;
+    }
+    mix(&mut v);
+    v
+}
+
+fn mix(v: &mut [u32; 4])
+{
+    v[0] = v[0].wrapping_add(v[1].wrapping_mul(v[3]));
+    v[1] = v[1].wrapping_add(v[2].wrapping_mul(v[0]));
+    v[2] = v[2].wrapping_add(v[0].wrapping_mul(v[1]));
+    v[3] = v[3].wrapping_add(v[1].wrapping_mul(v[2]));
+}
+
+// Scrambles the seed of the render settings into the seed the kernels combine with the dimensions
+pub fn hash_seed(seed: u64) -> u32
+{
+    pcg4d([seed as u32, (seed >> 32) as u32, 0, 0])[0]
+}
+
+// Three uniform random numbers in [0, 1) for a layer of three dimensions of a pixel's sample
+pub fn random3(seed: u32, coordinates: (u32, u32), sample: u32, layer: u32) -> Vector3<f32>
+{
+    let hash = pcg4d([coordinates.0, coordinates.1, sample, layer ^ seed]);
+    vec3(to_unit_float(hash[0]), to_unit_float(hash[1]), to_unit_float(hash[2]))
+}
+
+// The upper 24 bits give every float in [0, 1) with a spacing of 2^-24, exactly representable below one
+pub fn to_unit_float(x: u32) -> f32
+{
+    (x >> 8) as f32 * (1.0 / 16_777_216.0)
+}
@@ -1,118 +1,85 @@
 
 use metal::*;
+use block::ConcreteBlock;
 use std::mem;
 use std::fs::File;
 use std::io::prelude::*;
-use cgmath::*;
-use mersenne_twister::MT19937;
-use rand::Rng;
 
-const NOISE_BLOCK_SIZE: usize = 16;
-const NOISE_BUFFER_SIZE: usize = NOISE_BLOCK_SIZE * NOISE_BLOCK_SIZE * 3;
-
-const SIZE_OF_RAY: usize = 44;
-const SIZE_OF_INTERSECTION: usize = 16;
-
-#[derive(Copy, Clone, Debug)]
-struct Triangle
-{
-    material_index: u32
-}
-
-#[derive(Copy, Clone, Debug)]
-struct Material
-{
-    diffuse: [f32; 3]
-}
-
-#[derive(Copy, Clone, Debug)]
-struct EmitterTriangle
+use crate::types::*;
+use crate::random;
+use crate::dispatch;
+use crate::frames::{FrameRing, MAX_FRAMES_IN_FLIGHT};
+use crate::scene::Scene;
+use crate::emitters::EmitterDistribution;
+use crate::environment::EnvironmentMap;
+use crate::camera::Camera;
+use crate::settings::RenderSettings;
+
+fn new_buffer_with_slice<T>(device: &DeviceRef, data: &[T]) -> Buffer
 {
-    primitive_index: u32,
-    emissive: [f32; 3],
-    area: f32
+    device.new_buffer_with_data(data.as_ptr() as *const _, (data.len() * mem::size_of::<T>()) as u64, MTLResourceOptions::CPUCacheModeDefaultCache)
 }
 
-#[derive(Copy, Clone, Debug)]
-struct ApplicationData
+struct FrameBuffers
 {
-    ray_number: u32,
-    emitter_triangles_count: u32,
-    emitter_total_area: f32
+    app_buffer: Buffer,
+    camera_buffer: Buffer
 }
 
 pub struct RayTracer {
     acceleration_structure: TriangleAccelerationStructure,
     ray_intersector: RayIntersector,
+    shadow_ray_intersector: RayIntersector,
 
     ray_buffer: Option<Buffer>,
+    shadow_ray_buffer: Option<Buffer>,
     intersection_buffer: Option<Buffer>,
+    statistics_buffer: Option<Buffer>,
     triangle_buffer: Buffer,
     material_buffer: Buffer,
-    noise_buffer: Buffer,
-    app_buffer: Buffer,
+    // Uniforms written for every frame, see frames.rs
+    frames: FrameRing<FrameBuffers>,
     vertex_buffer: Buffer,
     index_buffer: Buffer,
     emitter_triangle_buffer: Buffer,
+    alias_table_buffer: Buffer,
+    triangle_emitter_buffer: Buffer,
+    light_tree_buffer: Buffer,
+    emitter_trail_buffer: Buffer,
+    environment_buffer: Buffer,
+    environment_pixel_pdf_buffer: Buffer,
+    environment_conditional_cdf_buffer: Buffer,
+    environment_marginal_cdf_buffer: Buffer,
+    light_buffer: Buffer,
+    light_table_buffer: Buffer,
 
     output_image: Option<Texture>,
     output_image_size: (usize, usize, usize),
     no_emitter_triangles: usize,
-    total_light_area: f32,
+    environment_size: (usize, usize),
+    environment_probability: f32,
+    lights_count: usize,
+    light_probability: f32,
+    camera: Camera,
+    settings: RenderSettings,
 
     test_pipeline_state: ComputePipelineState,
     accumulator_pipeline_state: ComputePipelineState,
     ray_generator_pipeline_state: ComputePipelineState,
     intersection_handler_pipeline_state: ComputePipelineState,
-    shadow_handler_pipeline_state: ComputePipelineState,
-
-    rng: MT19937
+    shadow_handler_pipeline_state: ComputePipelineState
 }
 
 impl RayTracer {
 
-    pub fn new(device: &DeviceRef, width: usize, height: usize) -> RayTracer
+    pub fn new(device: &DeviceRef, scene: &Scene, width: usize, height: usize, settings: &RenderSettings) -> RayTracer
     {
-        let (models, materials) = tobj::load_obj(&std::path::PathBuf::from("../../Data/3D models/cornellbox/cornellbox.obj")).unwrap();
-
-        let mut vertex_data = Vec::new();
-        let mut index_data = Vec::new();
-        let mut triangle_data = Vec::new();
-        let mut emitter_triangle_data = Vec::new();
-        let mut total_light_area = 0.0f32;
-        for model in models {
-            println!("{:?}", model);
-            let index = (vertex_data.len() / 3) as u32;
-            vertex_data.append(&mut model.mesh.positions.clone());
-            triangle_data.append(&mut vec![Triangle {material_index: model.mesh.material_id.unwrap() as u32}; model.mesh.indices.len()/3]);
-
-            if let Some(emissive_string) = materials[model.mesh.material_id.unwrap()].unknown_param.get("Ke") {
-                for model_primitive_index in 0..model.mesh.indices.len()/3 {
-                    let mut i = 3 * model.mesh.indices[model_primitive_index*3] as usize;
-                    let p0 = cgmath::Vector3::new(model.mesh.positions[i], model.mesh.positions[i + 1],model.mesh.positions[i + 2]);
-                    i = 3 * model.mesh.indices[model_primitive_index*3 + 1] as usize;
-                    let p1 = cgmath::Vector3::new(model.mesh.positions[i], model.mesh.positions[i + 1],model.mesh.positions[i + 2]);
-                    i = 3 * model.mesh.indices[model_primitive_index*3 + 2] as usize;
-                    let p2 = cgmath::Vector3::new(model.mesh.positions[i], model.mesh.positions[i + 1],model.mesh.positions[i + 2]);
-
-                    let area = 0.5 * (p1 - p0).cross(p2 - p0).magnitude();
-                    total_light_area += area;
-                    let emissive = parse_float3(emissive_string);
-
-                    emitter_triangle_data.push( EmitterTriangle {primitive_index: (index_data.len()/3 + model_primitive_index) as u32, emissive, area} );
-                }
-            }
-
-            for i in model.mesh.indices.iter() {
-                index_data.push(index + i);
-            }
-        }
-
-        let mut material_data = Vec::new();
-        for material in materials {
-            println!("{:?}", material);
-            material_data.push(Material { diffuse: material.diffuse });
-        }
+        let vertex_data = &scene.vertices;
+        let index_data = &scene.indices;
+        let triangle_data = &scene.triangles;
+        let material_data = &scene.materials;
+        let emitter_triangle_data = &scene.emitter_triangles;
+        let emitters = EmitterDistribution::new(scene);
 
         // Build acceleration structure:
         let vertex_buffer = device.new_buffer_with_data( unsafe { mem::transmute(vertex_data.as_ptr()) },
@@ -127,12 +94,33 @@ impl RayTracer {
         let material_buffer = device.new_buffer_with_data( unsafe { mem::transmute(material_data.as_ptr()) },
                                      (material_data.len() * mem::size_of::<Material>()) as u64,
                                      MTLResourceOptions::CPUCacheModeDefaultCache);
-        let emitter_triangle_buffer = device.new_buffer_with_data( unsafe { mem::transmute(emitter_triangle_data.as_ptr()) },
-                                     (emitter_triangle_data.len() * mem::size_of::<EmitterTriangle>()) as u64,
+        // Empty arrays are padded, Metal cannot create buffers of length zero
+        let emitter_data = emitters.buffer_data(scene);
+        let emitter_triangle_buffer = new_buffer_with_slice(device, &emitter_data.emitter_triangles);
+        let alias_table_buffer = new_buffer_with_slice(device, &emitter_data.alias_table);
+        let triangle_emitter_buffer = new_buffer_with_slice(device, &emitter_data.triangle_emitters);
+        let light_tree_buffer = new_buffer_with_slice(device, &emitter_data.light_tree_nodes);
+        let emitter_trail_buffer = new_buffer_with_slice(device, &emitter_data.emitter_trails);
+        // Rays leaving the scene read a black pixel if there is no environment
+        let environment = scene.environment.clone().unwrap_or_else(|| EnvironmentMap::constant([0.0; 3]));
+        let environment_buffer = device.new_buffer_with_data( unsafe { mem::transmute(environment.pixels.as_ptr()) },
+                                     (environment.pixels.len() * 3 * mem::size_of::<f32>()) as u64,
+                                     MTLResourceOptions::CPUCacheModeDefaultCache);
+        let environment_pixel_pdf_buffer = device.new_buffer_with_data( unsafe { mem::transmute(environment.pixel_pdfs.as_ptr()) },
+                                     (environment.pixel_pdfs.len() * mem::size_of::<f32>()) as u64,
                                      MTLResourceOptions::CPUCacheModeDefaultCache);
-        let noise_buffer = device.new_buffer((NOISE_BUFFER_SIZE * mem::size_of::<f32>()) as u64,
-                                             MTLResourceOptions::CPUCacheModeDefaultCache);
-        let app_buffer = device.new_buffer(mem::size_of::<ApplicationData>() as u64, MTLResourceOptions::CPUCacheModeDefaultCache);
+        let environment_conditional_cdf_buffer = device.new_buffer_with_data( unsafe { mem::transmute(environment.conditional_cdfs.as_ptr()) },
+                                     (environment.conditional_cdfs.len() * mem::size_of::<f32>()) as u64,
+                                     MTLResourceOptions::CPUCacheModeDefaultCache);
+        let environment_marginal_cdf_buffer = device.new_buffer_with_data( unsafe { mem::transmute(environment.marginal_cdf.as_ptr()) },
+                                     (environment.marginal_cdf.len() * mem::size_of::<f32>()) as u64,
+                                     MTLResourceOptions::CPUCacheModeDefaultCache);
+        let light_buffer = new_buffer_with_slice(device, &emitter_data.lights);
+        let light_table_buffer = new_buffer_with_slice(device, &emitter_data.light_table);
+        let frames = FrameRing::new((0..MAX_FRAMES_IN_FLIGHT).map(|_| FrameBuffers {
+            app_buffer: device.new_buffer(mem::size_of::<ApplicationData>() as u64, MTLResourceOptions::CPUCacheModeDefaultCache),
+            camera_buffer: device.new_buffer(mem::size_of::<CameraData>() as u64, MTLResourceOptions::CPUCacheModeDefaultCache)
+        }).collect());
 
         let acceleration_structure = TriangleAccelerationStructure::new(&device);
         acceleration_structure.set_vertex_buffer(Some(&vertex_buffer));
@@ -149,6 +137,12 @@ impl RayTracer {
         ray_intersector.set_intersection_stride(SIZE_OF_INTERSECTION as u64);
         ray_intersector.set_intersection_data_type(MPSIntersectionDataType::distancePrimitiveIndexCoordinates);
 
+        let shadow_ray_intersector = RayIntersector::new(&device);
+        shadow_ray_intersector.set_ray_stride(SIZE_OF_SHADOW_RAY as u64);
+        shadow_ray_intersector.set_ray_data_type(MPSRayDataType::originMinDistanceDirectionMaxDistance);
+        shadow_ray_intersector.set_intersection_stride(SIZE_OF_INTERSECTION as u64);
+        shadow_ray_intersector.set_intersection_data_type(MPSIntersectionDataType::distancePrimitiveIndexCoordinates);
+
         // Pipeline states:
         let test_pipeline_state = Self::create_compute_pipeline_state(device, "src/test.metal", "imageFillTest");
         let ray_generator_pipeline_state = Self::create_compute_pipeline_state(device, "src/tracing.metal", "generateRays");
@@ -156,23 +150,28 @@ impl RayTracer {
         let shadow_handler_pipeline_state = Self::create_compute_pipeline_state(device, "src/tracing.metal", "handleShadows");
         let accumulator_pipeline_state = Self::create_compute_pipeline_state(device, "src/tracing.metal", "accumulateImage");
 
-        let mut val = RayTracer {acceleration_structure, ray_intersector, vertex_buffer, index_buffer, triangle_buffer, emitter_triangle_buffer, material_buffer, noise_buffer, app_buffer, ray_buffer: None, intersection_buffer: None,
-            no_emitter_triangles: emitter_triangle_data.len(), total_light_area, output_image: None, output_image_size: (0,0,0), test_pipeline_state, ray_generator_pipeline_state, intersection_handler_pipeline_state, shadow_handler_pipeline_state, accumulator_pipeline_state,
-            rng: MT19937::new_unseeded()};
+        let mut val = RayTracer {acceleration_structure, ray_intersector, shadow_ray_intersector, vertex_buffer, index_buffer, triangle_buffer, emitter_triangle_buffer, alias_table_buffer, triangle_emitter_buffer, light_tree_buffer, emitter_trail_buffer, environment_buffer, environment_pixel_pdf_buffer,
+            environment_conditional_cdf_buffer, environment_marginal_cdf_buffer, light_buffer, light_table_buffer, material_buffer, frames, ray_buffer: None, shadow_ray_buffer: None, intersection_buffer: None, statistics_buffer: None,
+            no_emitter_triangles: emitter_triangle_data.len(), environment_size: (environment.width, environment.height),
+            environment_probability: emitters.environment_probability, lights_count: scene.lights.len(), light_probability: emitters.light_probability, camera: Camera::default(), settings: *settings, output_image: None, output_image_size: (0,0,0), test_pipeline_state, ray_generator_pipeline_state, intersection_handler_pipeline_state, shadow_handler_pipeline_state, accumulator_pipeline_state};
         val.resize(device, width, height);
         val
     }
 
-    fn update_noise_buffer(&mut self)
+    fn update_frame_buffers(&self, ray_number: usize)
     {
-        let mut data = [0.0f32; NOISE_BUFFER_SIZE];
-        for i in 0..NOISE_BUFFER_SIZE {
-            data[i] = self.rng.next_f32();
-        }
-
+        let frame = self.frames.current();
         unsafe {
-            let ptr = self.noise_buffer.contents() as *mut [f32; NOISE_BUFFER_SIZE];
-            *ptr = mem::transmute(data);
+            *(frame.camera_buffer.contents() as *mut CameraData) = self.camera.data();
+            let ptr = frame.app_buffer.contents() as *mut ApplicationData;
+            *ptr = ApplicationData {ray_number: ray_number as u32, seed: random::hash_seed(self.settings.seed),
+                sampler: self.settings.sampler, emitter_triangles_count: self.no_emitter_triangles as u32,
+                max_depth: self.settings.max_depth, russian_roulette_depth: self.settings.russian_roulette_depth,
+                light_sampling: self.settings.light_sampling, emitter_selection: self.settings.emitter_selection,
+                environment_width: self.environment_size.0 as u32, environment_height: self.environment_size.1 as u32,
+                environment_probability: self.environment_probability, lights_count: self.lights_count as u32,
+                light_probability: self.light_probability, adaptive_threshold: self.settings.adaptive_threshold,
+                image_width: self.output_image_size.0 as u32, image_height: self.output_image_size.1 as u32};
         }
     }
 
@@ -182,7 +181,10 @@ impl RayTracer {
         let mut contents = String::new();
         file.read_to_string(&mut contents).unwrap();
 
+        // Fast math lets the compiler reorder and approximate floating point operations, which makes renders with the
+        // same seed differ between compilers and devices
         let options = CompileOptions::new();
+        options.set_fast_math_enabled(false);
         let library = device.new_library_with_source(&contents, &options).unwrap();
         let compute_function = library.get_function(function_name, None).unwrap();
 
@@ -193,10 +195,23 @@ impl RayTracer {
 
     }
 
+    // The camera aspect ratio follows the output image size. The camera takes effect with the next frame.
+    pub fn set_camera(&mut self, camera: &Camera)
+    {
+        self.camera = *camera;
+        self.camera.aspect = self.output_image_size.0 as f32 / self.output_image_size.1 as f32;
+    }
+
+    pub fn camera(&self) -> &Camera
+    {
+        &self.camera
+    }
+
     pub fn resize(&mut self, device: &DeviceRef, width: usize, height: usize)
     {
         self.output_image_size = (width, height, 1);
         let ray_count = width * height;
+        self.camera.aspect = width as f32 / height as f32;
 
         let texture_descriptor = TextureDescriptor::new();
         texture_descriptor.set_pixel_format(MTLPixelFormat::RGBA32Float);
@@ -207,50 +222,61 @@ impl RayTracer {
         self.output_image = Some(device.new_texture(&texture_descriptor));
 
         self.ray_buffer = Some(device.new_buffer((ray_count * SIZE_OF_RAY) as u64, MTLResourceOptions::StorageModePrivate));
+        self.shadow_ray_buffer = Some(device.new_buffer((ray_count * SIZE_OF_SHADOW_RAY) as u64, MTLResourceOptions::StorageModePrivate));
         self.intersection_buffer = Some(device.new_buffer((ray_count * SIZE_OF_INTERSECTION) as u64, MTLResourceOptions::StorageModePrivate));
+        // Read by the host to decide when the adaptive passes are done, behind the frames in flight, see frames.rs
+        self.statistics_buffer = Some(device.new_buffer((ray_count * mem::size_of::<PixelStatistics>()) as u64, MTLResourceOptions::StorageModeShared));
 
     }
 
+    // Waits while all frames are in flight. The command buffer has to be committed, it releases the frame when it
+    // completes.
     pub fn encode_into(&mut self, ray_number: usize, command_buffer: &CommandBufferRef)
     {
-        self.update_noise_buffer();
-
-        self.encode_ray_generator(command_buffer, ray_number);
-
-        self.ray_intersector.encode_intersection_to_command_buffer(command_buffer,
-                                                                   MPSIntersectionType::nearest,
-                                                                   self.ray_buffer.as_ref().unwrap(), 0,
-                                                                   self.intersection_buffer.as_ref().unwrap(), 0,
-                                                                   (self.output_image_size.0 * self.output_image_size.1) as u64,
-                                                                   &self.acceleration_structure);
-
-        self.encode_intersection_handler(command_buffer, ray_number);
-
-        self.ray_intersector.encode_intersection_to_command_buffer(command_buffer,
-                                                                   MPSIntersectionType::any,
-                                                                   self.ray_buffer.as_ref().unwrap(), 0,
-                                                                   self.intersection_buffer.as_ref().unwrap(), 0,
-                                                                   (self.output_image_size.0 * self.output_image_size.1) as u64,
-                                                                   &self.acceleration_structure);
-
-        self.encode_shadow_handler(command_buffer);
+        let frame_available = self.frames.next_frame();
+        command_buffer.add_completed_handler(&ConcreteBlock::new(move |_: &CommandBufferRef| frame_available.signal()).copy());
+        self.update_frame_buffers(ray_number);
+
+        self.encode_ray_generator(command_buffer);
+
+        for bounce in 0..self.settings.max_depth {
+            self.ray_intersector.encode_intersection_to_command_buffer(command_buffer,
+                                                                       MPSIntersectionType::nearest,
+                                                                       self.ray_buffer.as_ref().unwrap(), 0,
+                                                                       self.intersection_buffer.as_ref().unwrap(), 0,
+                                                                       (self.output_image_size.0 * self.output_image_size.1) as u64,
+                                                                       &self.acceleration_structure);
+
+            self.encode_intersection_handler(command_buffer, bounce);
+
+            self.shadow_ray_intersector.encode_intersection_to_command_buffer(command_buffer,
+                                                                              MPSIntersectionType::any,
+                                                                              self.shadow_ray_buffer.as_ref().unwrap(), 0,
+                                                                              self.intersection_buffer.as_ref().unwrap(), 0,
+                                                                              (self.output_image_size.0 * self.output_image_size.1) as u64,
+                                                                              &self.acceleration_structure);
+
+            self.encode_shadow_handler(command_buffer);
+        }
 
-        self.encode_accumulator(command_buffer, ray_number);
+        self.encode_accumulator(command_buffer);
     }
 
-    fn encode_ray_generator(&self, command_buffer: &CommandBufferRef, ray_number: usize)
+    fn encode_ray_generator(&self, command_buffer: &CommandBufferRef)
     {
         let encoder = command_buffer.new_compute_command_encoder();
 
         encoder.set_buffer(0, Some(self.ray_buffer.as_ref().unwrap()), 0);
-        encoder.set_buffer(1, Some(&self.noise_buffer), 0);
+        encoder.set_buffer(1, Some(&self.frames.current().app_buffer), 0);
+        encoder.set_buffer(2, Some(&self.frames.current().camera_buffer), 0);
+        encoder.set_buffer(3, Some(self.statistics_buffer.as_ref().unwrap()), 0);
         encoder.set_compute_pipeline_state(&self.ray_generator_pipeline_state);
         self.dispatch_thread_groups(&encoder);
 
         encoder.end_encoding();
     }
 
-    fn encode_intersection_handler(&self, command_buffer: &CommandBufferRef, ray_number: usize)
+    fn encode_intersection_handler(&self, command_buffer: &CommandBufferRef, bounce: u32)
     {
         let encoder = command_buffer.new_compute_command_encoder();
 
@@ -261,8 +287,19 @@ impl RayTracer {
         encoder.set_buffer(4, Some(&self.vertex_buffer), 0);
         encoder.set_buffer(5, Some(&self.index_buffer), 0);
         encoder.set_buffer(6, Some(&self.emitter_triangle_buffer), 0);
-        encoder.set_buffer(7, Some(&self.app_buffer), 0);
-        encoder.set_buffer(8, Some(&self.noise_buffer), 0);
+        encoder.set_buffer(7, Some(&self.frames.current().app_buffer), 0);
+        encoder.set_buffer(8, Some(self.shadow_ray_buffer.as_ref().unwrap()), 0);
+        encoder.set_bytes(9, mem::size_of::<u32>() as u64, &bounce as *const u32 as *const _);
+        encoder.set_buffer(10, Some(&self.alias_table_buffer), 0);
+        encoder.set_buffer(11, Some(&self.triangle_emitter_buffer), 0);
+        encoder.set_buffer(12, Some(&self.light_tree_buffer), 0);
+        encoder.set_buffer(13, Some(&self.emitter_trail_buffer), 0);
+        encoder.set_buffer(14, Some(&self.environment_buffer), 0);
+        encoder.set_buffer(15, Some(&self.environment_pixel_pdf_buffer), 0);
+        encoder.set_buffer(16, Some(&self.environment_conditional_cdf_buffer), 0);
+        encoder.set_buffer(17, Some(&self.environment_marginal_cdf_buffer), 0);
+        encoder.set_buffer(18, Some(&self.light_buffer), 0);
+        encoder.set_buffer(19, Some(&self.light_table_buffer), 0);
         encoder.set_compute_pipeline_state(&self.intersection_handler_pipeline_state);
         self.dispatch_thread_groups(&encoder);
 
@@ -274,25 +311,23 @@ impl RayTracer {
         let encoder = command_buffer.new_compute_command_encoder();
 
         encoder.set_buffer(0, Some(self.ray_buffer.as_ref().unwrap()), 0);
-        encoder.set_buffer(1, Some(self.intersection_buffer.as_ref().unwrap()), 0);
+        encoder.set_buffer(1, Some(self.shadow_ray_buffer.as_ref().unwrap()), 0);
+        encoder.set_buffer(2, Some(self.intersection_buffer.as_ref().unwrap()), 0);
+        encoder.set_buffer(3, Some(&self.frames.current().app_buffer), 0);
         encoder.set_compute_pipeline_state(&self.shadow_handler_pipeline_state);
         self.dispatch_thread_groups(&encoder);
 
         encoder.end_encoding();
     }
 
-    fn encode_accumulator(&self, command_buffer: &CommandBufferRef, ray_number: usize)
+    fn encode_accumulator(&self, command_buffer: &CommandBufferRef)
     {
-        unsafe {
-            let ptr = self.app_buffer.contents() as *mut ApplicationData;
-            *ptr = ApplicationData {ray_number: ray_number as u32, emitter_triangles_count: self.no_emitter_triangles as u32, emitter_total_area: self.total_light_area};
-        }
-
         let encoder = command_buffer.new_compute_command_encoder();
 
         encoder.set_texture(0, Some(self.output_image.as_ref().unwrap()));
         encoder.set_buffer(0, Some(self.ray_buffer.as_ref().unwrap()), 0);
-        encoder.set_buffer(1, Some(&self.app_buffer), 0);
+        encoder.set_buffer(1, Some(&self.frames.current().app_buffer), 0);
+        encoder.set_buffer(2, Some(self.statistics_buffer.as_ref().unwrap()), 0);
         encoder.set_compute_pipeline_state(&self.accumulator_pipeline_state);
         self.dispatch_thread_groups(&encoder);
 
@@ -303,7 +338,9 @@ impl RayTracer {
     {
         let encoder = command_buffer.new_compute_command_encoder();
 
+        let size = [self.output_image_size.0 as u32, self.output_image_size.1 as u32];
         encoder.set_texture(0, Some(self.output_image.as_ref().unwrap()));
+        encoder.set_bytes(0, mem::size_of::<[u32; 2]>() as u64, size.as_ptr() as *const _);
         encoder.set_compute_pipeline_state(&self.test_pipeline_state);
         self.dispatch_thread_groups(&encoder);
 
@@ -312,10 +349,10 @@ impl RayTracer {
 
     fn dispatch_thread_groups(&self, encoder: &ComputeCommandEncoderRef)
     {
-        let threads_per_thread_group = MTLSize {width: 8, height: 8, depth: 1};
-        let thread_groups_count = MTLSize {width: self.output_image_size.0 as u64 / threads_per_thread_group.width,
-            height: self.output_image_size.1 as u64 / threads_per_thread_group.height,
-            depth: self.output_image_size.2 as u64 / threads_per_thread_group.depth};
+        let (group_width, group_height) = dispatch::THREADS_PER_THREAD_GROUP;
+        let threads_per_thread_group = MTLSize {width: group_width as u64, height: group_height as u64, depth: 1};
+        let (groups_width, groups_height) = dispatch::thread_groups_count((self.output_image_size.0, self.output_image_size.1));
+        let thread_groups_count = MTLSize {width: groups_width as u64, height: groups_height as u64, depth: 1};
         encoder.dispatch_thread_groups(thread_groups_count, threads_per_thread_group);
     }
 
@@ -324,13 +361,41 @@ impl RayTracer {
         self.output_image.as_ref().unwrap()
     }
 
-}
+    // Copies the output texture into CPU memory, row 0 is the bottom row as in the CPU reference
+    pub fn read_output_image(&self, device: &DeviceRef, command_queue: &CommandQueueRef) -> Vec<[f32; 4]>
+    {
+        let (width, height, _) = self.output_image_size;
+        let bytes_per_row = width * mem::size_of::<[f32; 4]>();
+        let buffer = device.new_buffer((bytes_per_row * height) as u64, MTLResourceOptions::StorageModeShared);
+
+        let command_buffer = command_queue.new_command_buffer();
+        let encoder = command_buffer.new_blit_command_encoder();
+        encoder.copy_from_texture_to_buffer(self.output_texture(), 0, 0, MTLOrigin {x: 0, y: 0, z: 0},
+                                            MTLSize {width: width as u64, height: height as u64, depth: 1},
+                                            &buffer, 0, bytes_per_row as u64, (bytes_per_row * height) as u64, MTLBlitOption::empty());
+        encoder.end_encoding();
+        command_buffer.commit();
+        command_buffer.wait_until_completed();
 
-fn parse_float3(val_str: &str) -> [f32; 3] {
-    let mut words = val_str[..].split_whitespace();
-    let mut vals = [0.0f32; 3];
-    for (i, p) in words.enumerate() {
-        vals[i] = std::str::FromStr::from_str(p).unwrap();
+        let mut pixels = vec![[0.0f32; 4]; width * height];
+        unsafe {
+            std::ptr::copy_nonoverlapping(buffer.contents() as *const [f32; 4], pixels.as_mut_ptr(), pixels.len());
+        }
+        pixels
     }
-    vals
-}
\ No newline at end of file
+
+    // Samples taken by every pixel, in the layout of the output image. Every pass updates the statistics, so this
+    // waits until all committed command buffers have completed.
+    pub fn read_pixel_statistics(&self) -> Vec<PixelStatistics>
+    {
+        self.frames.wait_until_idle();
+        let (width, height, _) = self.output_image_size;
+        let mut statistics = vec![PixelStatistics::default(); width * height];
+        unsafe {
+            std::ptr::copy_nonoverlapping(self.statistics_buffer.as_ref().unwrap().contents() as *const PixelStatistics, statistics.as_mut_ptr(),
+                                          statistics.len());
+        }
+        statistics
+    }
+
+}
@@ -0,0 +1,136 @@
+// Sample generators for the dimensions of a path. Every pixel, sample and layer of three dimensions maps to its
+// numbers without any state, like random.rs. The low discrepancy samplers spread the samples of a pixel more evenly
+// than independent random numbers, so the error falls faster with the number of samples. sample3 and the functions
+// it calls in tracing.metal are the twins of this module.
+
+use cgmath::*;
+
+use crate::random::{pcg4d, random3, to_unit_float};
+use crate::types::SamplerType;
+
+pub trait Sampler
+{
+    // Three numbers in [0, 1) for a layer of three dimensions of a pixel's sample
+    fn sample3(&self, coordinates: (u32, u32), sample: u32, layer: u32) -> Vector3<f32>;
+}
+
+// Uncorrelated numbers from the counter based hash
+pub struct IndependentSampler
+{
+    // Scrambled seed as in ApplicationData, see random::hash_seed
+    pub seed: u32
+}
+
+// The first three dimensions of the Sobol sequence with nested uniform (Owen) scrambling, following Burley 2020,
+// "Practical Hash-based Owen Scrambling". Every pixel and layer shuffles the order of the samples and scrambles
+// the dimensions with its own seeds, so layers are not correlated with each other. Any power of two of samples
+// starting at zero stratifies every pair of the first two dimensions.
+pub struct SobolSampler
+{
+    pub seed: u32
+}
+
+// A rank-1 lattice over the samples, the Kronecker sequence with the generalised golden ratio, rotated differently
+// in every pixel. The rotations come from the R2 lattice over the pixels, so the samples of neighbouring pixels are
+// spread evenly and the error appears as blue noise rather than white noise. Every tile of pixels and layer shifts
+// the rotations and visits the points of the sequence in its own Owen scrambled order. That keeps the layers
+// uncorrelated, and any power of two of samples still uses exactly the first points of the sequence.
+pub struct BlueNoiseSampler
+{
+    pub seed: u32
+}
+
+// Direction numbers of the second and third dimension of the Sobol sequence from the primitive polynomials x + 1
+// and x^2 + x + 1, the first dimension is the bit reversed index
+pub const SOBOL_DIRECTIONS: [[u32; 32]; 2] = [
+    [0x80000000, 0xc0000000, 0xa0000000, 0xf0000000, 0x88000000, 0xcc000000, 0xaa000000, 0xff000000,
+     0x80800000, 0xc0c00000, 0xa0a00000, 0xf0f00000, 0x88880000, 0xcccc0000, 0xaaaa0000, 0xffff0000,
+     0x80008000, 0xc000c000, 0xa000a000, 0xf000f000, 0x88008800, 0xcc00cc00, 0xaa00aa00, 0xff00ff00,
+     0x80808080, 0xc0c0c0c0, 0xa0a0a0a0, 0xf0f0f0f0, 0x88888888, 0xcccccccc, 0xaaaaaaaa, 0xffffffff],
+    [0x80000000, 0xc0000000, 0x60000000, 0x90000000, 0xe8000000, 0x5c000000, 0x8e000000, 0xc5000000,
+     0x68800000, 0x9cc00000, 0xee600000, 0x55900000, 0x80680000, 0xc09c0000, 0x60ee0000, 0x90550000,
+     0xe8808000, 0x5cc0c000, 0x8e606000, 0xc5909000, 0x6868e800, 0x9c9c5c00, 0xeeee8e00, 0x5555c500,
+     0x8000e880, 0xc0005cc0, 0x60008e60, 0x9000c590, 0xe8006868, 0x5c009c9c, 0x8e00eeee, 0xc5005555]];
+
+// Fractional parts of 1 / phi^i in 32 bit fixed point, where phi is the real root of x^4 = x + 1
+pub const SAMPLE_LATTICE: [u32; 3] = [0xd1b54a33, 0xabc98389, 0x8cb92ba7];
+// Multipliers of the pixel coordinates for each dimension from the plastic number, the real root of x^3 = x + 1.
+// The second and third dimension use the lattice transposed and mirrored, which are independent of the first.
+pub const PIXEL_LATTICE: [[u32; 2]; 3] = [[0xc13fa9a9, 0x91e10da6], [0x91e10da6, 0xc13fa9a9], [0xc13fa9a9, 0x6e1ef25a]];
+// Size of the square tiles of pixels that share the shifts of the rotations
+pub const BLUE_NOISE_TILE_SIZE: u32 = 8;
+
+impl Sampler for IndependentSampler
+{
+    fn sample3(&self, coordinates: (u32, u32), sample: u32, layer: u32) -> Vector3<f32>
+    {
+        random3(self.seed, coordinates, sample, layer)
+    }
+}
+
+impl Sampler for SobolSampler
+{
+    fn sample3(&self, coordinates: (u32, u32), sample: u32, layer: u32) -> Vector3<f32>
+    {
+        let seeds = pcg4d([coordinates.0, coordinates.1, layer, self.seed]);
+        let index = nested_uniform_scramble(sample, seeds[0]);
+        let sobol = [index.reverse_bits(), sobol(index, &SOBOL_DIRECTIONS[0]), sobol(index, &SOBOL_DIRECTIONS[1])];
+        vec3(to_unit_float(nested_uniform_scramble(sobol[0], seeds[1])), to_unit_float(nested_uniform_scramble(sobol[1], seeds[2])),
+             to_unit_float(nested_uniform_scramble(sobol[2], seeds[3])))
+    }
+}
+
+impl Sampler for BlueNoiseSampler
+{
+    fn sample3(&self, coordinates: (u32, u32), sample: u32, layer: u32) -> Vector3<f32>
+    {
+        let seeds = pcg4d([coordinates.0 / BLUE_NOISE_TILE_SIZE, coordinates.1 / BLUE_NOISE_TILE_SIZE, layer, self.seed]);
+        let index = nested_uniform_scramble(sample, seeds[0]);
+        let mut values = [0.0; 3];
+        for (dimension, value) in values.iter_mut().enumerate() {
+            let rotation = coordinates.0.wrapping_mul(PIXEL_LATTICE[dimension][0])
+                .wrapping_add(coordinates.1.wrapping_mul(PIXEL_LATTICE[dimension][1]))
+                .wrapping_add(seeds[dimension + 1]);
+            *value = to_unit_float(index.wrapping_mul(SAMPLE_LATTICE[dimension]).wrapping_add(rotation));
+        }
+        Vector3::from(values)
+    }
+}
+
+// The sampler of the render settings, what sample3 in tracing.metal does for the kernels
+pub fn sample3(sampler_type: SamplerType, seed: u32, coordinates: (u32, u32), sample: u32, layer: u32) -> Vector3<f32>
+{
+    match sampler_type {
+        SamplerType::Independent => IndependentSampler { seed }.sample3(coordinates, sample, layer),
+        SamplerType::Sobol => SobolSampler { seed }.sample3(coordinates, sample, layer),
+        SamplerType::BlueNoise => BlueNoiseSampler { seed }.sample3(coordinates, sample, layer)
+    }
+}
+
+fn sobol(index: u32, directions: &[u32; 32]) -> u32
+{
+    let mut result = 0;
+    let mut bits = index;
+    let mut bit = 0;
+    while bits != 0 {
+        if bits & 1 != 0 {
+            result ^= directions[bit];
+        }
+        bits >>= 1;
+        bit += 1;
+    }
+    result
+}
+
+// Flips every bit depending on the bits above it (Laine and Karras 2011), a random permutation that maps every
+// dyadic interval onto another one of the same size
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32
+{
+    let mut x = x.reverse_bits();
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x.reverse_bits()
+}
@@ -0,0 +1,233 @@
+
+use cgmath::*;
+use std::fmt;
+use std::path::Path;
+
+use crate::types::*;
+use crate::environment::EnvironmentMap;
+
+pub const DEFAULT_SCENE_PATH: &str = "../../Data/3D models/cornellbox/cornellbox.obj";
+
+// Flattened triangle soup in the layout expected by the acceleration structure and the kernels:
+// three floats per vertex, three indices per triangle and one Triangle (material id) per triangle.
+//
+// Analytic lights are given by 'light' lines in the OBJ file, which other OBJ readers ignore. Positions and
+// directions are in scene units, angles in degrees and colours are linear RGB:
+//
+//   light point <x y z> <intensity r g b>
+//   light spot <x y z> <direction x y z> <inner angle> <outer angle> <intensity r g b>
+//   light directional <direction x y z> <angular radius> <irradiance r g b>
+//   light sphere <x y z> <radius> <radiance r g b>
+pub struct Scene
+{
+    pub vertices: Vec<f32>,
+    pub indices: Vec<u32>,
+    pub triangles: Vec<Triangle>,
+    pub materials: Vec<Material>,
+    pub emitter_triangles: Vec<EmitterTriangle>,
+    pub emitter_total_area: f32,
+    pub lights: Vec<Light>,
+    // Light of rays leaving the scene, none is black
+    pub environment: Option<EnvironmentMap>
+}
+
+#[derive(Debug)]
+pub enum SceneError
+{
+    Load(tobj::LoadError),
+    InvalidParameter { material: String, parameter: String, value: String },
+    InvalidLight { line: usize, text: String },
+    Environment(image::ImageError)
+}
+
+impl fmt::Display for SceneError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self {
+            SceneError::Load(error) => write!(f, "failed to load scene: {}", error),
+            SceneError::InvalidParameter { material, parameter, value } =>
+                write!(f, "material '{}' has an invalid {} value '{}'", material, parameter, value),
+            SceneError::InvalidLight { line, text } => write!(f, "invalid light on line {}: '{}'", line, text),
+            SceneError::Environment(error) => write!(f, "failed to load environment map: {}", error)
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+impl From<tobj::LoadError> for SceneError
+{
+    fn from(error: tobj::LoadError) -> Self
+    {
+        SceneError::Load(error)
+    }
+}
+
+impl Scene {
+
+    pub fn load(path: &Path) -> Result<Scene, SceneError>
+    {
+        let (models, materials) = tobj::load_obj(path)?;
+        let mut scene = Self::from_models(&models, &materials)?;
+        let source = std::fs::read_to_string(path).map_err(|_| SceneError::Load(tobj::LoadError::ReadError))?;
+        scene.lights = parse_lights(&source)?;
+        Ok(scene)
+    }
+
+    pub fn from_models(models: &[tobj::Model], materials: &[tobj::Material]) -> Result<Scene, SceneError>
+    {
+        let mut material_data = Vec::new();
+        let mut material_emissive = Vec::new();
+        for material in materials {
+            let emissive = match material.unknown_param.get("Ke") {
+                Some(emissive_string) => Some(parse_float3(emissive_string).ok_or_else(|| SceneError::InvalidParameter {
+                    material: material.name.clone(), parameter: "Ke".to_string(), value: emissive_string.clone() })?),
+                None => None
+            };
+            if material.shininess < 0.0 {
+                return Err(SceneError::InvalidParameter { material: material.name.clone(), parameter: "Ns".to_string(),
+                    value: material.shininess.to_string() });
+            }
+            if material.optical_density <= 0.0 {
+                return Err(SceneError::InvalidParameter { material: material.name.clone(), parameter: "Ni".to_string(),
+                    value: material.optical_density.to_string() });
+            }
+            let material_type = material_type(material.illumination_model);
+            // The roughness of the PBR extension takes precedence over the Phong exponent. tobj reads a missing Ns
+            // as 0, which leaves the mirror of the reflective illumination models smooth.
+            let roughness = match material.unknown_param.get("Pr") {
+                Some(roughness_string) => match roughness_string.trim().parse::<f32>() {
+                    Ok(roughness) if (0.0..=1.0).contains(&roughness) => roughness * roughness,
+                    _ => return Err(SceneError::InvalidParameter { material: material.name.clone(), parameter: "Pr".to_string(),
+                        value: roughness_string.clone() })
+                },
+                None if material_type == MaterialType::Metal && material.shininess == 0.0 => 0.0,
+                None => shininess_to_roughness(material.shininess)
+            };
+            material_data.push(Material { material_type, diffuse: material.diffuse,
+                specular: material.specular, emissive: emissive.unwrap_or([0.0; 3]), roughness, ior: material.optical_density });
+            material_emissive.push(emissive);
+        }
+
+        // Meshes without a material are shaded with a default grey material
+        let default_material_index = material_data.len();
+        if models.iter().any(|model| model.mesh.material_id.is_none()) {
+            material_data.push(Material { diffuse: [0.8, 0.8, 0.8], ..Material::default() });
+            material_emissive.push(None);
+        }
+
+        let mut scene = Scene { vertices: Vec::new(), indices: Vec::new(), triangles: Vec::new(), materials: material_data,
+            emitter_triangles: Vec::new(), emitter_total_area: 0.0, lights: Vec::new(), environment: None };
+        for model in models {
+            let material_index = model.mesh.material_id.unwrap_or(default_material_index);
+            let vertex_offset = (scene.vertices.len() / 3) as u32;
+            let primitive_offset = scene.triangle_count();
+
+            scene.vertices.extend_from_slice(&model.mesh.positions);
+            scene.indices.extend(model.mesh.indices.iter().map(|i| vertex_offset + i));
+            scene.triangles.extend(std::iter::repeat_n(Triangle { material_index: material_index as u32 }, model.mesh.indices.len() / 3));
+
+            if let Some(emissive) = material_emissive[material_index] {
+                if emissive == [0.0; 3] {
+                    continue;
+                }
+                for primitive_index in primitive_offset..scene.triangle_count() {
+                    let [p0, p1, p2] = scene.triangle_vertices(primitive_index as u32);
+                    let area = 0.5 * (p1 - p0).cross(p2 - p0).magnitude();
+                    scene.emitter_total_area += area;
+                    scene.emitter_triangles.push(EmitterTriangle { primitive_index: primitive_index as u32, emissive, area });
+                }
+            }
+        }
+        Ok(scene)
+    }
+
+    pub fn triangle_count(&self) -> usize
+    {
+        self.indices.len() / 3
+    }
+
+    pub fn vertex(&self, index: u32) -> Vector3<f32>
+    {
+        let i = 3 * index as usize;
+        vec3(self.vertices[i], self.vertices[i + 1], self.vertices[i + 2])
+    }
+
+    pub fn triangle_vertices(&self, primitive_index: u32) -> [Vector3<f32>; 3]
+    {
+        let i = 3 * primitive_index as usize;
+        [self.vertex(self.indices[i]), self.vertex(self.indices[i + 1]), self.vertex(self.indices[i + 2])]
+    }
+}
+
+// Analytic lights from the 'light' lines of an OBJ file
+pub fn parse_lights(source: &str) -> Result<Vec<Light>, SceneError>
+{
+    let mut lights = Vec::new();
+    for (line_index, line) in source.lines().enumerate() {
+        let mut words = line.split_whitespace();
+        if words.next() != Some("light") {
+            continue;
+        }
+        let error = || SceneError::InvalidLight { line: line_index + 1, text: line.trim().to_string() };
+        let light_type = words.next().ok_or_else(error)?;
+        let values = words.map(|word| word.parse::<f32>()).collect::<Result<Vec<f32>, _>>().map_err(|_| error())?;
+        lights.push(parse_light(light_type, &values).ok_or_else(error)?);
+    }
+    Ok(lights)
+}
+
+fn parse_light(light_type: &str, values: &[f32]) -> Option<Light>
+{
+    if values.iter().any(|value| !value.is_finite()) {
+        return None;
+    }
+    let float3 = |offset: usize| [values[offset], values[offset + 1], values[offset + 2]];
+    let direction = |offset: usize| {
+        let direction = Vector3::from(float3(offset));
+        if direction.magnitude2() > 0.0 { Some(direction.normalize().into()) } else { None }
+    };
+    let cos_degrees = |angle: f32| if (0.0..=180.0).contains(&angle) { Some(angle.to_radians().cos()) } else { None };
+
+    let light = match (light_type, values.len()) {
+        ("point", 6) => Light { light_type: LightType::Point, position: float3(0), color: float3(3), ..Light::default() },
+        ("spot", 11) if values[6] <= values[7] => Light { light_type: LightType::Spot, position: float3(0), direction: direction(3)?,
+            cos_inner: cos_degrees(values[6])?, cos_outer: cos_degrees(values[7])?, color: float3(8), ..Light::default() },
+        ("directional", 7) if values[3] <= 90.0 => Light { light_type: LightType::Directional, direction: direction(0)?,
+            cos_outer: cos_degrees(values[3])?, color: float3(4), ..Light::default() },
+        ("sphere", 7) if values[3] > 0.0 => Light { light_type: LightType::Sphere, position: float3(0), radius: values[3], color: float3(4),
+            ..Light::default() },
+        _ => return None
+    };
+    if light.color.iter().any(|&c| c < 0.0) { None } else { Some(light) }
+}
+
+// Illumination models 3 and 5 are reflective and 4, 6, 7 and 9 refractive, see http://paulbourke.net/dataformats/mtl/
+fn material_type(illumination_model: Option<u8>) -> MaterialType
+{
+    match illumination_model {
+        Some(3) | Some(5) => MaterialType::Metal,
+        Some(4) | Some(6) | Some(7) | Some(9) => MaterialType::Dielectric,
+        _ => MaterialType::Diffuse
+    }
+}
+
+// Maps the Phong exponent to the roughness of a microfacet distribution with a similar highlight (Walter et al. 2007)
+fn shininess_to_roughness(shininess: f32) -> f32
+{
+    (2.0 / (shininess + 2.0)).sqrt()
+}
+
+fn parse_float3(val_str: &str) -> Option<[f32; 3]>
+{
+    let mut vals = [0.0f32; 3];
+    let mut words = val_str.split_whitespace();
+    for val in vals.iter_mut() {
+        *val = words.next()?.parse().ok()?;
+    }
+    match words.next() {
+        Some(_) => None,
+        None => Some(vals)
+    }
+}
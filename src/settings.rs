@@ -0,0 +1,28 @@
+
+use crate::types::{LightSampling, EmitterSelection, SamplerType};
+
+// Parameters of the light transport shared by the Metal and the CPU ray tracer
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RenderSettings
+{
+    pub seed: u64,
+    pub sampler: SamplerType,
+    // Maximum number of surface interactions along a path
+    pub max_depth: u32,
+    // Paths are terminated randomly based on their throughput from this bounce on
+    pub russian_roulette_depth: u32,
+    pub light_sampling: LightSampling,
+    pub emitter_selection: EmitterSelection,
+    // Pixels whose error relative to their brightness falls below this stop taking samples, see adaptive.rs. Zero
+    // takes the same number of samples in every pixel.
+    pub adaptive_threshold: f32
+}
+
+impl Default for RenderSettings
+{
+    fn default() -> Self
+    {
+        RenderSettings { seed: 0, sampler: SamplerType::Sobol, max_depth: 8, russian_roulette_depth: 3, light_sampling: LightSampling::MisPower,
+            emitter_selection: EmitterSelection::LightTree, adaptive_threshold: 0.0 }
+    }
+}
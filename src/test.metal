@@ -4,9 +4,11 @@
 using namespace metal;
 
 kernel void imageFillTest(texture2d<float, access::write> image [[texture(0)]],
-                     uint2 coordinates [[thread_position_in_grid]],
-                     uint2 size [[threads_per_grid]])
+                     constant uint2& size [[buffer(0)]],
+                     uint2 coordinates [[thread_position_in_grid]])
 {
-    float2 uv = float2(coordinates) / float2(size - 1);
+    if (coordinates.x >= size.x || coordinates.y >= size.y)
+        return;
+    float2 uv = float2(coordinates) / float2(max(size, uint2(2)) - 1);
     image.write(float4(uv, 0.0, 1.0), coordinates);
 }
\ No newline at end of file
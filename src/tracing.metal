@@ -5,14 +5,41 @@ using namespace metal;
 
 constant float PI = 3.1415926535897932384626433832795;
 constant float EPSILON = 0.000001;
-constant uint NOISE_BLOCK_SIZE = 16;
+constant float SURFACE_OFFSET = 0.0001;
+// Metals smoother than this are perfect mirrors, coats are clamped to it
+constant float MIN_ROUGHNESS = 0.001;
+constant uint CAMERA_RANDOM_LAYERS = 2;
+constant uint BOUNCE_RANDOM_LAYERS = 3;
+// Entry of the triangle emitter indices for triangles that do not emit
+constant uint NO_EMITTER = 0xFFFFFFFF;
+// Largest float below one
+constant float ONE_MINUS_EPSILON = 0x1.fffffep-1;
+// See adaptive.rs
+constant uint ADAPTIVE_MIN_SAMPLES = 16;
+constant float ADAPTIVE_MIN_LUMINANCE = 0.0001;
 
+// A path through a pixel, a negative maxDistance marks a terminated path
 struct Ray {
     packed_float3 origin;
     float minDistance;
     packed_float3 direction;
     float maxDistance;
     packed_float3 color;
+    packed_float3 throughput;
+    // Zero for camera rays and specular bounces, whose emission hits are not weighted against next event estimation
+    float bsdfPdf;
+    // Surface normal at the origin for the light tree density of hit emitters
+    packed_float3 normal;
+    // Index of the sample in the pixel's sequence
+    uint sampleIndex;
+};
+
+struct ShadowRay {
+    packed_float3 origin;
+    float minDistance;
+    packed_float3 direction;
+    float maxDistance;
+    packed_float3 color;
 };
 
 struct Intersection {
@@ -28,9 +55,28 @@ enum MaterialType
     Dielectric = 2
 };
 
+enum LightSampling
+{
+    EmitterSampling = 0,
+    BsdfSampling = 1,
+    MisBalance = 2,
+    MisPower = 3
+};
+
+enum EmitterSelection
+{
+    PowerSelection = 0,
+    LightTreeSelection = 1
+};
+
 struct Material
 {
+    MaterialType materialType;
     packed_float3 diffuse;
+    packed_float3 specular;
+    packed_float3 emissive;
+    float roughness;
+    float ior;
 };
 
 struct Triangle
@@ -38,6 +84,33 @@ struct Triangle
     uint materialIndex;
 };
 
+enum SamplerType
+{
+    IndependentSampler = 0,
+    SobolSampler = 1,
+    BlueNoiseSampler = 2
+};
+
+enum LightType
+{
+    PointLight = 0,
+    SpotLight = 1,
+    DirectionalLight = 2,
+    SphereLight = 3
+};
+
+// Analytic light from the scene file, see Light in types.rs for the meaning of the colour and the cone
+struct Light
+{
+    LightType lightType;
+    packed_float3 position;
+    packed_float3 direction;
+    float radius;
+    float cosInner;
+    float cosOuter;
+    packed_float3 color;
+};
+
 struct EmitterTriangle
 {
     uint primitiveIndex;
@@ -45,26 +118,371 @@ struct EmitterTriangle
     float area;
 };
 
+struct AliasEntry
+{
+    float probability;
+    uint alias;
+    float pdf;
+};
+
+// The first child of an interior node follows it, leaves have an emitterIndex
+struct LightTreeNode
+{
+    packed_float3 boundsMin;
+    float power;
+    packed_float3 boundsMax;
+    float cosThetaO;
+    packed_float3 axis;
+    float cosThetaE;
+    uint secondChild;
+    uint emitterIndex;
+};
+
+struct CameraData
+{
+    packed_float3 origin;
+    packed_float3 forward;
+    packed_float3 right;
+    packed_float3 up;
+    float lensRadius;
+    float focusDistance;
+};
+
 struct ApplicationData
 {
     uint frameIndex;
+    uint seed;
+    SamplerType samplerType;
     uint emitterTrianglesCount;
-    float emitterTotalArea;
+    uint maxDepth;
+    uint russianRouletteDepth;
+    LightSampling lightSampling;
+    EmitterSelection emitterSelection;
+    uint environmentWidth;
+    uint environmentHeight;
+    float environmentProbability;
+    uint lightsCount;
+    float lightProbability;
+    float adaptiveThreshold;
+    uint imageWidth;
+    uint imageHeight;
+};
+
+// Samples a pixel has taken so far, see adaptive.rs
+struct PixelStatistics
+{
+    float luminanceSquared;
+    uint sampleCount;
+    uint converged;
+};
+
+// Direction towards a light, a point on an emitter triangle or the environment
+struct LightSample
+{
+    float3 direction;
+    float distance;
+    // For delta lights the irradiance perpendicular to the direction
+    float3 radiance;
+    // Solid angle density, for delta lights only the probability of picking the light
+    float pdf;
+    // Point, spot and directional lights without an angular radius, which BSDF samples cannot hit
+    bool delta;
+    bool valid;
 };
 
-device const EmitterTriangle& sampleEmitterTriangle(device const EmitterTriangle* triangles, uint triangleCount, float totalArea, float xi)
+// Permuted congruential hash of four integers at once (Jarzynski and Olano 2020), see random.rs
+uint4 pcg4d(uint4 v)
+{
+    v = v * 1664525u + 1013904223u;
+    v.x += v.y * v.w;
+    v.y += v.z * v.x;
+    v.z += v.x * v.y;
+    v.w += v.y * v.z;
+    v ^= v >> 16u;
+    v.x += v.y * v.w;
+    v.y += v.z * v.x;
+    v.z += v.x * v.y;
+    v.w += v.y * v.z;
+    return v;
+}
+
+float toUnitFloat(uint x)
+{
+    return float(x >> 8u) * (1.0f / 16777216.0f);
+}
+
+// Three uniform random numbers in [0, 1) for a layer of three dimensions of a pixel's sample
+float3 random3(uint seed, uint2 coordinates, uint sampleIndex, uint layer)
+{
+    uint4 hash = pcg4d(uint4(coordinates, sampleIndex, layer ^ seed));
+    return float3(toUnitFloat(hash.x), toUnitFloat(hash.y), toUnitFloat(hash.z));
+}
+
+// Direction numbers of the second and third dimension of the Sobol sequence, see sampler.rs
+constant uint SOBOL_DIRECTIONS[2][32] = {
+    {0x80000000, 0xc0000000, 0xa0000000, 0xf0000000, 0x88000000, 0xcc000000, 0xaa000000, 0xff000000,
+     0x80800000, 0xc0c00000, 0xa0a00000, 0xf0f00000, 0x88880000, 0xcccc0000, 0xaaaa0000, 0xffff0000,
+     0x80008000, 0xc000c000, 0xa000a000, 0xf000f000, 0x88008800, 0xcc00cc00, 0xaa00aa00, 0xff00ff00,
+     0x80808080, 0xc0c0c0c0, 0xa0a0a0a0, 0xf0f0f0f0, 0x88888888, 0xcccccccc, 0xaaaaaaaa, 0xffffffff},
+    {0x80000000, 0xc0000000, 0x60000000, 0x90000000, 0xe8000000, 0x5c000000, 0x8e000000, 0xc5000000,
+     0x68800000, 0x9cc00000, 0xee600000, 0x55900000, 0x80680000, 0xc09c0000, 0x60ee0000, 0x90550000,
+     0xe8808000, 0x5cc0c000, 0x8e606000, 0xc5909000, 0x6868e800, 0x9c9c5c00, 0xeeee8e00, 0x5555c500,
+     0x8000e880, 0xc0005cc0, 0x60008e60, 0x9000c590, 0xe8006868, 0x5c009c9c, 0x8e00eeee, 0xc5005555}};
+constant uint SAMPLE_LATTICE[3] = {0xd1b54a33, 0xabc98389, 0x8cb92ba7};
+constant uint PIXEL_LATTICE[3][2] = {{0xc13fa9a9, 0x91e10da6}, {0x91e10da6, 0xc13fa9a9}, {0xc13fa9a9, 0x6e1ef25a}};
+constant uint BLUE_NOISE_TILE_SIZE = 8;
+
+uint sobol(uint index, uint dimension)
+{
+    uint result = 0;
+    for (uint bit = 0; index != 0; index >>= 1, bit++) {
+        if (index & 1) {
+            result ^= SOBOL_DIRECTIONS[dimension][bit];
+        }
+    }
+    return result;
+}
+
+// Owen scrambling by the hash of Laine and Karras 2011
+uint nestedUniformScramble(uint x, uint seed)
+{
+    x = reverse_bits(x);
+    x += seed;
+    x ^= x * 0x6c50b47c;
+    x ^= x * 0xb82f1e52;
+    x ^= x * 0xc7afe638;
+    x ^= x * 0x8d22f6e6;
+    return reverse_bits(x);
+}
+
+// SobolSampler in sampler.rs
+float3 sobolSample3(uint seed, uint2 coordinates, uint sampleIndex, uint layer)
+{
+    uint4 seeds = pcg4d(uint4(coordinates, layer, seed));
+    uint index = nestedUniformScramble(sampleIndex, seeds.x);
+    return float3(toUnitFloat(nestedUniformScramble(reverse_bits(index), seeds.y)),
+                  toUnitFloat(nestedUniformScramble(sobol(index, 0), seeds.z)),
+                  toUnitFloat(nestedUniformScramble(sobol(index, 1), seeds.w)));
+}
+
+// BlueNoiseSampler in sampler.rs
+float3 blueNoiseSample3(uint seed, uint2 coordinates, uint sampleIndex, uint layer)
+{
+    uint4 seeds = pcg4d(uint4(coordinates / BLUE_NOISE_TILE_SIZE, layer, seed));
+    uint index = nestedUniformScramble(sampleIndex, seeds.x);
+    float3 values;
+    for (uint dimension = 0; dimension < 3; dimension++) {
+        uint rotation = coordinates.x * PIXEL_LATTICE[dimension][0] + coordinates.y * PIXEL_LATTICE[dimension][1] + seeds[dimension + 1];
+        values[dimension] = toUnitFloat(index * SAMPLE_LATTICE[dimension] + rotation);
+    }
+    return values;
+}
+
+// Three numbers in [0, 1) for a layer of three dimensions of the pixel's sample from the sampler of the settings
+float3 sample3(device const ApplicationData& appData, uint2 coordinates, uint sampleIndex, uint layer)
+{
+    switch (appData.samplerType) {
+        case SobolSampler:
+            return sobolSample3(appData.seed, coordinates, sampleIndex, layer);
+        case BlueNoiseSampler:
+            return blueNoiseSample3(appData.seed, coordinates, sampleIndex, layer);
+        default:
+            return random3(appData.seed, coordinates, sampleIndex, layer);
+    }
+}
+
+// Index of the emitter triangle or analytic light picked by 'xi' from an alias table built by EmitterDistribution
+uint sampleAliasTable(device const AliasEntry* aliasTable, uint count, float xi)
+{
+    float scaled = xi * count;
+    uint column = min(uint(scaled), count - 1);
+    device const AliasEntry& entry = aliasTable[column];
+    return scaled - column < entry.probability ? column : entry.alias;
+}
+
+// cos(max(0, a - b))
+float cosSubClamped(float sinA, float cosA, float sinB, float cosB)
+{
+    return cosA > cosB ? 1.0 : cosA * cosB + sinA * sinB;
+}
+
+// sin(max(0, a - b))
+float sinSubClamped(float sinA, float cosA, float sinB, float cosB)
+{
+    return cosA > cosB ? 0.0 : sinA * cosB - cosA * sinB;
+}
+
+// Upper bound of the light the emitters below the node send towards the point, see light_tree.rs
+float lightTreeImportance(device const LightTreeNode& node, float3 point, float3 normal)
 {
-    float cfd = 0.0;
-    for (uint index = 0; index < triangleCount-1; index++)
+    if (node.power == 0.0)
+        return 0.0;
+
+    float3 boundsMin = node.boundsMin;
+    float3 boundsMax = node.boundsMax;
+    float3 center = (boundsMin + boundsMax) * 0.5;
+    float3 toPoint = point - center;
+    float distance2 = length_squared(toPoint);
+    float clampedDistance2 = max(distance2, 0.5 * length(boundsMax - boundsMin));
+
+    float radius2 = length_squared(boundsMax - center);
+    if (distance2 <= radius2)
+        return node.power / clampedDistance2;
+
+    float sinThetaB = sqrt(radius2 / distance2);
+    float cosThetaB = sqrt(1.0 - radius2 / distance2);
+
+    float3 wi = toPoint / sqrt(distance2);
+    float cosThetaW = dot(float3(node.axis), wi);
+    float sinThetaW = sqrt(max(1.0 - cosThetaW * cosThetaW, 0.0));
+    float sinThetaO = sqrt(max(1.0 - node.cosThetaO * node.cosThetaO, 0.0));
+    float cosThetaX = cosSubClamped(sinThetaW, cosThetaW, sinThetaO, node.cosThetaO);
+    float sinThetaX = sinSubClamped(sinThetaW, cosThetaW, sinThetaO, node.cosThetaO);
+    float cosTheta = cosSubClamped(sinThetaX, cosThetaX, sinThetaB, cosThetaB);
+    if (cosTheta <= node.cosThetaE)
+        return 0.0;
+
+    float cosThetaI = abs(dot(wi, normal));
+    float sinThetaI = sqrt(max(1.0 - cosThetaI * cosThetaI, 0.0));
+    float cosThetaIB = cosSubClamped(sinThetaI, cosThetaI, sinThetaB, cosThetaB);
+
+    return max(node.power * cosTheta * cosThetaIB / clampedDistance2, 0.0);
+}
+
+// Picks an emitter triangle by traversing the light tree, false if no emitter can light the point
+bool sampleLightTree(device const LightTreeNode* nodes, float3 point, float3 normal, float xi, thread uint& emitterIndex, thread float& pdf)
+{
+    float u = xi;
+    pdf = 1.0;
+    uint nodeIndex = 0;
+    while (true)
+    {
+        device const LightTreeNode& node = nodes[nodeIndex];
+        if (node.emitterIndex != NO_EMITTER)
+        {
+            emitterIndex = node.emitterIndex;
+            return lightTreeImportance(node, point, normal) > 0.0;
+        }
+
+        float first = lightTreeImportance(nodes[nodeIndex + 1], point, normal);
+        float second = lightTreeImportance(nodes[node.secondChild], point, normal);
+        if (first == 0.0 && second == 0.0)
+            return false;
+        float firstProbability = first / (first + second);
+        if (u < firstProbability)
+        {
+            u = min(u / firstProbability, ONE_MINUS_EPSILON);
+            pdf *= firstProbability;
+            nodeIndex += 1;
+        }
+        else
+        {
+            u = min((u - firstProbability) / (1.0 - firstProbability), ONE_MINUS_EPSILON);
+            pdf *= 1.0 - firstProbability;
+            nodeIndex = node.secondChild;
+        }
+    }
+}
+
+// Probability of sampleLightTree picking the emitter, following the branches recorded in its trail
+float lightTreePdf(device const LightTreeNode* nodes, uint trail, float3 point, float3 normal)
+{
+    float pdf = 1.0;
+    uint nodeIndex = 0;
+    while (true)
     {
-        float pdf = triangles[index].area / totalArea;
-        cfd += pdf;
-        if (xi < cfd)
+        device const LightTreeNode& node = nodes[nodeIndex];
+        if (node.emitterIndex != NO_EMITTER)
+            return lightTreeImportance(node, point, normal) > 0.0 ? pdf : 0.0;
+
+        float first = lightTreeImportance(nodes[nodeIndex + 1], point, normal);
+        float second = lightTreeImportance(nodes[node.secondChild], point, normal);
+        if (first == 0.0 && second == 0.0)
+            return 0.0;
+        float firstProbability = first / (first + second);
+        if ((trail & 1) == 0)
         {
-            return triangles[index];
+            pdf *= firstProbability;
+            nodeIndex += 1;
         }
+        else
+        {
+            pdf *= 1.0 - firstProbability;
+            nodeIndex = node.secondChild;
+        }
+        trail >>= 1;
+    }
+}
+
+// Equirectangular image coordinates with v from the top, which is straight up along +y
+float2 directionToUv(float3 direction)
+{
+    float phi = atan2(direction.x, -direction.z);
+    float theta = acos(clamp(direction.y, -1.0, 1.0));
+    return float2(0.5 + phi / (2.0 * PI), theta / PI);
+}
+
+float3 uvToDirection(float2 uv)
+{
+    float phi = 2.0 * PI * (uv.x - 0.5);
+    float theta = PI * uv.y;
+    return float3(sin(theta) * sin(phi), cos(theta), -sin(theta) * cos(phi));
+}
+
+uint environmentPixel(float3 direction, uint width, uint height)
+{
+    float2 uv = directionToUv(direction);
+    uint column = min(uint(uv.x * width), width - 1);
+    uint row = min(uint(uv.y * height), height - 1);
+    return column + row * width;
+}
+
+// Last of the 'count' intervals of the cumulative distribution starting at or below u
+uint findInterval(device const float* cdf, uint count, float u)
+{
+    uint first = 0;
+    uint last = count - 1;
+    while (first < last)
+    {
+        uint middle = (first + last + 1) / 2;
+        if (cdf[middle] <= u)
+            first = middle;
+        else
+            last = middle - 1;
     }
-    return triangles[triangleCount-1];
+    return first;
+}
+
+float intervalOffset(device const float* cdf, uint index, float u)
+{
+    float width = cdf[index + 1] - cdf[index];
+    return width > 0.0 ? (u - cdf[index]) / width : 0.0;
+}
+
+// Solid angle density of sampleEnvironment, see environment.rs
+float environmentPdf(device const float* pixelPdfs, uint width, uint height, float3 direction)
+{
+    float sinTheta = sqrt(max(1.0 - direction.y * direction.y, 0.0));
+    if (sinTheta == 0.0)
+        return 0.0;
+    return pixelPdfs[environmentPixel(direction, width, height)] / (2.0 * PI * PI * sinTheta);
+}
+
+bool sampleEnvironment(device const float* pixelPdfs, device const float* conditionalCdfs, device const float* marginalCdf,
+                       uint width, uint height, float2 u, thread float3& direction, thread float& pdf)
+{
+    uint row = findInterval(marginalCdf, height, u.y);
+    float v = (row + intervalOffset(marginalCdf, row, u.y)) / height;
+    device const float* conditionalCdf = conditionalCdfs + row * (width + 1);
+    uint column = findInterval(conditionalCdf, width, u.x);
+    float uu = (column + intervalOffset(conditionalCdf, column, u.x)) / width;
+
+    direction = uvToDirection(float2(uu, v));
+    float sinTheta = sqrt(max(1.0 - direction.y * direction.y, 0.0));
+    if (sinTheta == 0.0)
+        return false;
+    pdf = pixelPdfs[column + row * width] / (2.0 * PI * PI * sinTheta);
+    return pdf > 0.0;
 }
 
 float3 barycentric(float2 smp)
@@ -74,28 +492,472 @@ float3 barycentric(float2 smp)
     return float3(1.0f - r1, r1 * (1.0f - r2), r1 * r2);
 }
 
-kernel void generateRays(device Ray* rays [[buffer(0)]],
-                         device const packed_float3* noise [[buffer(1)]],
-                         uint2 coordinates [[thread_position_in_grid]],
-                         uint2 size [[threads_per_grid]])
+// Solid angle density of a point sampled uniformly by area on an emitter, see emitters::point_sample_pdf
+float pointSamplePdf(float area, float distance, float cosTheta)
+{
+    return distance * distance / (area * cosTheta);
+}
+
+// Orthonormal basis around a unit vector (Duff et al. 2017)
+void orthonormalBasis(float3 n, thread float3& tangent, thread float3& bitangent)
+{
+    float sign = n.z >= 0.0f ? 1.0f : -1.0f;
+    float a = -1.0f / (sign + n.z);
+    float b = n.x * n.y * a;
+    tangent = float3(1.0f + sign * n.x * n.x * a, sign * b, -sign * n.x);
+    bitangent = float3(b, sign + n.y * n.y * a, -n.y);
+}
+
+float3 sampleCosineHemisphere(float3 normal, float2 smp)
+{
+    float r = sqrt(smp.x);
+    float phi = 2.0f * PI * smp.y;
+    float3 tangent, bitangent;
+    orthonormalBasis(normal, tangent, bitangent);
+    return normalize(r * cos(phi) * tangent + r * sin(phi) * bitangent + sqrt(max(0.0f, 1.0f - smp.x)) * normal);
+}
+
+// The analytic light functions below are the twins of the ones in lights.rs
+float conePdf(float oneMinusCosThetaMax)
+{
+    return 1.0f / (2.0f * PI * oneMinusCosThetaMax);
+}
+
+// Uniform direction within the cone around the unit axis
+float3 sampleCone(float3 axis, float oneMinusCosThetaMax, float2 u)
+{
+    float oneMinusCosTheta = u.x * oneMinusCosThetaMax;
+    float cosTheta = 1.0f - oneMinusCosTheta;
+    float sinTheta = sqrt(max(oneMinusCosTheta * (2.0f - oneMinusCosTheta), 0.0f));
+    float phi = 2.0f * PI * u.y;
+    float3 tangent, bitangent;
+    orthonormalBasis(axis, tangent, bitangent);
+    return normalize(sinTheta * cos(phi) * tangent + sinTheta * sin(phi) * bitangent + cosTheta * axis);
+}
+
+// One minus the cosine of the half angle of the cone the sphere covers seen from the point, negative from inside
+float sphereCone(device const Light& light, float3 point)
+{
+    float distance2 = length_squared(float3(light.position) - point);
+    float radius2 = light.radius * light.radius;
+    if (distance2 <= radius2)
+        return -1.0f;
+    float sin2ThetaMax = radius2 / distance2;
+    return sin2ThetaMax / (1.0f + sqrt(1.0f - sin2ThetaMax));
+}
+
+// Smooth transition from the outer to the inner cone, direction points away from the light
+float spotFalloff(device const Light& light, float3 direction)
+{
+    float cosTheta = dot(direction, float3(light.direction));
+    if (light.cosInner <= light.cosOuter)
+        return cosTheta >= light.cosOuter ? 1.0f : 0.0f;
+    float t = clamp((cosTheta - light.cosOuter) / (light.cosInner - light.cosOuter), 0.0f, 1.0f);
+    return t * t * (3.0f - 2.0f * t);
+}
+
+// Distance along the ray to the surface of a sphere light, negative if it misses the sphere or starts inside it
+float intersectSphereLight(device const Light& light, float3 origin, float3 direction)
+{
+    if (light.lightType != SphereLight)
+        return -1.0f;
+    float3 toOrigin = origin - float3(light.position);
+    float b = dot(toOrigin, direction);
+    float c = length_squared(toOrigin) - light.radius * light.radius;
+    float discriminant = b * b - c;
+    if (c <= 0.0f || b >= 0.0f || discriminant < 0.0f)
+        return -1.0f;
+    return -b - sqrt(discriminant);
+}
+
+// Direction from the point towards the light, not valid if the light does not reach it
+LightSample sampleLight(device const Light& light, float3 point, float2 u)
+{
+    LightSample result;
+    result.valid = true;
+    result.delta = false;
+    float3 position = light.position;
+    float3 color = light.color;
+    switch (light.lightType)
+    {
+        case PointLight:
+        case SpotLight:
+        {
+            float3 toLight = position - point;
+            float lightDistance = length(toLight);
+            result.direction = toLight / lightDistance;
+            float falloff = light.lightType == SpotLight ? spotFalloff(light, -result.direction) : 1.0f;
+            result.valid = lightDistance > 0.0f && falloff > 0.0f;
+            result.distance = lightDistance - SURFACE_OFFSET;
+            result.radiance = color * (falloff / (lightDistance * lightDistance));
+            result.pdf = 1.0f;
+            result.delta = true;
+            break;
+        }
+        case DirectionalLight:
+        {
+            float3 axis = -float3(light.direction);
+            result.distance = INFINITY;
+            if (light.cosOuter >= 1.0f)
+            {
+                result.direction = axis;
+                result.radiance = color;
+                result.pdf = 1.0f;
+                result.delta = true;
+                break;
+            }
+            // The irradiance is spread evenly over the cone
+            result.direction = sampleCone(axis, 1.0f - light.cosOuter, u);
+            result.pdf = conePdf(1.0f - light.cosOuter);
+            result.radiance = color * result.pdf;
+            break;
+        }
+        case SphereLight:
+        {
+            float oneMinusCosThetaMax = sphereCone(light, point);
+            if (oneMinusCosThetaMax < 0.0f)
+            {
+                result.valid = false;
+                break;
+            }
+            result.direction = sampleCone(normalize(position - point), oneMinusCosThetaMax, u);
+            // Directions on the edge of the cone may miss the sphere by rounding errors, they touch it at the tangent point
+            float lightDistance = intersectSphereLight(light, point, result.direction);
+            if (lightDistance < 0.0f)
+                lightDistance = sqrt(length_squared(position - point) - light.radius * light.radius);
+            result.distance = lightDistance - SURFACE_OFFSET;
+            result.radiance = color;
+            result.pdf = conePdf(oneMinusCosThetaMax);
+            break;
+        }
+    }
+    return result;
+}
+
+// Solid angle density of sampleLight returning a direction that hits the light, zero for delta lights
+float lightPdf(device const Light& light, float3 point)
+{
+    if (light.lightType == DirectionalLight && light.cosOuter < 1.0f)
+        return conePdf(1.0f - light.cosOuter);
+    if (light.lightType == SphereLight)
+    {
+        float oneMinusCosThetaMax = sphereCone(light, point);
+        return oneMinusCosThetaMax < 0.0f ? 0.0f : conePdf(oneMinusCosThetaMax);
+    }
+    return 0.0f;
+}
+
+// Radiance of a directional light with an angular radius seen along the direction, false outside its cone
+bool directionalRadiance(device const Light& light, float3 direction, thread float3& radiance)
+{
+    if (light.lightType != DirectionalLight || light.cosOuter >= 1.0f || -dot(direction, float3(light.direction)) < light.cosOuter)
+        return false;
+    radiance = float3(light.color) * conePdf(1.0f - light.cosOuter);
+    return true;
+}
+
+// Schlick's approximation for a conductor with the given reflectance at normal incidence
+float3 fresnelSchlick(float3 f0, float cosTheta)
+{
+    return f0 + (1.0f - f0) * pow(max(0.0f, 1.0f - cosTheta), 5.0f);
+}
+
+// Unpolarized Fresnel reflectance of a dielectric interface, one on total internal reflection
+float fresnelDielectric(float cosThetaI, float etaI, float etaT)
+{
+    float sinThetaT = etaI / etaT * sqrt(max(0.0f, 1.0f - cosThetaI * cosThetaI));
+    if (sinThetaT >= 1.0f)
+        return 1.0f;
+    float cosThetaT = sqrt(max(0.0f, 1.0f - sinThetaT * sinThetaT));
+    float parallel = (etaT * cosThetaI - etaI * cosThetaT) / (etaT * cosThetaI + etaI * cosThetaT);
+    float perpendicular = (etaI * cosThetaI - etaT * cosThetaT) / (etaI * cosThetaI + etaT * cosThetaT);
+    return 0.5f * (parallel * parallel + perpendicular * perpendicular);
+}
+
+float ggxDistribution(float cosThetaH, float alpha)
+{
+    if (cosThetaH <= 0.0f)
+        return 0.0f;
+    float alpha2 = alpha * alpha;
+    float d = cosThetaH * cosThetaH * (alpha2 - 1.0f) + 1.0f;
+    return alpha2 / (PI * d * d);
+}
+
+float smithLambda(float cosTheta, float alpha)
+{
+    float cos2 = cosTheta * cosTheta;
+    float tan2 = max(0.0f, 1.0f - cos2) / cos2;
+    return 0.5f * (sqrt(1.0f + alpha * alpha * tan2) - 1.0f);
+}
+
+float smithG1(float cosTheta, float alpha)
+{
+    return 1.0f / (1.0f + smithLambda(cosTheta, alpha));
+}
+
+// Height correlated masking and shadowing
+float smithG2(float cosThetaO, float cosThetaI, float alpha)
+{
+    return 1.0f / (1.0f + smithLambda(cosThetaO, alpha) + smithLambda(cosThetaI, alpha));
+}
+
+// Microfacet normal distributed proportional to its visible projected area seen from wo (Heitz 2018)
+float3 sampleGgxVisibleNormal(float alpha, float3 normal, float3 wo, float2 u)
+{
+    float3 tangent, bitangent;
+    orthonormalBasis(normal, tangent, bitangent);
+    float3 localWo = float3(dot(wo, tangent), dot(wo, bitangent), dot(wo, normal));
+
+    // Stretch to the hemisphere configuration
+    float3 vh = normalize(float3(alpha * localWo.x, alpha * localWo.y, localWo.z));
+    float length2 = vh.x * vh.x + vh.y * vh.y;
+    float3 t1 = length2 > 0.0f ? float3(-vh.y, vh.x, 0.0f) / sqrt(length2) : float3(1.0f, 0.0f, 0.0f);
+    float3 t2 = cross(vh, t1);
+
+    // Uniform point on the disk, warped to the projected visible half of the hemisphere
+    float r = sqrt(u.x);
+    float phi = 2.0f * PI * u.y;
+    float p1 = r * cos(phi);
+    float s = 0.5f * (1.0f + vh.z);
+    float p2 = (1.0f - s) * sqrt(max(0.0f, 1.0f - p1 * p1)) + s * r * sin(phi);
+    float3 nh = p1 * t1 + p2 * t2 + sqrt(max(0.0f, 1.0f - p1 * p1 - p2 * p2)) * vh;
+
+    // Unstretch
+    float3 localH = normalize(float3(alpha * nh.x, alpha * nh.y, max(0.0f, nh.z)));
+    return normalize(localH.x * tangent + localH.y * bitangent + localH.z * normal);
+}
+
+float3 microfacetReflection(float3 f0, float alpha, float3 normal, float3 wo, float3 wi)
+{
+    float3 halfVector = normalize(wo + wi);
+    float cosThetaO = dot(wo, normal);
+    float cosThetaI = dot(wi, normal);
+    float distribution = ggxDistribution(dot(halfVector, normal), alpha);
+    float masking = smithG2(cosThetaO, cosThetaI, alpha);
+    return fresnelSchlick(f0, dot(wi, halfVector)) * (distribution * masking / (4.0f * cosThetaO * cosThetaI));
+}
+
+// Density of reflecting wo about a visible normal, the Jacobian of the reflection cancels the half vector cosine
+float microfacetReflectionPdf(float alpha, float3 normal, float3 wo, float3 wi)
 {
-    const float3 origin = float3(0.0f, 1.0f, 2.1f);
+    float3 halfVector = normalize(wo + wi);
+    float cosThetaO = dot(wo, normal);
+    return smithG1(cosThetaO, alpha) * ggxDistribution(dot(halfVector, normal), alpha) / (4.0f * cosThetaO);
+}
+
+// The BSDF functions below are the twins of the ones in bsdf.rs. Directions point away from the surface and
+// normal is the geometric normal of the triangle. Diffuse materials are Lambertian with an optional glossy coat
+// given by the specular colour, metals are conductors and both use a GGX microfacet distribution.
+struct BsdfSample
+{
+    float3 direction;
+    // BSDF times cosine divided by the pdf
+    float3 weight;
+    // Solid angle density of the direction, for specular samples the probability of the chosen event
+    float pdf;
+    bool specular;
+    // False if the sampled direction is absorbed
+    bool valid;
+};
+
+// True if the material only scatters into discrete directions which next event estimation cannot hit
+bool isSpecular(device const Material& material)
+{
+    switch (material.materialType)
+    {
+        case Metal:
+            return material.roughness < MIN_ROUGHNESS;
+        case Dielectric:
+            return true;
+        default:
+            return false;
+    }
+}
+
+// Probability of sampling the glossy coat of a diffuse material rather than the diffuse base
+float coatProbability(device const Material& material)
+{
+    float specular = max(material.specular[0], max(material.specular[1], material.specular[2]));
+    float diffuse = (1.0f - specular) * max(material.diffuse[0], max(material.diffuse[1], material.diffuse[2]));
+    return specular + diffuse > 0.0f ? specular / (specular + diffuse) : 0.0f;
+}
+
+// BSDF value for light arriving from wi and leaving towards wo, zero for specular materials
+float3 evaluateBsdf(device const Material& material, float3 normal, float3 wo, float3 wi)
+{
+    if (dot(wo, normal) < 0.0f)
+        normal = -normal;
+    float cosThetaO = dot(wo, normal);
+    float cosThetaI = dot(wi, normal);
+    if (isSpecular(material) || cosThetaO <= 0.0f || cosThetaI <= 0.0f)
+        return float3(0.0f);
+
+    float3 specular = material.specular;
+    if (material.materialType == Metal)
+        return microfacetReflection(specular, material.roughness, normal, wo, wi);
+
+    float3 diffuse = float3(material.diffuse) / PI;
+    if (all(specular == 0.0f))
+        return diffuse;
+    // Light reflected by the coat on the way in or out does not reach the diffuse base
+    float3 transmission = (1.0f - fresnelSchlick(specular, cosThetaO)) * (1.0f - fresnelSchlick(specular, cosThetaI));
+    return diffuse * transmission + microfacetReflection(specular, max(material.roughness, MIN_ROUGHNESS), normal, wo, wi);
+}
+
+// Solid angle density with which sampleBsdf chooses wi, zero for specular materials
+float bsdfPdf(device const Material& material, float3 normal, float3 wo, float3 wi)
+{
+    if (dot(wo, normal) < 0.0f)
+        normal = -normal;
+    float cosThetaI = dot(wi, normal);
+    if (isSpecular(material) || dot(wo, normal) <= 0.0f || cosThetaI <= 0.0f)
+        return 0.0f;
+
+    if (material.materialType == Metal)
+        return microfacetReflectionPdf(material.roughness, normal, wo, wi);
 
-    uint noiseSampleIndex = (coordinates.x % NOISE_BLOCK_SIZE) + NOISE_BLOCK_SIZE * (coordinates.y % NOISE_BLOCK_SIZE);
+    float probability = coatProbability(material);
+    float pdf = (1.0f - probability) * cosThetaI / PI;
+    if (probability > 0.0f)
+        pdf += probability * microfacetReflectionPdf(max(material.roughness, MIN_ROUGHNESS), normal, wo, wi);
+    return pdf;
+}
+
+// Samples an incoming direction with u.xy and chooses between the lobes with u.z
+BsdfSample sampleBsdf(device const Material& material, float3 normal, float3 wo, float3 u)
+{
+    BsdfSample result;
+    result.weight = float3(1.0f);
+    result.specular = true;
+    result.valid = true;
+
+    if (material.materialType == Dielectric)
+    {
+        // Smooth glass, chooses between reflection and refraction proportional to the Fresnel reflectance
+        // which then cancels in the weight
+        bool entering = dot(wo, normal) > 0.0f;
+        normal = entering ? normal : -normal;
+        float etaI = entering ? 1.0f : material.ior;
+        float etaT = entering ? material.ior : 1.0f;
+        float reflectance = fresnelDielectric(dot(wo, normal), etaI, etaT);
+        if (u.z < reflectance)
+        {
+            result.direction = reflect(-wo, normal);
+            result.pdf = reflectance;
+        }
+        else
+        {
+            result.direction = refract(-wo, normal, etaI / etaT);
+            result.pdf = 1.0f - reflectance;
+        }
+        return result;
+    }
 
-    device const packed_float3& noiseSample = noise[noiseSampleIndex];
-    float2 rnd = (noiseSample.xy * 2.0 - 1.0) / float2(size - 1);
+    if (dot(wo, normal) < 0.0f)
+        normal = -normal;
+    float cosThetaO = dot(wo, normal);
+    if (cosThetaO <= 0.0f)
+    {
+        result.valid = false;
+        return result;
+    }
+
+    if (isSpecular(material))
+    {
+        result.direction = reflect(-wo, normal);
+        result.weight = fresnelSchlick(material.specular, cosThetaO);
+        result.pdf = 1.0f;
+        return result;
+    }
 
-    float aspect = float(size.x) / float(size.y);
-    float2 uv = float2(coordinates) / float2(size - 1) * 2.0f - 1.0f;
+    if (material.materialType == Metal)
+        result.direction = reflect(-wo, sampleGgxVisibleNormal(material.roughness, normal, wo, u.xy));
+    else if (u.z < coatProbability(material))
+        result.direction = reflect(-wo, sampleGgxVisibleNormal(max(material.roughness, MIN_ROUGHNESS), normal, wo, u.xy));
+    else
+        result.direction = sampleCosineHemisphere(normal, u.xy);
 
-    float3 direction = float3(aspect * (uv.x + rnd.x), (uv.y + rnd.y), -1.0f);
+    float cosThetaI = dot(result.direction, normal);
+    result.pdf = bsdfPdf(material, normal, wo, result.direction);
+    result.specular = false;
+    result.valid = cosThetaI > 0.0f && result.pdf > 0.0f;
+    if (result.valid)
+        result.weight = evaluateBsdf(material, normal, wo, result.direction) * (cosThetaI / result.pdf);
+    return result;
+}
 
+// Weight of a sample with density pdf combined with a strategy of density otherPdf
+float misWeight(float pdf, float otherPdf, LightSampling lightSampling)
+{
+    if (lightSampling == MisPower)
+        return pdf * pdf / (pdf * pdf + otherPdf * otherPdf);
+    return pdf / (pdf + otherPdf);
+}
+
+// Weight of emission hit by a BSDF sampled ray against next event estimation sampling it with density lightPdf
+float emissionWeight(float bsdfPdf, float lightPdf, LightSampling lightSampling)
+{
+    if (bsdfPdf == 0.0)
+        return 1.0;
+    switch (lightSampling)
+    {
+        case EmitterSampling:
+            return 0.0;
+        case BsdfSampling:
+            return 1.0;
+        default:
+            return misWeight(bsdfPdf, lightPdf, lightSampling);
+    }
+}
+
+// The grid of threads is rounded up to whole thread groups, threads past the right and bottom edges of the image
+// return right away
+uint2 imageSize(device const ApplicationData& appData)
+{
+    return uint2(appData.imageWidth, appData.imageHeight);
+}
+
+kernel void generateRays(device Ray* rays [[buffer(0)]],
+                         device const ApplicationData& appData [[buffer(1)]],
+                         device const CameraData& camera [[buffer(2)]],
+                         device const PixelStatistics* statistics [[buffer(3)]],
+                         uint2 coordinates [[thread_position_in_grid]])
+{
+    uint2 size = imageSize(appData);
+    if (coordinates.x >= size.x || coordinates.y >= size.y)
+        return;
     uint rayIndex = coordinates.x + coordinates.y * size.x;
+
+    // Converged pixels only start a path again when the render restarts
+    if (appData.frameIndex > 0 && statistics[rayIndex].converged != 0)
+    {
+        rays[rayIndex].maxDistance = -1.0;
+        rays[rayIndex].color = float3(0.0);
+        return;
+    }
+    uint sampleIndex = appData.frameIndex > 0 ? statistics[rayIndex].sampleCount : 0;
+
+    float3 jitter = sample3(appData, coordinates, sampleIndex, 0);
+    float2 uv = (float2(coordinates) + jitter.xy) / float2(size) * 2.0f - 1.0f;
+
+    float3 direction = camera.forward + uv.x * camera.right + uv.y * camera.up;
+
+    // Thin lens
+    float3 lensSample = sample3(appData, coordinates, sampleIndex, 1);
+    float3 focusPoint = camera.origin + camera.focusDistance * direction;
+    float radius = camera.lensRadius * sqrt(lensSample.x);
+    float angle = 2.0 * PI * lensSample.y;
+    float3 origin = camera.origin + radius * cos(angle) * normalize(float3(camera.right)) + radius * sin(angle) * normalize(float3(camera.up));
+
     rays[rayIndex].origin = origin;
-    rays[rayIndex].direction = normalize(direction);
+    rays[rayIndex].direction = normalize(focusPoint - origin);
     rays[rayIndex].minDistance = EPSILON;
     rays[rayIndex].maxDistance = INFINITY;
+    rays[rayIndex].color = float3(0.0);
+    rays[rayIndex].throughput = float3(1.0);
+    rays[rayIndex].bsdfPdf = 0.0;
+    rays[rayIndex].normal = float3(0.0);
+    rays[rayIndex].sampleIndex = sampleIndex;
 }
 
 kernel void handleIntersections(device const Intersection* intersections [[buffer(0)]],
@@ -106,14 +968,75 @@ kernel void handleIntersections(device const Intersection* intersections [[buffe
                                 device const packed_uint3* indices [[buffer(5)]],
                                 device const EmitterTriangle* emitterTriangles [[buffer(6)]],
                                 device const ApplicationData& appData [[buffer(7)]],
-                                device const packed_float3* noise [[buffer(8)]],
-                                uint2 coordinates [[thread_position_in_grid]],
-                                uint2 size [[threads_per_grid]])
+                                device ShadowRay* shadowRays [[buffer(8)]],
+                                constant uint& bounce [[buffer(9)]],
+                                device const AliasEntry* aliasTable [[buffer(10)]],
+                                device const uint* triangleEmitters [[buffer(11)]],
+                                device const LightTreeNode* lightTree [[buffer(12)]],
+                                device const uint* emitterTrails [[buffer(13)]],
+                                device const packed_float3* environment [[buffer(14)]],
+                                device const float* environmentPixelPdfs [[buffer(15)]],
+                                device const float* environmentConditionalCdfs [[buffer(16)]],
+                                device const float* environmentMarginalCdf [[buffer(17)]],
+                                device const Light* lights [[buffer(18)]],
+                                device const AliasEntry* lightTable [[buffer(19)]],
+                                uint2 coordinates [[thread_position_in_grid]])
 {
+    uint2 size = imageSize(appData);
+    if (coordinates.x >= size.x || coordinates.y >= size.y)
+        return;
     uint rayIndex = coordinates.x + coordinates.y * size.x;
+    device Ray& ray = rays[rayIndex];
+    device ShadowRay& shadowRay = shadowRays[rayIndex];
+    shadowRay.maxDistance = -1.0;
+
     device const Intersection& intersection = intersections[rayIndex];
-    if (intersection.distance < EPSILON)
+    if (ray.maxDistance < 0.0)
+        return;
+
+    // Sphere lights are not part of the acceleration structure, rays are tested against all of them
+    float3 rayOrigin = ray.origin;
+    float3 direction = ray.direction;
+    float sphereDistance = intersection.distance < 0.0 ? INFINITY : intersection.distance;
+    int sphereLight = -1;
+    for (uint i = 0; i < appData.lightsCount; i++)
+    {
+        float lightDistance = intersectSphereLight(lights[i], rayOrigin, direction);
+        if (lightDistance >= 0.0 && lightDistance < sphereDistance)
+        {
+            sphereDistance = lightDistance;
+            sphereLight = i;
+        }
+    }
+    if (sphereLight >= 0)
+    {
+        device const Light& light = lights[sphereLight];
+        float lightSamplePdf = appData.lightProbability * lightTable[sphereLight].pdf * lightPdf(light, rayOrigin);
+        ray.color = float3(ray.color) + float3(ray.throughput) * float3(light.color) * emissionWeight(ray.bsdfPdf, lightSamplePdf, appData.lightSampling);
+        ray.maxDistance = -1.0;
+        return;
+    }
+
+    // Light from the environment and the directional lights, weighted like emitters hit by BSDF sampling. Without
+    // an environment map a single black pixel is bound.
+    if (intersection.distance < 0.0)
+    {
+        float environmentSamplePdf = appData.environmentProbability * environmentPdf(environmentPixelPdfs, appData.environmentWidth, appData.environmentHeight, direction);
+        float3 radiance = environment[environmentPixel(direction, appData.environmentWidth, appData.environmentHeight)]
+            * emissionWeight(ray.bsdfPdf, environmentSamplePdf, appData.lightSampling);
+        for (uint i = 0; i < appData.lightsCount; i++)
+        {
+            float3 lightRadiance;
+            if (directionalRadiance(lights[i], direction, lightRadiance))
+            {
+                float lightSamplePdf = appData.lightProbability * lightTable[i].pdf * lightPdf(lights[i], rayOrigin);
+                radiance += lightRadiance * emissionWeight(ray.bsdfPdf, lightSamplePdf, appData.lightSampling);
+            }
+        }
+        ray.color = float3(ray.color) + float3(ray.throughput) * radiance;
+        ray.maxDistance = -1.0;
         return;
+    }
 
     device const Triangle& triangle = triangles[intersection.primitiveIndex];
     device const Material& material = materials[triangle.materialIndex];
@@ -125,69 +1048,230 @@ kernel void handleIntersections(device const Intersection* intersections [[buffe
     device const packed_float3& c = vertices[triangleIndices.z];
     float3 intersection_point = intersection.coordinates.x * a + intersection.coordinates.y * b + (1.0 - intersection.coordinates.x - intersection.coordinates.y) * c;
 
-    // Find normal
-    float3 normal = normalize(cross(b-a, c-a));
-
-    // Sample light
-    uint noiseSampleIndex = (coordinates.x % NOISE_BLOCK_SIZE) + NOISE_BLOCK_SIZE * (coordinates.y % NOISE_BLOCK_SIZE);
-    device const packed_float3& noiseSample = noise[noiseSampleIndex];
-    device const EmitterTriangle& emitterTriangle = sampleEmitterTriangle(emitterTriangles, appData.emitterTrianglesCount, appData.emitterTotalArea, noiseSample.x);
-
-    // Light attributes
-    float3 lightTriangleBarycentric = barycentric(noiseSample.yz);
-    device const packed_uint3& lightTriangleIndices = indices[emitterTriangle.primitiveIndex];
-    device const packed_float3& d = vertices[lightTriangleIndices.x];
-    device const packed_float3& e = vertices[lightTriangleIndices.y];
-    device const packed_float3& f = vertices[lightTriangleIndices.z];
-    float3 light_position = lightTriangleBarycentric.x * d + lightTriangleBarycentric.y * e + lightTriangleBarycentric.z * f;
-    float3 light_normal = normalize(cross(e-d, f-d));
-    float light_pdf = emitterTriangle.area / appData.emitterTotalArea;
-    float3 light_dir = light_position - intersection_point;
-    float light_dist = length(light_dir);
-    light_dir /= light_dist;
-
-    // Find color
-    float materialBsdf = (1.0 / PI) * dot(light_dir, normal);
-    float cosTheta = -dot(light_dir, light_normal);
-    float pointSamplePdf = (light_dist * light_dist) / (emitterTriangle.area * cosTheta);
-    float lightSamplePdf = light_pdf * pointSamplePdf;
-    rays[rayIndex].color = emitterTriangle.emissive * material.diffuse * (materialBsdf / lightSamplePdf);
-
-    // Set shadow ray
-    rays[rayIndex].origin = intersection_point;
-    rays[rayIndex].direction = light_dir;
-    rays[rayIndex].minDistance = EPSILON;
-    rays[rayIndex].maxDistance = light_dist - EPSILON;
+    // Find normal, emitters only emit on the side the geometric normal points to
+    float3 geometricNormal = normalize(cross(b-a, c-a));
+    float3 wo = -float3(ray.direction);
+    float3 throughput = ray.throughput;
+    bool frontFacing = dot(geometricNormal, wo) > 0.0;
+    float3 normal = frontFacing ? geometricNormal : -geometricNormal;
+
+    // Emission hit by BSDF sampling, weighted against the chance of next event estimation sampling the same point
+    if (frontFacing && any(float3(material.emissive) != 0.0))
+    {
+        float weight = 1.0;
+        if (ray.bsdfPdf != 0.0)
+        {
+            switch (appData.lightSampling)
+            {
+                case EmitterSampling:
+                    weight = 0.0;
+                    break;
+                case BsdfSampling:
+                    weight = 1.0;
+                    break;
+                default:
+                {
+                    // Emitter points are sampled uniformly by area within the picked triangle
+                    uint emitterIndex = triangleEmitters[intersection.primitiveIndex];
+                    float emitterArea = 0.5 * length(cross(b-a, c-a));
+                    float lightPdf = 0.0;
+                    if (emitterIndex != NO_EMITTER)
+                        lightPdf = appData.emitterSelection == LightTreeSelection
+                            ? lightTreePdf(lightTree, emitterTrails[emitterIndex], ray.origin, ray.normal)
+                            : aliasTable[emitterIndex].pdf;
+                    float triangleProbability = 1.0 - appData.environmentProbability - appData.lightProbability;
+                    float lightSamplePdf = triangleProbability * lightPdf * pointSamplePdf(emitterArea, intersection.distance, dot(geometricNormal, wo));
+                    weight = misWeight(ray.bsdfPdf, lightSamplePdf, appData.lightSampling);
+                    break;
+                }
+            }
+        }
+        ray.color = float3(ray.color) + throughput * material.emissive * weight;
+    }
+
+    // Sample light, specular surfaces only reflect the single direction next event estimation cannot hit. The path
+    // is not continued after the last bounce, so there emitter sampling is the only strategy for every mode.
+    bool lastBounce = bounce + 1 >= appData.maxDepth;
+    LightSampling lightSampling = lastBounce ? EmitterSampling : appData.lightSampling;
+    float3 lightSample = sample3(appData, coordinates, ray.sampleIndex, CAMERA_RANDOM_LAYERS + BOUNCE_RANDOM_LAYERS * bounce);
+    float3 origin = intersection_point + SURFACE_OFFSET * normal;
+    LightSample light;
+    light.valid = false;
+    light.delta = false;
+    if (!isSpecular(material) && lightSampling != BsdfSampling)
+    {
+        float environmentProbability = appData.environmentProbability;
+        float lightProbability = appData.lightProbability;
+        if (lightSample.x < environmentProbability)
+        {
+            light.valid = sampleEnvironment(environmentPixelPdfs, environmentConditionalCdfs, environmentMarginalCdf, appData.environmentWidth,
+                                            appData.environmentHeight, lightSample.yz, light.direction, light.pdf);
+            light.distance = INFINITY;
+            light.radiance = environment[environmentPixel(light.direction, appData.environmentWidth, appData.environmentHeight)];
+            light.pdf *= environmentProbability;
+        }
+        else if (lightSample.x < environmentProbability + lightProbability)
+        {
+            float xi = (lightSample.x - environmentProbability) / lightProbability;
+            uint lightIndex = sampleAliasTable(lightTable, appData.lightsCount, xi);
+            light = sampleLight(lights[lightIndex], origin, lightSample.yz);
+            light.pdf *= lightProbability * lightTable[lightIndex].pdf;
+        }
+        else if (appData.emitterTrianglesCount > 0)
+        {
+            float triangleProbability = 1.0 - environmentProbability - lightProbability;
+            float xi = (lightSample.x - environmentProbability - lightProbability) / triangleProbability;
+            uint emitterIndex = 0;
+            float light_pdf = 0.0;
+            bool emitterSampled = true;
+            if (appData.emitterSelection == LightTreeSelection)
+            {
+                emitterSampled = sampleLightTree(lightTree, origin, normal, xi, emitterIndex, light_pdf);
+            }
+            else
+            {
+                emitterIndex = sampleAliasTable(aliasTable, appData.emitterTrianglesCount, xi);
+                light_pdf = aliasTable[emitterIndex].pdf;
+            }
+
+            if (emitterSampled)
+            {
+                device const EmitterTriangle& emitterTriangle = emitterTriangles[emitterIndex];
+
+                // Light attributes
+                float3 lightTriangleBarycentric = barycentric(lightSample.yz);
+                device const packed_uint3& lightTriangleIndices = indices[emitterTriangle.primitiveIndex];
+                device const packed_float3& d = vertices[lightTriangleIndices.x];
+                device const packed_float3& e = vertices[lightTriangleIndices.y];
+                device const packed_float3& f = vertices[lightTriangleIndices.z];
+                float3 light_position = lightTriangleBarycentric.x * d + lightTriangleBarycentric.y * e + lightTriangleBarycentric.z * f;
+                float3 light_normal = normalize(cross(e-d, f-d));
+                float3 light_dir = light_position - origin;
+                float light_dist = length(light_dir);
+                light_dir /= light_dist;
+
+                float cosTheta = -dot(light_dir, light_normal);
+                light.valid = cosTheta > 0.0;
+                light.direction = light_dir;
+                light.distance = light_dist - SURFACE_OFFSET;
+                light.radiance = emitterTriangle.emissive;
+                light.pdf = triangleProbability * light_pdf * pointSamplePdf(emitterTriangle.area, light_dist, cosTheta);
+            }
+        }
+    }
+
+    float cosSurface = light.valid ? dot(light.direction, normal) : 0.0;
+    if (cosSurface > 0.0)
+    {
+        // Find color
+        float3 materialBsdf = evaluateBsdf(material, geometricNormal, wo, light.direction);
+        float weight = 1.0;
+        if (lightSampling != EmitterSampling && !light.delta)
+            weight = misWeight(light.pdf, bsdfPdf(material, geometricNormal, wo, light.direction), lightSampling);
+
+        // Set shadow ray
+        shadowRay.color = throughput * light.radiance * materialBsdf * (weight * cosSurface / light.pdf);
+        shadowRay.origin = origin;
+        shadowRay.direction = light.direction;
+        shadowRay.minDistance = EPSILON;
+        shadowRay.maxDistance = light.distance;
+    }
+
+    // Continue the path
+    float3 bsdfSample = sample3(appData, coordinates, ray.sampleIndex, CAMERA_RANDOM_LAYERS + BOUNCE_RANDOM_LAYERS * bounce + 1);
+    float3 terminationSample = sample3(appData, coordinates, ray.sampleIndex, CAMERA_RANDOM_LAYERS + BOUNCE_RANDOM_LAYERS * bounce + 2);
+    BsdfSample scattering = sampleBsdf(material, geometricNormal, wo, bsdfSample);
+    if (!scattering.valid)
+    {
+        ray.maxDistance = -1.0;
+        return;
+    }
+    throughput *= scattering.weight;
+
+    // Russian roulette
+    float continuationProbability = 1.0;
+    if (bounce >= appData.russianRouletteDepth)
+        continuationProbability = min(max(throughput.x, max(throughput.y, throughput.z)), 0.95);
+    if (lastBounce || terminationSample.x >= continuationProbability)
+    {
+        ray.maxDistance = -1.0;
+        return;
+    }
+
+    ray.throughput = throughput / continuationProbability;
+    ray.bsdfPdf = scattering.specular ? 0.0 : scattering.pdf;
+    ray.normal = normal;
+    // Refracted rays start below the surface
+    float offset = dot(scattering.direction, normal) < 0.0 ? -SURFACE_OFFSET : SURFACE_OFFSET;
+    ray.origin = intersection_point + offset * normal;
+    ray.direction = scattering.direction;
+    ray.minDistance = EPSILON;
+    ray.maxDistance = INFINITY;
 }
 
 kernel void handleShadows(device Ray* rays [[buffer(0)]],
-                         device const Intersection* intersections [[buffer(1)]],
-                         uint2 coordinates [[thread_position_in_grid]],
-                         uint2 size [[threads_per_grid]])
+                          device const ShadowRay* shadowRays [[buffer(1)]],
+                          device const Intersection* intersections [[buffer(2)]],
+                          device const ApplicationData& appData [[buffer(3)]],
+                          uint2 coordinates [[thread_position_in_grid]])
 {
+    uint2 size = imageSize(appData);
+    if (coordinates.x >= size.x || coordinates.y >= size.y)
+        return;
     uint rayIndex = coordinates.x + coordinates.y * size.x;
 
     float intersectionDistance = intersections[rayIndex].distance;
 
-    if (rays[rayIndex].maxDistance < 0.0f || intersectionDistance >= 0.0f) {
-        rays[rayIndex].color = float3(0.0);
+    if (shadowRays[rayIndex].maxDistance >= 0.0f && intersectionDistance < 0.0f) {
+        rays[rayIndex].color = float3(rays[rayIndex].color) + float3(shadowRays[rayIndex].color);
     }
 }
 
+float luminance(float3 color)
+{
+    return dot(color, float3(0.2126, 0.7152, 0.0722));
+}
+
+// Standard error of the mean luminance relative to its square root, see adaptive::relative_error
+float relativeError(float meanLuminance, PixelStatistics statistics)
+{
+    if (statistics.sampleCount < 2)
+        return INFINITY;
+    float varianceOfMean = max(statistics.luminanceSquared - meanLuminance * meanLuminance, 0.0f) / float(statistics.sampleCount - 1);
+    return sqrt(varianceOfMean) / sqrt(max(meanLuminance, ADAPTIVE_MIN_LUMINANCE));
+}
+
 kernel void accumulateImage(
     texture2d<float, access::read_write> image [[texture(0)]],
     device Ray* rays [[buffer(0)]],
     device const ApplicationData& appData [[buffer(1)]],
-    uint2 coordinates [[thread_position_in_grid]],
-    uint2 size [[threads_per_grid]])
+    device PixelStatistics* statistics [[buffer(2)]],
+    uint2 coordinates [[thread_position_in_grid]])
 {
+    uint2 size = imageSize(appData);
+    if (coordinates.x >= size.x || coordinates.y >= size.y)
+        return;
     uint rayIndex = coordinates.x + coordinates.y * size.x;
-    float4 outputColor = float4(rays[rayIndex].color, 1.0);
-    if (appData.frameIndex > 0)
+    PixelStatistics pixel = statistics[rayIndex];
+    if (appData.frameIndex > 0 && pixel.converged != 0)
+        return;
+
+    uint sampleIndex = rays[rayIndex].sampleIndex;
+    float3 color = float3(rays[rayIndex].color);
+    float4 outputColor = float4(color, 1.0);
+    float luminanceSquared = luminance(color) * luminance(color);
+    if (sampleIndex > 0)
     {
         float4 storedColor = image.read(coordinates);
-        float t = float(appData.frameIndex) / float(appData.frameIndex + 1);
+        float t = float(sampleIndex) / float(sampleIndex + 1);
         outputColor = mix(outputColor, storedColor, t);
+        luminanceSquared = mix(luminanceSquared, pixel.luminanceSquared, t);
     }
     image.write(outputColor, coordinates);
-}
\ No newline at end of file
+
+    pixel.luminanceSquared = luminanceSquared;
+    pixel.sampleCount = sampleIndex + 1;
+    float error = relativeError(luminance(outputColor.rgb), pixel);
+    pixel.converged = appData.adaptiveThreshold > 0.0 && pixel.sampleCount >= ADAPTIVE_MIN_SAMPLES && error < appData.adaptiveThreshold;
+    statistics[rayIndex] = pixel;
+}
@@ -0,0 +1,297 @@
+
+// Data layouts shared between the host, the Metal kernels in tracing.metal and the CPU reference.
+
+// The random numbers of a path come in layers of three dimensions, see random.rs. The camera uses the first two
+// layers and every bounce the next three, for the light sample, the BSDF sample and path termination.
+pub const CAMERA_RANDOM_LAYERS: u32 = 2;
+pub const BOUNCE_RANDOM_LAYERS: u32 = 3;
+
+pub const SIZE_OF_RAY: usize = 76;
+pub const SIZE_OF_SHADOW_RAY: usize = 44;
+pub const SIZE_OF_INTERSECTION: usize = 16;
+
+pub const EPSILON: f32 = 0.000001;
+// Distance along the normal that secondary rays start from the surface to avoid self intersections
+pub const SURFACE_OFFSET: f32 = 0.0001;
+
+// A path through a pixel. A negative max distance marks a terminated path which the intersector skips.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct Ray
+{
+    pub origin: [f32; 3],
+    pub min_distance: f32,
+    pub direction: [f32; 3],
+    pub max_distance: f32,
+    // Radiance gathered along the path so far
+    pub color: [f32; 3],
+    pub throughput: [f32; 3],
+    // Solid angle density the direction was sampled with. Zero for camera rays and specular bounces, which
+    // next event estimation cannot sample, so the emission they hit is added without a MIS weight.
+    pub bsdf_pdf: f32,
+    // Surface normal at the origin, the light tree needs it to find the emitter density of hit emitters
+    pub normal: [f32; 3],
+    // Index of the sample in the pixel's sequence, the number of samples the pixel has taken before
+    pub sample_index: u32
+}
+
+// Next event estimation towards a point on an emitter, 'color' is added to the path if the ray is unoccluded
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct ShadowRay
+{
+    pub origin: [f32; 3],
+    pub min_distance: f32,
+    pub direction: [f32; 3],
+    pub max_distance: f32,
+    pub color: [f32; 3]
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct Intersection
+{
+    pub distance: f32,
+    pub primitive_index: u32,
+    pub coordinates: [f32; 2]
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct Triangle
+{
+    pub material_index: u32
+}
+
+// Same values as the MaterialType enum in tracing.metal
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MaterialType
+{
+    Diffuse = 0,
+    // Conductor reflecting with the Fresnel reflectance given by the specular colour at normal incidence
+    Metal = 1,
+    // Glass reflecting and refracting according to the index of refraction
+    Dielectric = 2
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct Material
+{
+    pub material_type: MaterialType,
+    pub diffuse: [f32; 3],
+    pub specular: [f32; 3],
+    pub emissive: [f32; 3],
+    // The alpha parameter of the GGX distribution of the glossy reflection
+    pub roughness: f32,
+    pub ior: f32
+}
+
+// How direct light is sampled, same values as the LightSampling enum in tracing.metal
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LightSampling
+{
+    // Next event estimation only, emitters hit after diffuse or glossy bounces are ignored
+    Emitter = 0,
+    // Only emitters hit by BSDF sampled rays
+    Bsdf = 1,
+    // Both strategies combined by multiple importance sampling with the balance heuristic
+    MisBalance = 2,
+    // Both strategies combined with the power heuristic (exponent two)
+    MisPower = 3
+}
+
+// How next event estimation picks the emitter triangle, same values as the EmitterSelection enum in tracing.metal
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EmitterSelection
+{
+    // Proportional to the power of the emitters from the alias table, regardless of the shading point
+    Power = 0,
+    // Proportional to the estimated contribution at the shading point by traversing the light tree
+    LightTree = 1
+}
+
+// Generator of the numbers a path is sampled with, same values as the SamplerType enum in tracing.metal
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SamplerType
+{
+    // Independent random numbers from the counter based hash
+    Independent = 0,
+    // Owen scrambled Sobol sequence
+    Sobol = 1,
+    // Kronecker sequence with pixel rotations distributed as blue noise
+    BlueNoise = 2
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct EmitterTriangle
+{
+    pub primitive_index: u32,
+    pub emissive: [f32; 3],
+    pub area: f32
+}
+
+// Same values as the LightType enum in tracing.metal
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LightType
+{
+    // Emits its intensity equally in all directions from a single point
+    Point = 0,
+    // Point light restricted to a cone around its direction
+    Spot = 1,
+    // Light from infinitely far away arriving from a cone of directions, a single one for a zero angular radius
+    Directional = 2,
+    // Sphere whose surface emits its radiance
+    Sphere = 3
+}
+
+// Analytic light described in the scene file, same layout as Light in tracing.metal
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Light
+{
+    pub light_type: LightType,
+    // Point and spot light position or sphere centre
+    pub position: [f32; 3],
+    // Unit direction the spot and directional lights shine along
+    pub direction: [f32; 3],
+    pub radius: f32,
+    // Spot lights have their full intensity within the inner cone and fall off smoothly to zero at the outer
+    // cone. The outer cosine of directional lights is the cosine of their angular radius.
+    pub cos_inner: f32,
+    pub cos_outer: f32,
+    // Intensity of point and spot lights, irradiance at normal incidence of directional lights and radiance of
+    // sphere lights
+    pub color: [f32; 3]
+}
+
+// Column of the alias table emitters are picked from, same layout as AliasEntry in tracing.metal. The column
+// keeps its own emitter with 'probability' and otherwise picks 'alias'. 'pdf' is the overall probability of
+// picking the emitter of the same index, which MIS needs for emitters hit by BSDF samples.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AliasEntry
+{
+    pub probability: f32,
+    pub alias: u32,
+    pub pdf: f32
+}
+
+// Node of the light tree, same layout as LightTreeNode in tracing.metal. The bounds and the normal cone enclose
+// all emitters below the node, whose directions of emission lie within 'cos_theta_e' of a normal in the cone.
+// The first child of an interior node directly follows it, leaves hold a single emitter.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LightTreeNode
+{
+    pub bounds_min: [f32; 3],
+    // Sum of area times luminance of the emitters
+    pub power: f32,
+    pub bounds_max: [f32; 3],
+    pub cos_theta_o: f32,
+    pub axis: [f32; 3],
+    pub cos_theta_e: f32,
+    pub second_child: u32,
+    // Index into the emitter triangles for leaves, NO_EMITTER for interior nodes
+    pub emitter_index: u32
+}
+
+// Camera basis where 'right' and 'up' are scaled to span the image plane at distance one along 'forward'
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CameraData
+{
+    pub origin: [f32; 3],
+    pub forward: [f32; 3],
+    pub right: [f32; 3],
+    pub up: [f32; 3],
+    pub lens_radius: f32,
+    pub focus_distance: f32
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct ApplicationData
+{
+    pub ray_number: u32,
+    // Scrambled seed of the render settings the random numbers are hashed with
+    pub seed: u32,
+    pub sampler: SamplerType,
+    pub emitter_triangles_count: u32,
+    pub max_depth: u32,
+    pub russian_roulette_depth: u32,
+    pub light_sampling: LightSampling,
+    pub emitter_selection: EmitterSelection,
+    pub environment_width: u32,
+    pub environment_height: u32,
+    // Probability of next event estimation sampling the environment instead of an emitter triangle
+    pub environment_probability: f32,
+    pub lights_count: u32,
+    // Probability of next event estimation sampling an analytic light
+    pub light_probability: f32,
+    // Relative error below which pixels stop taking samples, zero samples every pixel in every pass
+    pub adaptive_threshold: f32,
+    // Size of the output image, the grid of the kernels may be larger, see dispatch.rs
+    pub image_width: u32,
+    pub image_height: u32
+}
+
+// Samples a pixel has taken so far, the running mean of their colour is in the output image. Restarting the render
+// with ray number zero resets them.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct PixelStatistics
+{
+    // Running mean of the squared luminance of the samples
+    pub luminance_squared: f32,
+    pub sample_count: u32,
+    // Non-zero once the error of the mean is below the adaptive threshold
+    pub converged: u32
+}
+
+impl Default for Ray
+{
+    fn default() -> Self
+    {
+        Ray { origin: [0.0; 3], min_distance: 0.0, direction: [0.0; 3], max_distance: -1.0, color: [0.0; 3], throughput: [0.0; 3], bsdf_pdf: 0.0,
+            normal: [0.0; 3], sample_index: 0 }
+    }
+}
+
+impl Default for Material
+{
+    fn default() -> Self
+    {
+        Material { material_type: MaterialType::Diffuse, diffuse: [0.0; 3], specular: [0.0; 3], emissive: [0.0; 3], roughness: 1.0, ior: 1.5 }
+    }
+}
+
+impl Default for Light
+{
+    fn default() -> Self
+    {
+        Light { light_type: LightType::Point, position: [0.0; 3], direction: [0.0, -1.0, 0.0], radius: 0.0, cos_inner: 1.0, cos_outer: 1.0, color: [0.0; 3] }
+    }
+}
+
+impl Default for ShadowRay
+{
+    fn default() -> Self
+    {
+        ShadowRay { origin: [0.0; 3], min_distance: 0.0, direction: [0.0; 3], max_distance: -1.0, color: [0.0; 3] }
+    }
+}
+
+impl Default for Intersection
+{
+    fn default() -> Self
+    {
+        Intersection { distance: -1.0, primitive_index: 0, coordinates: [0.0; 2] }
+    }
+}
@@ -0,0 +1,265 @@
+
+use objc::{msg_send, sel, sel_impl};
+use cocoa::base::id as cocoa_id;
+use cocoa::base::YES;
+use cocoa::foundation::{NSAutoreleasePool};
+use cocoa::appkit::{NSWindow, NSView};
+
+use metal::*;
+
+use winit::os::macos::WindowExt;
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::mem;
+
+use cgmath::*;
+
+use metal_ray_tracing_rs::adaptive;
+use metal_ray_tracing_rs::camera::Camera;
+use metal_ray_tracing_rs::raytracer;
+use metal_ray_tracing_rs::scene::Scene;
+use metal_ray_tracing_rs::cli::Options;
+
+fn create_blit_pipeline_state(device: &DeviceRef) -> RenderPipelineState
+{
+    let mut file = File::open("src/blit.metal").unwrap();
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).unwrap();
+
+    let options = CompileOptions::new();
+    let library = device.new_library_with_source(&contents, &options).unwrap();
+    let vert = library.get_function("blitVertex", None).unwrap();
+    let frag = library.get_function("blitFragment", None).unwrap();
+
+    let pipeline_state_descriptor = RenderPipelineDescriptor::new();
+    pipeline_state_descriptor.set_vertex_function(Some(&vert));
+    pipeline_state_descriptor.set_fragment_function(Some(&frag));
+    pipeline_state_descriptor.color_attachments().object_at(0).unwrap().set_pixel_format(MTLPixelFormat::BGRA8Unorm);
+
+    device.new_render_pipeline_state(&pipeline_state_descriptor).unwrap()
+}
+
+fn encode_blit_into(command_buffer: &CommandBufferRef, blit_pipeline_state: &RenderPipelineStateRef, input_texture: &TextureRef, output_texture: &TextureRef)
+{
+    let descriptor = RenderPassDescriptor::new();
+    let color_attachment = descriptor.color_attachments().object_at(0).unwrap();
+    color_attachment.set_load_action(MTLLoadAction::DontCare);
+    color_attachment.set_store_action(MTLStoreAction::Store);
+    color_attachment.set_texture(Some(output_texture));
+
+    let encoder = command_buffer.new_render_command_encoder(&descriptor);
+    encoder.set_render_pipeline_state(blit_pipeline_state);
+    encoder.set_fragment_texture(0, Some(input_texture));
+    encoder.draw_primitives(MTLPrimitiveType::Triangle, 0, 3);
+    encoder.end_encoding();
+}
+
+// Radians the camera orbits by per point the mouse is dragged
+const ORBIT_SPEED: f32 = 0.005;
+// Fraction of the distance to the target the camera flies per key press
+const FLY_STEP: f32 = 0.05;
+// Factor of the distance to the target per line scrolled, and the points of a line on a touchpad
+const ZOOM_PER_LINE: f32 = 0.9;
+const POINTS_PER_LINE: f32 = 10.0;
+// Degrees the field of view changes per key press
+const FOV_STEP: f32 = 5.0;
+// Passes between reads of the pixel statistics of the adaptive sampling, each read waits for the frames in flight
+const STATISTICS_INTERVAL: usize = 16;
+
+// Dragging with the left mouse button orbits around the target, W, A, S, D, Q and E fly forward, left, back, right,
+// down and up, scrolling zooms towards the target and [ and ] narrow and widen the field of view
+struct CameraControls
+{
+    camera: Camera,
+    dragging: bool,
+    cursor: Option<(f64, f64)>
+}
+
+impl CameraControls
+{
+    // Applies the event to the camera, true if the view changed
+    fn handle_event(&mut self, event: &winit::WindowEvent) -> bool
+    {
+        let previous_camera = self.camera;
+        match *event {
+            winit::WindowEvent::MouseInput { state, button: winit::MouseButton::Left, .. } =>
+                self.dragging = state == winit::ElementState::Pressed,
+            winit::WindowEvent::CursorMoved { position, .. } => {
+                if let (Some((x, y)), true) = (self.cursor, self.dragging) {
+                    self.camera.orbit(Rad(-(position.x - x) as f32 * ORBIT_SPEED), Rad((position.y - y) as f32 * ORBIT_SPEED));
+                }
+                self.cursor = Some((position.x, position.y));
+            },
+            winit::WindowEvent::MouseWheel { delta, .. } => {
+                let lines = match delta {
+                    winit::MouseScrollDelta::LineDelta(_, y) => y,
+                    winit::MouseScrollDelta::PixelDelta(position) => position.y as f32 / POINTS_PER_LINE
+                };
+                self.camera.zoom(ZOOM_PER_LINE.powf(lines));
+            },
+            winit::WindowEvent::KeyboardInput {
+                input: winit::KeyboardInput { virtual_keycode: Some(virtual_code), state: winit::ElementState::Pressed, .. },
+                ..
+            } => {
+                let step = FLY_STEP * (self.camera.target - self.camera.eye).magnitude();
+                match virtual_code {
+                    winit::VirtualKeyCode::W => self.camera.fly(vec3(0.0, 0.0, step)),
+                    winit::VirtualKeyCode::S => self.camera.fly(vec3(0.0, 0.0, -step)),
+                    winit::VirtualKeyCode::A => self.camera.fly(vec3(-step, 0.0, 0.0)),
+                    winit::VirtualKeyCode::D => self.camera.fly(vec3(step, 0.0, 0.0)),
+                    winit::VirtualKeyCode::Q => self.camera.fly(vec3(0.0, -step, 0.0)),
+                    winit::VirtualKeyCode::E => self.camera.fly(vec3(0.0, step, 0.0)),
+                    winit::VirtualKeyCode::LBracket => self.camera.change_fov(-FOV_STEP),
+                    winit::VirtualKeyCode::RBracket => self.camera.change_fov(FOV_STEP),
+                    _ => ()
+                }
+            },
+            _ => ()
+        }
+        self.camera != previous_camera
+    }
+}
+
+// Size of the window contents in pixels, at least one pixel while the window is minimized
+fn drawable_size(window: &winit::Window) -> (usize, usize)
+{
+    let size = window.get_inner_size().unwrap().to_physical(window.get_hidpi_factor());
+    ((size.width.round() as usize).max(1), (size.height.round() as usize).max(1))
+}
+
+// Reading the statistics of the adaptive sampling waits until the GPU is idle, so they are only checked every
+// STATISTICS_INTERVAL passes. The render may run up to STATISTICS_INTERVAL - 1 passes past the budget.
+fn needs_pass(raytracer: &raytracer::RayTracer, ray_number: usize, max_no_rays: usize, adaptive_threshold: f32) -> bool
+{
+    if ray_number == 0 {
+        true
+    }
+    else if adaptive_threshold > 0.0 {
+        !ray_number.is_multiple_of(STATISTICS_INTERVAL) || adaptive::needs_samples(&raytracer.read_pixel_statistics(), max_no_rays)
+    }
+    else {
+        ray_number < max_no_rays
+    }
+}
+
+pub fn run(options: &Options, scene: &Scene) {
+    let mut events_loop = winit::EventsLoop::new();
+    let winit_window = winit::WindowBuilder::new()
+        .with_dimensions((options.width as u32, options.height as u32).into())
+        .with_title("Metal ray tracer".to_string())
+        .build(&events_loop).unwrap();
+
+    let window: cocoa_id = unsafe { mem::transmute(winit_window.get_nswindow()) };
+    let device = Device::system_default();
+
+    let layer = CoreAnimationLayer::new();
+    layer.set_device(&device);
+    layer.set_pixel_format(MTLPixelFormat::BGRA8Unorm_sRGB);
+    layer.set_presents_with_transaction(false);
+
+    unsafe {
+        let view = window.contentView();
+        view.setWantsBestResolutionOpenGLSurface_(YES);
+        view.setWantsLayer(YES);
+        view.setLayer(mem::transmute(layer.as_ref()));
+    }
+
+    let mut draw_size = drawable_size(&winit_window);
+    layer.set_contents_scale(winit_window.get_hidpi_factor());
+    layer.set_drawable_size(draw_size.0 as f64, draw_size.1 as f64);
+
+    let blit_pipeline_state = create_blit_pipeline_state(&device);
+    let command_queue = device.new_command_queue();
+
+    let mut raytracer = raytracer::RayTracer::new(&device, scene, draw_size.0, draw_size.1, &options.settings);
+    raytracer.set_camera(&options.camera);
+
+    let mut pool = unsafe { NSAutoreleasePool::new(cocoa::base::nil) };
+    let mut running = true;
+
+    let mut ray_number = 0;
+    let mut finished = false;
+    let max_no_rays = options.samples;
+    let mut controls = CameraControls { camera: *raytracer.camera(), dragging: false, cursor: None };
+
+    while running {
+        let mut resized = false;
+        let mut view_changed = false;
+        events_loop.poll_events(|event| {
+            match event {
+                winit::Event::WindowEvent { event, .. } => {
+                    view_changed |= controls.handle_event(&event);
+                    match event {
+                        winit::WindowEvent::CloseRequested => running = false,
+                        winit::WindowEvent::Resized(_) | winit::WindowEvent::HiDpiFactorChanged(_) => resized = true,
+                        winit::WindowEvent::KeyboardInput {
+                            input:
+                                winit::KeyboardInput {
+                                    virtual_keycode: Some(virtual_code),
+                                    state,
+                                    ..
+                                },
+                            ..
+                        } => match (virtual_code, state) {
+                            (winit::VirtualKeyCode::Escape, _) => running = false,
+                            (winit::VirtualKeyCode::R, _) => {
+                                ray_number = 0;
+                                finished = false;
+                            },
+                            _ => (),
+                        },
+                        _ => (),
+                    }
+                },
+                _ => {}
+            }
+        });
+
+        // Progressive accumulation starts over from the new view
+        if view_changed {
+            raytracer.set_camera(&controls.camera);
+            ray_number = 0;
+            finished = false;
+        }
+
+        // The output image follows the drawable in pixels and the render starts over at the new size
+        if resized && drawable_size(&winit_window) != draw_size {
+            draw_size = drawable_size(&winit_window);
+            layer.set_contents_scale(winit_window.get_hidpi_factor());
+            layer.set_drawable_size(draw_size.0 as f64, draw_size.1 as f64);
+            raytracer.resize(&device, draw_size.0, draw_size.1);
+            ray_number = 0;
+            finished = false;
+        }
+
+        if ray_number == 0 {
+            println!("Started ray tracing");
+        }
+
+        if let Some(drawable) = layer.next_drawable() {
+
+            let command_buffer = command_queue.new_command_buffer();
+            if !finished && needs_pass(&raytracer, ray_number, max_no_rays, options.settings.adaptive_threshold) {
+                if (ray_number+1) % 10 == 0 {
+                    println!("Ray number: {}", ray_number+1);
+                }
+                raytracer.encode_into(ray_number, command_buffer);
+                ray_number += 1;
+            }
+            else if !finished {
+                println!("Finished ray tracing");
+                finished = true;
+            }
+            encode_blit_into(&command_buffer, &blit_pipeline_state, raytracer.output_texture(), &drawable.texture());
+
+            command_buffer.present_drawable(&drawable);
+            command_buffer.commit();
+
+            unsafe {
+                msg_send![pool, drain];
+                pool = NSAutoreleasePool::new(cocoa::base::nil);
+            }
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use cgmath::vec3;
+use metal_ray_tracing_rs::adaptive::*;
+use metal_ray_tracing_rs::camera::Camera;
+use metal_ray_tracing_rs::cpu::CpuRayTracer;
+use metal_ray_tracing_rs::emitters::luminance;
+use metal_ray_tracing_rs::environment::EnvironmentMap;
+use metal_ray_tracing_rs::types::*;
+use metal_ray_tracing_rs::settings::RenderSettings;
+
+mod common;
+
+const SIZE: usize = 16;
+
+// A grey floor under a constant white sky, seen from just above with the horizon in the middle of the image. The
+// sky pixels have no noise and the floor pixels have the radiance of the albedo, 0.5, when only the sky lights them
+// directly.
+fn sky_and_floor(settings: &RenderSettings) -> CpuRayTracer
+{
+    let mut scene = common::empty_scene(vec![Material {diffuse: [0.5, 0.5, 0.5], ..Material::default()}]);
+    common::add_quad(&mut scene, [[-100.0, 0.0, -100.0], [-100.0, 0.0, 10.0], [100.0, 0.0, 10.0], [100.0, 0.0, -100.0]], 0);
+    scene.environment = Some(EnvironmentMap::constant([1.0, 1.0, 1.0]));
+    let settings = RenderSettings {max_depth: 1, ..*settings};
+    let mut ray_tracer = CpuRayTracer::new(scene, SIZE, SIZE, &settings);
+    ray_tracer.set_camera(&Camera::new(vec3(0.0, 1.0, 0.0), vec3(0.0, 1.0, -1.0), vec3(0.0, 1.0, 0.0), 90.0, 1.0));
+    ray_tracer
+}
+
+// Renders passes like the headless mode until the budget is spent
+fn render(ray_tracer: &mut CpuRayTracer, samples: usize) -> usize
+{
+    let mut ray_number = 0;
+    while ray_number == 0 || needs_samples(ray_tracer.pixel_statistics(), samples) {
+        ray_tracer.render(ray_number);
+        ray_number += 1;
+    }
+    ray_number
+}
+
+fn floor_rmse(image: &[[f32; 4]]) -> f32
+{
+    let errors: Vec<f32> = image.iter().filter(|pixel| pixel[0] < 0.9).map(|pixel| (pixel[0] - 0.5) * (pixel[0] - 0.5)).collect();
+    (errors.iter().sum::<f32>() / errors.len() as f32).sqrt()
+}
+
+#[test]
+fn without_threshold_every_pixel_takes_the_samples()
+{
+    let mut ray_tracer = sky_and_floor(&RenderSettings::default());
+    assert_eq!(render(&mut ray_tracer, 20), 20);
+    assert!(ray_tracer.pixel_statistics().iter().all(|statistics| statistics.sample_count == 20 && statistics.converged == 0));
+    assert_eq!(mean_sample_count(ray_tracer.pixel_statistics()), 20.0);
+}
+
+#[test]
+fn second_moment_matches_the_samples()
+{
+    // The colour of every sample follows from two consecutive means
+    let mut ray_tracer = sky_and_floor(&RenderSettings::default());
+    let mut means = vec![[0.0f32; 3]; SIZE * SIZE];
+    let mut squares: Vec<Vec<f64>> = vec![Vec::new(); SIZE * SIZE];
+    for ray_number in 0..24 {
+        ray_tracer.render(ray_number);
+        for ((mean, squares), pixel) in means.iter_mut().zip(squares.iter_mut()).zip(ray_tracer.output_image().iter()) {
+            let k = ray_number as f32;
+            let sample = [0, 1, 2].map(|c| (k + 1.0) * pixel[c] - k * mean[c]);
+            squares.push((luminance(sample) * luminance(sample)) as f64);
+            *mean = [pixel[0], pixel[1], pixel[2]];
+        }
+    }
+
+    for ((pixel, statistics), squares) in ray_tracer.output_image().iter().zip(ray_tracer.pixel_statistics()).zip(squares.iter()) {
+        let second_moment = squares.iter().sum::<f64>() / squares.len() as f64;
+        assert!((statistics.luminance_squared as f64 - second_moment).abs() < 1e-3 * second_moment.max(1.0), "{:?} {}", statistics, second_moment);
+
+        let mean = luminance([pixel[0], pixel[1], pixel[2]]) as f64;
+        let n = squares.len() as f64;
+        let variance = (second_moment - mean * mean).max(0.0) / (n - 1.0);
+        assert!((variance_of_mean(mean as f32, statistics) as f64 - variance).abs() < 1e-3 * variance.max(1e-2), "{:?} {}", statistics, variance);
+    }
+}
+
+#[test]
+fn noisy_pixels_get_the_samples_of_converged_ones()
+{
+    // Independent samples, the error of the Sobol sequence drops faster at powers of two than between them
+    const SAMPLES: usize = 32;
+    let settings = RenderSettings {sampler: SamplerType::Independent, ..RenderSettings::default()};
+    let mut uniform = sky_and_floor(&settings);
+    render(&mut uniform, SAMPLES);
+    let mut adaptive = sky_and_floor(&RenderSettings {adaptive_threshold: 0.01, ..settings});
+    render(&mut adaptive, SAMPLES);
+
+    let statistics = adaptive.pixel_statistics();
+    let sky: Vec<_> = adaptive.output_image().iter().zip(statistics).filter(|(pixel, _)| pixel[0] > 0.9).map(|(_, statistics)| *statistics).collect();
+    let floor: Vec<_> = adaptive.output_image().iter().zip(statistics).filter(|(pixel, _)| pixel[0] < 0.9).map(|(_, statistics)| *statistics).collect();
+    assert!(sky.len() > SIZE * SIZE / 4 && floor.len() > SIZE * SIZE / 4);
+    assert!(sky.iter().all(|statistics| statistics.sample_count == MIN_SAMPLES && statistics.converged == 1));
+    assert!(floor.iter().all(|statistics| statistics.sample_count as usize > SAMPLES && statistics.converged == 0));
+
+    // The budget is spent within the last pass
+    let taken: usize = statistics.iter().map(|statistics| statistics.sample_count as usize).sum();
+    assert!(taken >= SAMPLES * SIZE * SIZE && taken < SAMPLES * SIZE * SIZE + floor.len());
+
+    let (uniform_error, adaptive_error) = (floor_rmse(uniform.output_image()), floor_rmse(adaptive.output_image()));
+    assert!(adaptive_error < 0.9 * uniform_error, "{} {}", adaptive_error, uniform_error);
+}
+
+#[test]
+fn restart_resets_the_statistics()
+{
+    let mut ray_tracer = sky_and_floor(&RenderSettings {adaptive_threshold: 0.01, ..RenderSettings::default()});
+    render(&mut ray_tracer, 20);
+    ray_tracer.render(0);
+    assert!(ray_tracer.pixel_statistics().iter().all(|statistics| statistics.sample_count == 1 && statistics.converged == 0));
+}
+
+#[test]
+fn passes_stop_when_converged_or_spent()
+{
+    let pixel = |sample_count, converged| PixelStatistics {luminance_squared: 0.0, sample_count, converged};
+    assert!(needs_samples(&[pixel(16, 1), pixel(16, 0)], 20));
+    assert!(!needs_samples(&[pixel(16, 1), pixel(16, 1)], 20));
+    assert!(!needs_samples(&[pixel(16, 1), pixel(24, 0)], 20));
+    // No pixel takes more than a few times its share when almost all have converged
+    assert!(!needs_samples(&[pixel(16, 1), pixel(16, 1), pixel(16, 1), pixel(16, 1), pixel(16, 1), pixel(16, 1), pixel(80, 0)], 20));
+}
+
+#[test]
+fn statistics_image_has_variance_count_and_convergence()
+{
+    let statistics = [PixelStatistics {luminance_squared: 1.25, sample_count: 5, converged: 1}, PixelStatistics {luminance_squared: 4.0, sample_count: 1, converged: 0}];
+    let image = statistics_image(&[[1.0, 1.0, 1.0, 1.0], [2.0, 2.0, 2.0, 1.0]], &statistics);
+    assert!((image[0][0] - 0.0625).abs() < 1e-6);
+    assert_eq!(&image[0][1..], &[5.0, 1.0, 1.0]);
+    // A single sample has no variance estimate
+    assert_eq!(image[1], [0.0, 1.0, 0.0, 1.0]);
+}
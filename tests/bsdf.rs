@@ -0,0 +1,168 @@
+use cgmath::*;
+use mersenne_twister::MT19937;
+use rand::{Rng, SeedableRng};
+use std::f32::consts::PI;
+
+use metal_ray_tracing_rs::bsdf::*;
+use metal_ray_tracing_rs::types::*;
+
+const NORMAL: Vector3<f32> = Vector3 { x: 0.0, y: 0.0, z: 1.0 };
+
+fn glossy_materials() -> Vec<Material>
+{
+    let mut materials = Vec::new();
+    for &roughness in [0.05, 0.2, 0.5, 1.0].iter() {
+        materials.push(Material {material_type: MaterialType::Metal, specular: [1.0, 1.0, 1.0], roughness, ..Material::default()});
+        materials.push(Material {material_type: MaterialType::Metal, specular: [0.95, 0.64, 0.54], roughness, ..Material::default()});
+        materials.push(Material {diffuse: [1.0, 1.0, 1.0], specular: [0.04, 0.04, 0.04], roughness, ..Material::default()});
+        materials.push(Material {diffuse: [0.2, 0.5, 1.0], specular: [0.5, 0.5, 0.5], roughness, ..Material::default()});
+    }
+    materials
+}
+
+fn direction(cos_theta: f32, phi: f32) -> Vector3<f32>
+{
+    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+    vec3(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+fn uniform_hemisphere(rng: &mut MT19937) -> Vector3<f32>
+{
+    direction(rng.next_f32(), 2.0 * PI * rng.next_f32())
+}
+
+fn random_sample(rng: &mut MT19937) -> Vector3<f32>
+{
+    vec3(rng.next_f32(), rng.next_f32(), rng.next_f32())
+}
+
+#[test]
+fn ggx_distribution_is_normalized()
+{
+    let mut rng: MT19937 = SeedableRng::from_seed(1u64);
+    for &alpha in [0.1, 0.3, 1.0].iter() {
+        // Projected area of the microfacets integrated with cosine weighted directions
+        let count = 200_000;
+        let mut integral = 0.0;
+        for _ in 0..count {
+            let h = uniform_hemisphere(&mut rng);
+            integral += ggx_distribution(h.z, alpha) * h.z * 2.0 * PI;
+        }
+        integral /= count as f32;
+        assert!((integral - 1.0).abs() < 0.03, "alpha {}: {}", alpha, integral);
+    }
+}
+
+#[test]
+fn glossy_bsdfs_are_reciprocal()
+{
+    let mut rng: MT19937 = SeedableRng::from_seed(2u64);
+    for material in glossy_materials() {
+        for _ in 0..1000 {
+            let wo = uniform_hemisphere(&mut rng);
+            let wi = uniform_hemisphere(&mut rng);
+            let forward = evaluate(&material, NORMAL, wo, wi);
+            let backward = evaluate(&material, NORMAL, wi, wo);
+            for i in 0..3 {
+                assert!((forward[i] - backward[i]).abs() <= 1e-4 * forward[i].max(1.0), "{:?}: {:?} {:?}", material, forward, backward);
+            }
+        }
+    }
+}
+
+#[test]
+fn glossy_bsdfs_conserve_energy()
+{
+    let mut rng: MT19937 = SeedableRng::from_seed(3u64);
+    for material in glossy_materials() {
+        for &cos_theta_o in [1.0, 0.7, 0.3, 0.05].iter() {
+            let wo = direction(cos_theta_o, 0.3);
+            let count = 20_000;
+            let mut albedo = vec3(0.0, 0.0, 0.0);
+            for _ in 0..count {
+                if let Some(sample) = sample(&material, NORMAL, wo, random_sample(&mut rng)) {
+                    albedo += sample.weight / count as f32;
+                }
+            }
+            assert!(albedo.x <= 1.01 && albedo.y <= 1.01 && albedo.z <= 1.01, "{:?} at cos {}: {:?}", material, cos_theta_o, albedo);
+        }
+    }
+
+    // Light lost to masking is the only loss of a white conductor, which is small for smooth surfaces
+    let mirror = Material {material_type: MaterialType::Metal, specular: [1.0, 1.0, 1.0], roughness: 0.05, ..Material::default()};
+    let mut albedo = 0.0;
+    for _ in 0..20_000 {
+        if let Some(sample) = sample(&mirror, NORMAL, direction(0.8, 0.0), random_sample(&mut rng)) {
+            albedo += sample.weight.x / 20_000.0;
+        }
+    }
+    assert!(albedo > 0.98, "{}", albedo);
+}
+
+#[test]
+fn samples_match_evaluate_and_pdf()
+{
+    let mut rng: MT19937 = SeedableRng::from_seed(4u64);
+    for material in glossy_materials() {
+        for _ in 0..1000 {
+            let wo = uniform_hemisphere(&mut rng);
+            if let Some(sample) = sample(&material, NORMAL, wo, random_sample(&mut rng)) {
+                assert!(!sample.specular);
+                let density = pdf(&material, NORMAL, wo, sample.direction);
+                assert!((density - sample.pdf).abs() <= 1e-4 * density, "{} {}", density, sample.pdf);
+
+                let weight = evaluate(&material, NORMAL, wo, sample.direction) * (sample.direction.z / density);
+                for i in 0..3 {
+                    assert!((weight[i] - sample.weight[i]).abs() <= 1e-4 * weight[i].max(1.0));
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn pdf_integrates_to_at_most_one()
+{
+    let mut rng: MT19937 = SeedableRng::from_seed(5u64);
+    for material in glossy_materials() {
+        if material.roughness < 0.2 {
+            // Too peaked for uniform sampling to integrate
+            continue;
+        }
+        let wo = direction(0.6, 1.0);
+        let count = 100_000;
+        let mut integral = 0.0;
+        for _ in 0..count {
+            integral += pdf(&material, NORMAL, wo, uniform_hemisphere(&mut rng)) * 2.0 * PI / count as f32;
+        }
+        // Reflections about visible normals may point below the horizon, rough surfaces lose a large part
+        assert!(integral > 0.6 && integral < 1.02, "{:?}: {}", material, integral);
+    }
+}
+
+#[test]
+fn glossy_reflection_is_two_sided()
+{
+    let material = Material {material_type: MaterialType::Metal, specular: [1.0, 1.0, 1.0], roughness: 0.3, ..Material::default()};
+    let wo = direction(0.8, 0.2);
+    let wi = direction(0.6, 2.5);
+    assert_eq!(evaluate(&material, NORMAL, wo, wi), evaluate(&material, -NORMAL, wo, wi));
+    assert_eq!(evaluate(&material, NORMAL, wo, -wi), vec3(0.0, 0.0, 0.0));
+}
+
+#[test]
+fn smooth_materials_are_specular()
+{
+    let mirror = Material {material_type: MaterialType::Metal, specular: [0.9, 0.9, 0.9], roughness: 0.0, ..Material::default()};
+    let reflected = sample(&mirror, NORMAL, direction(1.0, 0.0), vec3(0.5, 0.5, 0.5)).unwrap();
+    assert!(reflected.specular && is_specular(&mirror));
+    assert!((reflected.direction - NORMAL).magnitude() < 1e-6);
+    assert!((reflected.weight.x - 0.9).abs() < 1e-6);
+
+    let glass = Material {material_type: MaterialType::Dielectric, ior: 1.5, ..Material::default()};
+    let reflected = sample(&glass, NORMAL, direction(1.0, 0.0), vec3(0.5, 0.5, 0.01)).unwrap();
+    let refracted = sample(&glass, NORMAL, direction(1.0, 0.0), vec3(0.5, 0.5, 0.5)).unwrap();
+    assert!((reflected.direction - NORMAL).magnitude() < 1e-6 && (reflected.pdf - 0.04).abs() < 1e-6);
+    assert!((refracted.direction + NORMAL).magnitude() < 1e-6 && (refracted.pdf - 0.96).abs() < 1e-6);
+    assert_eq!(evaluate(&glass, NORMAL, direction(1.0, 0.0), direction(1.0, 0.0)), vec3(0.0, 0.0, 0.0));
+}
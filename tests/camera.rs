@@ -0,0 +1,156 @@
+use cgmath::*;
+use metal_ray_tracing_rs::camera::Camera;
+
+const SIZE: (usize, usize) = (4, 2);
+
+fn direction(camera: &Camera, pixel: (usize, usize), jitter: Vector2<f32>) -> Vector3<f32>
+{
+    Vector3::from(camera.generate_ray(pixel, SIZE, jitter, vec2(0.0, 0.0)).direction)
+}
+
+fn assert_near(a: Vector3<f32>, b: Vector3<f32>)
+{
+    assert!((a - b).magnitude() < 1e-5, "{:?} != {:?}", a, b);
+}
+
+#[test]
+fn default_camera_matches_the_former_hard_coded_rays()
+{
+    let camera = Camera { aspect: 2.0, ..Camera::default() };
+    let ray = camera.generate_ray((2, 1), SIZE, vec2(0.0, 0.0), vec2(0.3, 0.7));
+    assert_eq!(ray.origin, [0.0, 1.0, 2.1]);
+    assert_near(Vector3::from(ray.direction), vec3(0.0, 0.0, -1.0));
+
+    // Bottom left corner of the image plane spans the 90 degree vertical field of view
+    assert_near(direction(&camera, (0, 0), vec2(0.0, 0.0)), vec3(-2.0, -1.0, -1.0).normalize());
+    assert_near(direction(&camera, (3, 1), vec2(1.0, 1.0)), vec3(2.0, 1.0, -1.0).normalize());
+}
+
+#[test]
+fn looks_at_the_target()
+{
+    let camera = Camera::new(vec3(1.0, 2.0, 3.0), vec3(-2.0, 0.0, 1.0), vec3(0.0, 1.0, 0.0), 40.0, 2.0);
+    let center = direction(&camera, (2, 1), vec2(0.0, 0.0));
+    assert_near(center, (camera.target - camera.eye).normalize());
+
+    // Top and bottom edges are half the field of view from the center
+    let top = direction(&camera, (2, 1), vec2(0.0, 1.0));
+    let bottom = direction(&camera, (2, 0), vec2(0.0, 0.0));
+    assert!((Deg::from(center.angle(top)).0 - 20.0).abs() < 1e-3);
+    assert!((Deg::from(center.angle(bottom)).0 - 20.0).abs() < 1e-3);
+    assert!(top.y > center.y && bottom.y < center.y);
+
+    // The right edge is further out by the aspect ratio
+    let right = direction(&camera, (3, 1), vec2(1.0, 0.0));
+    let expected = Rad((2.0 * Rad::from(Deg(20.0f32)).0.tan()).atan());
+    assert!((center.angle(right).0 - expected.0).abs() < 1e-4);
+    assert!(center.cross(camera.up).dot(right) > 0.0);
+}
+
+#[test]
+fn jitter_stays_within_the_pixel()
+{
+    let camera = Camera { aspect: 2.0, ..Camera::default() };
+    let pixel_end = direction(&camera, (1, 0), vec2(1.0, 1.0));
+    let next_pixel_start = direction(&camera, (2, 1), vec2(0.0, 0.0));
+    assert_near(pixel_end, next_pixel_start);
+}
+
+fn lens_samples() -> Vec<Vector2<f32>>
+{
+    (0..8).flat_map(|i| (0..8).map(move |j| vec2((i as f32 + 0.5) / 8.0, (j as f32 + 0.5) / 8.0))).collect()
+}
+
+#[test]
+fn thin_lens_rays_converge_at_the_focal_plane()
+{
+    let mut camera = Camera::new(vec3(1.0, 2.0, 3.0), vec3(-2.0, 0.0, 1.0), vec3(0.0, 1.0, 0.0), 40.0, 2.0);
+    camera.aperture_radius = 0.25;
+    camera.focus_distance = 2.5;
+    let forward = (camera.target - camera.eye).normalize();
+
+    for &(pixel, jitter) in [((0, 0), vec2(0.1, 0.2)), ((2, 1), vec2(0.5, 0.5)), ((3, 1), vec2(0.9, 0.4))].iter() {
+        let pinhole = Camera { aperture_radius: 0.0, ..camera }.generate_ray(pixel, SIZE, jitter, vec2(0.0, 0.0));
+        let pinhole_direction = Vector3::from(pinhole.direction);
+        let expected = camera.eye + pinhole_direction * (camera.focus_distance / pinhole_direction.dot(forward));
+
+        for lens_sample in lens_samples() {
+            let ray = camera.generate_ray(pixel, SIZE, jitter, lens_sample);
+            let origin = Vector3::from(ray.origin);
+            let direction = Vector3::from(ray.direction);
+
+            // Origins lie on the lens disk perpendicular to the view direction
+            assert!((origin - camera.eye).dot(forward).abs() < 1e-5);
+            assert!((origin - camera.eye).magnitude() <= camera.aperture_radius + 1e-5);
+
+            let t = (camera.focus_distance - (origin - camera.eye).dot(forward)) / direction.dot(forward);
+            assert!((origin + t * direction - expected).magnitude() < 1e-4);
+        }
+    }
+}
+
+#[test]
+fn thin_lens_samples_cover_the_aperture()
+{
+    let camera = Camera { aperture_radius: 0.5, ..Camera::default() };
+    let origins: Vec<Vector3<f32>> = lens_samples().into_iter()
+        .map(|lens_sample| Vector3::from(camera.generate_ray((0, 0), SIZE, vec2(0.5, 0.5), lens_sample).origin) - camera.eye)
+        .collect();
+
+    // Uniform over the disk: the mean is the center and the mean squared radius is R^2 / 2
+    let mean = origins.iter().fold(Vector3::zero(), |sum, o| sum + o) / origins.len() as f32;
+    let mean_squared_radius = origins.iter().map(|o| o.magnitude2()).sum::<f32>() / origins.len() as f32;
+    assert!(mean.magnitude() < 1e-3);
+    assert!((mean_squared_radius - 0.125).abs() < 1e-2);
+    assert!(origins.iter().any(|o| o.x > 0.4) && origins.iter().any(|o| o.y < -0.4));
+}
+
+#[test]
+fn orbit_keeps_the_target_and_distance()
+{
+    let mut camera = Camera::new(vec3(0.0, 1.0, 3.0), vec3(0.0, 1.0, 0.0), vec3(0.0, 1.0, 0.0), 60.0, 1.0);
+    camera.orbit(Rad(std::f32::consts::FRAC_PI_2), Rad(0.0));
+    assert_near(camera.eye, vec3(3.0, 1.0, 0.0));
+    assert_eq!(camera.target, vec3(0.0, 1.0, 0.0));
+
+    camera.orbit(Rad(0.0), Rad::from(Deg(30.0)));
+    assert_near(camera.eye, vec3(3.0 * 30f32.to_radians().cos(), 1.0 + 1.5, 0.0));
+    assert!(((camera.eye - camera.target).magnitude() - 3.0).abs() < 1e-5);
+
+    // The eye stops before it looks straight down, where the view would have no right direction
+    camera.orbit(Rad(0.0), Rad::from(Deg(120.0)));
+    let view = (camera.target - camera.eye).normalize();
+    assert!(view.cross(camera.up).magnitude() > 1e-2);
+    assert!((Deg::from(view.angle(-camera.up)).0 - 1.0).abs() < 1e-2);
+}
+
+#[test]
+fn fly_moves_along_the_view()
+{
+    let mut camera = Camera::default();
+    let view = camera.target - camera.eye;
+    camera.fly(vec3(0.5, 0.25, 2.0));
+    assert_near(camera.eye, vec3(0.5, 1.25, 0.1));
+    assert_near(camera.target - camera.eye, view);
+}
+
+#[test]
+fn zoom_keeps_the_target_in_focus()
+{
+    let mut camera = Camera::new(vec3(0.0, 0.0, 4.0), vec3(0.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0), 60.0, 1.0);
+    camera.zoom(0.5);
+    assert_near(camera.eye, vec3(0.0, 0.0, 2.0));
+    assert_eq!(camera.focus_distance, 2.0);
+}
+
+#[test]
+fn field_of_view_changes_within_limits()
+{
+    let mut camera = Camera::default();
+    camera.change_fov(-30.0);
+    assert_eq!(camera.vertical_fov, 60.0);
+    camera.change_fov(-100.0);
+    assert_eq!(camera.vertical_fov, 5.0);
+    camera.change_fov(200.0);
+    assert_eq!(camera.vertical_fov, 170.0);
+}
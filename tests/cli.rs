@@ -0,0 +1,151 @@
+use std::path::PathBuf;
+use cgmath::vec3;
+use metal_ray_tracing_rs::camera::Camera;
+use metal_ray_tracing_rs::cli::*;
+use metal_ray_tracing_rs::settings::RenderSettings;
+use metal_ray_tracing_rs::types::{LightSampling, EmitterSelection, SamplerType};
+
+// Any existing file passes the scene validation
+const SCENE: &str = "Cargo.toml";
+
+#[test]
+fn parses_all_options()
+{
+    let options = parse(vec![SCENE, "--width", "320", "--height=240", "--samples", "16", "--seed", "42", "--sampler", "blue-noise", "--max-depth=3", "--adaptive-threshold", "0.05",
+                             "--light-sampling", "balance", "--emitter-selection", "power", "--output", "out.png", "--aov-output", "aov.exr", "--backend", "cpu", "--headless"]).unwrap();
+    let settings = RenderSettings { seed: 42, sampler: SamplerType::BlueNoise, max_depth: 3, light_sampling: LightSampling::MisBalance, emitter_selection: EmitterSelection::Power,
+        adaptive_threshold: 0.05, ..RenderSettings::default() };
+    assert_eq!(options, Options { scene: PathBuf::from(SCENE), width: 320, height: 240, samples: 16, settings, environment: None, sky: [0.0; 3],
+        camera: Camera::default(),
+        output: Some(PathBuf::from("out.png")), aov_output: Some(PathBuf::from("aov.exr")), backend: Backend::Cpu, headless: true });
+}
+
+#[test]
+fn uses_defaults()
+{
+    let options = parse(vec![SCENE, "--backend", "cpu", "--headless"]).unwrap();
+    assert_eq!((options.width, options.height, options.samples, options.settings), (800, 600, 1000, RenderSettings::default()));
+    assert_eq!((options.output, options.aov_output), (None, None));
+}
+
+#[test]
+fn accepts_single_pixel_images()
+{
+    let options = parse(vec![SCENE, "--headless", "--backend", "cpu", "--width", "1", "--height=1"]).unwrap();
+    assert_eq!((options.width, options.height), (1, 1));
+    let options = parse(vec![SCENE, "--headless", "--backend", "cpu", "--width", "1", "--height", "599"]).unwrap();
+    assert_eq!((options.width, options.height), (1, 599));
+}
+
+#[test]
+fn parses_camera_options()
+{
+    let options = parse(vec![SCENE, "--headless", "--backend", "cpu", "--eye", "1,2,3", "--target=0, 0.5, -1", "--up", "0,0,1", "--fov", "35"]).unwrap();
+    assert_eq!(options.camera, Camera::new(vec3(1.0, 2.0, 3.0), vec3(0.0, 0.5, -1.0), vec3(0.0, 0.0, 1.0), 35.0, Camera::default().aspect));
+
+    let options = parse(vec![SCENE, "--headless", "--backend", "cpu", "--aperture", "0.1", "--focus-distance", "3"]).unwrap();
+    assert_eq!((options.camera.aperture_radius, options.camera.focus_distance), (0.1, 3.0));
+    let error = parse(vec![SCENE, "--headless", "--backend", "cpu", "--focus-distance", "0"]).unwrap_err();
+    assert_eq!(error.to_string(), "invalid value '0' for '--focus-distance', expected a positive number");
+
+    let error = parse(vec![SCENE, "--headless", "--backend", "cpu", "--eye", "1,2"]).unwrap_err();
+    assert_eq!(error.to_string(), "invalid value '1,2' for '--eye', expected three comma separated numbers");
+    let error = parse(vec![SCENE, "--headless", "--backend", "cpu", "--fov", "180"]).unwrap_err();
+    assert_eq!(error.to_string(), "invalid value '180' for '--fov', expected an angle in degrees between 0 and 180");
+    let error = parse(vec![SCENE, "--headless", "--backend", "cpu", "--target", "0,1,2.1"]).unwrap_err();
+    assert_eq!(error.to_string(), "the camera target must differ from the eye position");
+    let error = parse(vec![SCENE, "--headless", "--backend", "cpu", "--up", "0,0,1"]).unwrap_err();
+    assert_eq!(error.to_string(), "the camera up direction must not be parallel to the view direction");
+}
+
+#[test]
+fn parses_environment_options()
+{
+    let options = parse(vec![SCENE, "--headless", "--backend", "cpu", "--sky", "0.5,0.7,1"]).unwrap();
+    assert_eq!((options.environment, options.sky), (None, [0.5, 0.7, 1.0]));
+
+    // Only the existence and the extension of the file are checked
+    let path = std::env::temp_dir().join("metal-ray-tracing-rs-cli-sky.hdr");
+    std::fs::write(&path, b"").unwrap();
+    let options = parse(vec![SCENE, "--headless", "--backend", "cpu", "--environment", path.to_str().unwrap()]).unwrap();
+    assert_eq!(options.environment, Some(path.clone()));
+    let error = parse(vec![SCENE, "--headless", "--backend", "cpu", "--environment", path.to_str().unwrap(), "--sky", "1,1,1"]).unwrap_err();
+    assert_eq!(error.to_string(), "'--sky' cannot be combined with '--environment'");
+    std::fs::remove_file(&path).unwrap();
+
+    let error = parse(vec![SCENE, "--headless", "--backend", "cpu", "--environment", "missing.hdr"]).unwrap_err();
+    assert_eq!(error.to_string(), "environment map 'missing.hdr' does not exist");
+    let error = parse(vec![SCENE, "--headless", "--backend", "cpu", "--environment", SCENE]).unwrap_err();
+    assert_eq!(error.to_string(), "unsupported environment map format of 'Cargo.toml', expected .hdr or .exr");
+    let error = parse(vec![SCENE, "--headless", "--backend", "cpu", "--sky", "1,-1,1"]).unwrap_err();
+    assert_eq!(error.to_string(), "invalid value '1,-1,1' for '--sky', expected three non-negative numbers");
+}
+
+#[test]
+fn rejects_invalid_values()
+{
+    let error = parse(vec![SCENE, "--headless", "--backend", "cpu", "--width", "0"]).unwrap_err();
+    assert_eq!(error.to_string(), "invalid value '0' for '--width', expected an integer of at least 1");
+    let error = parse(vec![SCENE, "--headless", "--backend", "cpu", "--height", "0"]).unwrap_err();
+    assert_eq!(error.to_string(), "invalid value '0' for '--height', expected an integer of at least 1");
+
+    let error = parse(vec![SCENE, "--headless", "--samples", "many"]).unwrap_err();
+    assert_eq!(error.to_string(), "invalid value 'many' for '--samples', expected an integer of at least 1");
+
+    let error = parse(vec![SCENE, "--headless", "--backend", "vulkan"]).unwrap_err();
+    assert_eq!(error.to_string(), "invalid value 'vulkan' for '--backend', expected 'metal' or 'cpu'");
+
+    let error = parse(vec![SCENE, "--headless", "--seed", "-1"]).unwrap_err();
+    assert_eq!(error.to_string(), "invalid value '-1' for '--seed', expected an unsigned integer");
+
+    let error = parse(vec![SCENE, "--headless", "--max-depth", "0"]).unwrap_err();
+    assert_eq!(error.to_string(), "invalid value '0' for '--max-depth', expected an integer of at least 1");
+    let error = parse(vec![SCENE, "--headless", "--max-depth", "4294967297"]).unwrap_err();
+    assert_eq!(error.to_string(), "invalid value '4294967297' for '--max-depth', expected an integer of at least 1");
+
+    let error = parse(vec![SCENE, "--headless", "--light-sampling", "uniform"]).unwrap_err();
+    assert_eq!(error.to_string(), "invalid value 'uniform' for '--light-sampling', expected 'emitter', 'bsdf', 'balance' or 'power'");
+
+    let error = parse(vec![SCENE, "--headless", "--emitter-selection", "uniform"]).unwrap_err();
+    assert_eq!(error.to_string(), "invalid value 'uniform' for '--emitter-selection', expected 'power' or 'tree'");
+
+    let error = parse(vec![SCENE, "--headless", "--adaptive-threshold", "-0.1"]).unwrap_err();
+    assert_eq!(error.to_string(), "invalid value '-0.1' for '--adaptive-threshold', expected a non-negative number");
+
+    let error = parse(vec![SCENE, "--headless", "--sampler", "halton"]).unwrap_err();
+    assert_eq!(error.to_string(), "invalid value 'halton' for '--sampler', expected 'independent', 'sobol' or 'blue-noise'");
+}
+
+#[test]
+fn rejects_malformed_arguments()
+{
+    assert_eq!(parse(vec![SCENE, "--height"]).unwrap_err(), CliError::MissingValue("--height".to_string()));
+    assert_eq!(parse(vec![SCENE, "--fast"]).unwrap_err(), CliError::UnknownArgument("--fast".to_string()));
+    assert_eq!(parse(vec![SCENE, "other.obj"]).unwrap_err(), CliError::UnknownArgument("other.obj".to_string()));
+    assert_eq!(parse(vec!["--help"]).unwrap_err(), CliError::HelpRequested);
+}
+
+#[test]
+fn rejects_inconsistent_options()
+{
+    let error = parse(vec!["missing.obj", "--headless", "--backend", "cpu"]).unwrap_err();
+    assert_eq!(error.to_string(), "scene file 'missing.obj' does not exist");
+
+    assert!(parse(vec![SCENE, "--backend", "cpu"]).is_err());
+    assert!(parse(vec![SCENE, "--backend", "cpu", "--output", "out.png"]).is_err());
+    assert!(parse(vec![SCENE, "--backend", "cpu", "--aov-output", "aov.pfm"]).is_err());
+    if !cfg!(target_os = "macos") {
+        assert!(parse(vec![SCENE, "--headless", "--backend", "metal"]).is_err());
+    }
+}
+
+#[test]
+fn rejects_unsupported_output_formats()
+{
+    assert!(parse(vec![SCENE, "--headless", "--backend", "cpu", "--output", "out.exr"]).is_ok());
+    let error = parse(vec![SCENE, "--headless", "--backend", "cpu", "--output", "out.jpg"]).unwrap_err();
+    assert_eq!(error.to_string(), "unsupported output format of 'out.jpg', expected .png, .pfm or .exr");
+
+    let error = parse(vec![SCENE, "--headless", "--backend", "cpu", "--aov-output", "aov.png"]).unwrap_err();
+    assert_eq!(error.to_string(), "unsupported AOV output format of 'aov.png', expected .pfm or .exr");
+}
@@ -0,0 +1,95 @@
+
+// Scenes shared by the integration tests, not every test binary uses all of them
+#![allow(dead_code)]
+
+use cgmath::InnerSpace;
+use metal_ray_tracing_rs::types::*;
+use metal_ray_tracing_rs::scene::Scene;
+
+// A Cornell box spanning [-1, 1] x [0, 2] x [-1, 3] with red and green side walls and a square light just
+// below the ceiling facing down. The box is closed behind the default camera at z = 2.1.
+pub fn cornell_box() -> Scene
+{
+    let white = Material {diffuse: [0.73, 0.73, 0.73], ..Material::default()};
+    let red = Material {diffuse: [0.65, 0.05, 0.05], ..Material::default()};
+    let green = Material {diffuse: [0.12, 0.45, 0.15], ..Material::default()};
+    let light = Material {emissive: [15.0, 15.0, 15.0], ..Material::default()};
+
+    let mut scene = empty_scene(vec![white, red, green, light]);
+
+    // Quads facing the inside of the box
+    add_quad(&mut scene, [[-1.0, 0.0, -1.0], [-1.0, 0.0, 3.0], [1.0, 0.0, 3.0], [1.0, 0.0, -1.0]], 0);
+    add_quad(&mut scene, [[-1.0, 2.0, -1.0], [1.0, 2.0, -1.0], [1.0, 2.0, 3.0], [-1.0, 2.0, 3.0]], 0);
+    add_quad(&mut scene, [[-1.0, 0.0, -1.0], [1.0, 0.0, -1.0], [1.0, 2.0, -1.0], [-1.0, 2.0, -1.0]], 0);
+    add_quad(&mut scene, [[-1.0, 0.0, 3.0], [-1.0, 2.0, 3.0], [1.0, 2.0, 3.0], [1.0, 0.0, 3.0]], 0);
+    add_quad(&mut scene, [[-1.0, 0.0, -1.0], [-1.0, 2.0, -1.0], [-1.0, 2.0, 3.0], [-1.0, 0.0, 3.0]], 1);
+    add_quad(&mut scene, [[1.0, 0.0, -1.0], [1.0, 0.0, 3.0], [1.0, 2.0, 3.0], [1.0, 2.0, -1.0]], 2);
+    add_quad(&mut scene, [[-0.25, 1.99, -0.25], [0.25, 1.99, -0.25], [0.25, 1.99, 0.25], [-0.25, 1.99, 0.25]], 3);
+    scene
+}
+
+pub fn empty_scene(materials: Vec<Material>) -> Scene
+{
+    Scene {vertices: Vec::new(), indices: Vec::new(), triangles: Vec::new(), materials, emitter_triangles: Vec::new(), emitter_total_area: 0.0,
+        lights: Vec::new(), environment: None}
+}
+
+// Adds two triangles, the quad faces the side its corners are counter-clockwise on
+pub fn add_quad(scene: &mut Scene, corners: [[f32; 3]; 4], material_index: u32)
+{
+    add_triangle(scene, [corners[0], corners[1], corners[2]], material_index);
+    add_triangle(scene, [corners[0], corners[2], corners[3]], material_index);
+}
+
+// The triangle faces the side its corners are counter-clockwise on
+pub fn add_triangle(scene: &mut Scene, corners: [[f32; 3]; 3], material_index: u32)
+{
+    let first = (scene.vertices.len() / 3) as u32;
+    for corner in corners.iter() {
+        scene.vertices.extend_from_slice(corner);
+    }
+    let primitive_index = scene.triangles.len() as u32;
+    scene.indices.extend([first, first + 1, first + 2].iter());
+    scene.triangles.push(Triangle {material_index});
+
+    let emissive = scene.materials[material_index as usize].emissive;
+    if emissive.iter().any(|&e| e > 0.0) {
+        let [a, b, c] = scene.triangle_vertices(primitive_index);
+        let area = 0.5 * (b - a).cross(c - a).magnitude();
+        scene.emitter_triangles.push(EmitterTriangle {primitive_index, emissive, area});
+        scene.emitter_total_area += area;
+    }
+}
+
+// Adds the six faces of an axis aligned box facing outwards
+pub fn add_box(scene: &mut Scene, min: [f32; 3], max: [f32; 3], material_index: u32)
+{
+    let corner = |x: usize, y: usize, z: usize| [[min[0], max[0]][x], [min[1], max[1]][y], [min[2], max[2]][z]];
+    add_quad(scene, [corner(0, 0, 0), corner(1, 0, 0), corner(1, 0, 1), corner(0, 0, 1)], material_index);
+    add_quad(scene, [corner(0, 1, 0), corner(0, 1, 1), corner(1, 1, 1), corner(1, 1, 0)], material_index);
+    add_quad(scene, [corner(0, 0, 0), corner(0, 0, 1), corner(0, 1, 1), corner(0, 1, 0)], material_index);
+    add_quad(scene, [corner(1, 0, 0), corner(1, 1, 0), corner(1, 1, 1), corner(1, 0, 1)], material_index);
+    add_quad(scene, [corner(0, 0, 0), corner(0, 1, 0), corner(1, 1, 0), corner(1, 0, 0)], material_index);
+    add_quad(scene, [corner(0, 0, 1), corner(1, 0, 1), corner(1, 1, 1), corner(0, 1, 1)], material_index);
+}
+
+// Adds a sphere of latitude and longitude quads facing outwards, the quads at the poles are single triangles
+pub fn add_sphere(scene: &mut Scene, center: [f32; 3], radius: f32, rings: usize, segments: usize, material_index: u32)
+{
+    let point = |ring: usize, segment: usize| {
+        let theta = std::f32::consts::PI * ring as f32 / rings as f32;
+        let phi = 2.0 * std::f32::consts::PI * segment as f32 / segments as f32;
+        [center[0] + radius * theta.sin() * phi.cos(), center[1] + radius * theta.cos(), center[2] + radius * theta.sin() * phi.sin()]
+    };
+    for ring in 0..rings {
+        for segment in 0..segments {
+            let corners = [point(ring, segment), point(ring, segment + 1), point(ring + 1, segment + 1), point(ring + 1, segment)];
+            if ring > 0 {
+                add_triangle(scene, [corners[0], corners[1], corners[2]], material_index);
+            }
+            if ring + 1 < rings {
+                add_triangle(scene, [corners[0], corners[2], corners[3]], material_index);
+            }
+        }
+    }
+}
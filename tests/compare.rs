@@ -0,0 +1,91 @@
+use metal_ray_tracing_rs::compare::*;
+
+const SIZE: usize = 32;
+
+// A grey image with a brighter square in the middle
+fn square(background: f32, square: f32) -> Vec<[f32; 4]>
+{
+    (0..SIZE * SIZE).map(|i| {
+        let (x, y) = (i % SIZE, i / SIZE);
+        let value = if (8..24).contains(&x) && (8..24).contains(&y) { square } else { background };
+        [value, value, value, 1.0]
+    }).collect()
+}
+
+fn mean(values: &[f32]) -> f32
+{
+    values.iter().sum::<f32>() / values.len() as f32
+}
+
+#[test]
+fn identical_images_have_no_error()
+{
+    let image = square(0.2, 0.6);
+    assert_eq!(rmse(&image, &image), 0.0);
+    assert!(flip(&image, &image, SIZE, SIZE).iter().all(|&error| error == 0.0));
+}
+
+#[test]
+fn rmse_of_a_constant_offset_is_the_offset()
+{
+    let image = square(0.2, 0.6);
+    let brighter: Vec<[f32; 4]> = image.iter().map(|pixel| [pixel[0] + 0.1, pixel[1] + 0.1, pixel[2] + 0.1, 1.0]).collect();
+    assert!((rmse(&brighter, &image) - 0.1).abs() < 1e-5);
+}
+
+#[test]
+fn downsample_averages_blocks()
+{
+    let image = square(0.0, 1.0);
+    let small = downsample(&image, SIZE, SIZE, 8);
+    assert_eq!(small.len(), 16);
+    assert_eq!(small[0], [0.0, 0.0, 0.0, 1.0]);
+    assert_eq!(small[1 + 4], [1.0, 1.0, 1.0, 1.0]);
+}
+
+#[test]
+fn flip_grows_with_the_difference()
+{
+    let reference = square(0.2, 0.6);
+    let errors: Vec<f32> = [0.62, 0.7, 0.9].iter().map(|&value| mean(&flip(&square(0.2, value), &reference, SIZE, SIZE))).collect();
+    assert!(errors[0] > 0.0 && errors[0] < errors[1] && errors[1] < errors[2], "{:?}", errors);
+    assert!(flip(&square(0.2, 0.9), &reference, SIZE, SIZE).iter().all(|&error| (0.0..=1.0).contains(&error)));
+
+    // Values beyond the display range look the same
+    assert_eq!(mean(&flip(&square(0.2, 3.0), &square(0.2, 2.0), SIZE, SIZE)), 0.0);
+}
+
+#[test]
+fn flip_notices_colour_shifts_more_than_rmse()
+{
+    // A hue change of the same size as a brightness change is more visible
+    let reference = square(0.2, 0.5);
+    let brighter: Vec<[f32; 4]> = reference.iter().map(|pixel| [pixel[0] + 0.05, pixel[1] + 0.05, pixel[2] + 0.05, 1.0]).collect();
+    let tinted: Vec<[f32; 4]> = reference.iter().map(|pixel| [pixel[0] + 0.05 * 3f32.sqrt(), pixel[1], pixel[2], 1.0]).collect();
+    assert!((rmse(&brighter, &reference) - rmse(&tinted, &reference)).abs() < 1e-5);
+    let (brighter_error, tinted_error) = (mean(&flip(&brighter, &reference, SIZE, SIZE)), mean(&flip(&tinted, &reference, SIZE, SIZE)));
+    assert!(tinted_error > brighter_error, "{} {}", tinted_error, brighter_error);
+}
+
+#[test]
+fn flip_is_largest_where_edges_move()
+{
+    // Moving the square by a pixel only changes the image along its edges
+    let reference = square(0.1, 0.8);
+    let shifted: Vec<[f32; 4]> = (0..SIZE * SIZE).map(|i| reference[(i % SIZE).saturating_sub(1) + (i / SIZE) * SIZE]).collect();
+    let errors = flip(&shifted, &reference, SIZE, SIZE);
+    let edge = errors[8 + 16 * SIZE];
+    assert!(edge > 0.3, "{}", edge);
+    assert!(errors[16 + 16 * SIZE] < 0.1 * edge);
+    assert!(errors[2 + 2 * SIZE] < 0.1 * edge);
+}
+
+#[test]
+fn heatmap_goes_from_dark_to_bright()
+{
+    let colours = error_heatmap(&[0.0, 0.5, 1.0, 2.0]);
+    let luminance: Vec<f32> = colours.iter().map(|c| 0.2126 * c[0] + 0.7152 * c[1] + 0.0722 * c[2]).collect();
+    assert!(luminance[0] < 0.01);
+    assert!(luminance[0] < luminance[1] && luminance[1] < luminance[2]);
+    assert_eq!(colours[2], colours[3]);
+}
@@ -0,0 +1,209 @@
+use cgmath::vec3;
+use metal_ray_tracing_rs::camera::Camera;
+use metal_ray_tracing_rs::cpu::*;
+use metal_ray_tracing_rs::environment::EnvironmentMap;
+use metal_ray_tracing_rs::types::*;
+use metal_ray_tracing_rs::scene::Scene;
+use metal_ray_tracing_rs::settings::RenderSettings;
+
+mod common;
+
+// A grey floor at y = 0 seen by the default camera at (0, 1, 2.1) and a small light at y = 2 facing down.
+const VERTEX_DATA: [f32; 24] = [
+    -10.0, 0.0, -10.0,  10.0, 0.0, -10.0,  10.0, 0.0, 10.0,  -10.0, 0.0, 10.0,
+    -0.5, 2.0, -0.5,  0.5, 2.0, -0.5,  0.5, 2.0, 0.5,  -0.5, 2.0, 0.5];
+const INDEX_DATA: [u32; 12] = [0, 2, 1,  0, 3, 2,  4, 5, 6,  4, 6, 7];
+
+fn floor_and_light(width: usize, height: usize) -> CpuRayTracer
+{
+    let triangle_data = vec![Triangle {material_index: 0}, Triangle {material_index: 0}, Triangle {material_index: 1}, Triangle {material_index: 1}];
+    let material_data = vec![Material {diffuse: [0.5, 0.5, 0.5], ..Material::default()}, Material {emissive: [10.0, 10.0, 10.0], ..Material::default()}];
+    let emitter_triangle_data = vec![EmitterTriangle {primitive_index: 2, emissive: [10.0, 10.0, 10.0], area: 0.5},
+                                     EmitterTriangle {primitive_index: 3, emissive: [10.0, 10.0, 10.0], area: 0.5}];
+    let scene = Scene {vertices: VERTEX_DATA.to_vec(), indices: INDEX_DATA.to_vec(), triangles: triangle_data, materials: material_data,
+        emitter_triangles: emitter_triangle_data, emitter_total_area: 1.0, lights: Vec::new(), environment: None};
+    CpuRayTracer::new(scene, width, height, &RenderSettings::default())
+}
+
+#[test]
+fn bvh_reports_nearest_intersection_like_mps()
+{
+    let (vertex_data, index_data) = (VERTEX_DATA, INDEX_DATA);
+    let bvh = Bvh::new(&vertex_data, &index_data);
+
+    let ray = Ray {origin: [0.2, 1.0, 0.1], min_distance: EPSILON, direction: [0.0, 1.0, 0.0], max_distance: f32::INFINITY, color: [0.0; 3],
+        throughput: [1.0; 3], bsdf_pdf: 0.0, normal: [0.0; 3], sample_index: 0};
+    let intersection = bvh.intersect_nearest(&ray);
+    assert!(intersection.primitive_index == 2 || intersection.primitive_index == 3);
+    assert!((intersection.distance - 1.0).abs() < 1e-5);
+
+    let down = Ray {direction: [0.0, -1.0, 0.0], ..ray};
+    let intersection = bvh.intersect_nearest(&down);
+    assert!(intersection.primitive_index < 2);
+    assert!((intersection.distance - 1.0).abs() < 1e-5);
+
+    // The coordinates are the weights of the first two vertices
+    let c = intersection.coordinates;
+    let i = 3 * intersection.primitive_index as usize;
+    let p: Vec<f32> = (0..3).map(|axis| {
+        c[0] * vertex_data[3 * index_data[i] as usize + axis] + c[1] * vertex_data[3 * index_data[i + 1] as usize + axis]
+            + (1.0 - c[0] - c[1]) * vertex_data[3 * index_data[i + 2] as usize + axis]
+    }).collect();
+    assert!((p[0] - 0.2).abs() < 1e-4 && p[1].abs() < 1e-4 && (p[2] - 0.1).abs() < 1e-4);
+
+    let short = ShadowRay {origin: ray.origin, min_distance: EPSILON, direction: ray.direction, max_distance: 0.5, color: [0.0; 3]};
+    assert!(bvh.intersect_any(&short).distance < 0.0);
+    let terminated = Ray {max_distance: -1.0, ..ray};
+    assert!(bvh.intersect_nearest(&terminated).distance < 0.0);
+}
+
+#[test]
+fn floor_is_lit_and_accumulates()
+{
+    let mut ray_tracer = floor_and_light(32, 24);
+    for ray_number in 0..16 {
+        ray_tracer.render(ray_number);
+    }
+
+    let (width, height) = ray_tracer.output_image_size();
+    let image = ray_tracer.output_image();
+    assert_eq!(image.len(), width * height);
+    assert!(image.iter().all(|pixel| pixel.iter().all(|v| v.is_finite()) && pixel[3] == 1.0));
+
+    // Row 0 is the bottom of the image and looks down at the floor below the light
+    let bottom = image[width / 2];
+    assert!(bottom[0] > 0.0 && bottom[0] == bottom[1] && bottom[1] == bottom[2]);
+}
+
+#[test]
+fn renders_are_reproducible_from_the_seed()
+{
+    let scene = || {
+        let mut scene = common::cornell_box();
+        scene.lights.push(Light {light_type: LightType::Sphere, position: [0.5, 0.5, 0.0], radius: 0.2, color: [2.0, 1.0, 0.5], ..Light::default()});
+        scene.environment = Some(EnvironmentMap::constant([0.1, 0.2, 0.3]));
+        scene
+    };
+    let render = |ray_tracer: &mut CpuRayTracer| {
+        for ray_number in 0..8 {
+            ray_tracer.render(ray_number);
+        }
+        ray_tracer.output_image().iter().flat_map(|pixel| pixel.iter().map(|v| v.to_bits())).collect::<Vec<u32>>()
+    };
+
+    for &sampler in [SamplerType::Independent, SamplerType::Sobol, SamplerType::BlueNoise].iter() {
+        let settings = RenderSettings {seed: 7, sampler, ..RenderSettings::default()};
+        let mut ray_tracer = CpuRayTracer::new(scene(), 16, 12, &settings);
+        let image = render(&mut ray_tracer);
+        assert_eq!(image, render(&mut CpuRayTracer::new(scene(), 16, 12, &settings)), "{:?}", sampler);
+
+        // Nothing carries over from earlier renders
+        ray_tracer.set_camera(&Camera::new(vec3(0.5, 1.0, 1.0), vec3(0.0, 0.5, 0.0), vec3(0.0, 1.0, 0.0), 60.0, 1.0));
+        render(&mut ray_tracer);
+        ray_tracer.set_camera(&Camera::default());
+        assert_eq!(image, render(&mut ray_tracer), "{:?}", sampler);
+
+        let other_seed = render(&mut CpuRayTracer::new(scene(), 16, 12, &RenderSettings {seed: 8, ..settings}));
+        assert_ne!(image, other_seed, "{:?}", sampler);
+    }
+}
+
+fn mean_cornell_box_radiance(settings: &RenderSettings, samples: usize) -> [f32; 3]
+{
+    let mut ray_tracer = CpuRayTracer::new(common::cornell_box(), 32, 24, settings);
+    for ray_number in 0..samples {
+        ray_tracer.render(ray_number);
+    }
+
+    let image = ray_tracer.output_image();
+    let mut mean = [0.0; 3];
+    for pixel in image.iter() {
+        assert!(pixel.iter().all(|v| v.is_finite()));
+        for (m, v) in mean.iter_mut().zip(pixel.iter()) {
+            *m += v / image.len() as f32;
+        }
+    }
+    mean
+}
+
+#[test]
+fn indirect_light_brightens_the_cornell_box()
+{
+    let direct = mean_cornell_box_radiance(&RenderSettings { max_depth: 1, ..RenderSettings::default() }, 32);
+    let global = mean_cornell_box_radiance(&RenderSettings { max_depth: 8, ..RenderSettings::default() }, 32);
+    for channel in 0..3 {
+        assert!(global[channel] > 1.2 * direct[channel], "{:?} {:?}", direct, global);
+    }
+
+    // Light bouncing off the red wall tints the rest of the box
+    let red_bleeding = (global[0] - direct[0]) / direct[0];
+    let blue_bleeding = (global[2] - direct[2]) / direct[2];
+    assert!(red_bleeding > blue_bleeding, "{:?} {:?}", direct, global);
+}
+
+#[test]
+fn russian_roulette_is_unbiased()
+{
+    let without_roulette = mean_cornell_box_radiance(&RenderSettings { max_depth: 6, russian_roulette_depth: 6, seed: 1, ..RenderSettings::default() }, 256);
+    let with_roulette = mean_cornell_box_radiance(&RenderSettings { max_depth: 6, russian_roulette_depth: 1, seed: 2, ..RenderSettings::default() }, 256);
+    for channel in 0..3 {
+        let relative_difference = (with_roulette[channel] - without_roulette[channel]).abs() / without_roulette[channel];
+        assert!(relative_difference < 0.02, "{:?} {:?}", without_roulette, with_roulette);
+    }
+}
+
+fn mean_radiance(ray_tracer: &mut CpuRayTracer, samples: usize) -> f32
+{
+    for ray_number in 0..samples {
+        ray_tracer.render(ray_number);
+    }
+    let image = ray_tracer.output_image();
+    image.iter().map(|pixel| pixel[0]).sum::<f32>() / image.len() as f32
+}
+
+#[test]
+fn metal_reflects_the_light_with_schlick_fresnel()
+{
+    let mirror = Material {material_type: MaterialType::Metal, specular: [0.9, 0.9, 0.9], roughness: 0.0, ..Material::default()};
+    let light = Material {emissive: [10.0, 10.0, 10.0], ..Material::default()};
+    let mut scene = common::empty_scene(vec![mirror, light]);
+    common::add_quad(&mut scene, [[-10.0, 0.0, -10.0], [-10.0, 0.0, 10.0], [10.0, 0.0, 10.0], [10.0, 0.0, -10.0]], 0);
+    common::add_quad(&mut scene, [[-1.0, 2.0, -1.0], [1.0, 2.0, -1.0], [1.0, 2.0, 1.0], [-1.0, 2.0, 1.0]], 1);
+
+    // Looking straight down the mirror shows the light behind the camera
+    let mut ray_tracer = CpuRayTracer::new(scene, 16, 16, &RenderSettings::default());
+    ray_tracer.set_camera(&Camera::new(vec3(0.0, 1.0, 0.0), vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, -1.0), 20.0, 1.0));
+    let radiance = mean_radiance(&mut ray_tracer, 4);
+    assert!((radiance - 9.0).abs() < 1e-3, "{}", radiance);
+}
+
+#[test]
+fn glass_slab_transmits_the_light_minus_fresnel_reflections()
+{
+    let glass = Material {material_type: MaterialType::Dielectric, ior: 1.5, ..Material::default()};
+    let light = Material {emissive: [10.0, 10.0, 10.0], ..Material::default()};
+    let mut scene = common::empty_scene(vec![glass, light]);
+    common::add_quad(&mut scene, [[-10.0, 1.4, -10.0], [10.0, 1.4, -10.0], [10.0, 1.4, 10.0], [-10.0, 1.4, 10.0]], 0);
+    common::add_quad(&mut scene, [[-10.0, 1.5, -10.0], [-10.0, 1.5, 10.0], [10.0, 1.5, 10.0], [10.0, 1.5, -10.0]], 0);
+    common::add_quad(&mut scene, [[-1.0, 2.0, -1.0], [1.0, 2.0, -1.0], [1.0, 2.0, 1.0], [-1.0, 2.0, 1.0]], 1);
+
+    // Near normal incidence each interface reflects ((1.5 - 1) / (1.5 + 1))^2 of the light and the inter
+    // reflections in the slab transmit (1 - F) / (1 + F) in total
+    let mut ray_tracer = CpuRayTracer::new(scene, 16, 16, &RenderSettings::default());
+    ray_tracer.set_camera(&Camera::new(vec3(0.0, 1.0, 0.0), vec3(0.0, 2.0, 0.0), vec3(0.0, 0.0, -1.0), 10.0, 1.0));
+    let radiance = mean_radiance(&mut ray_tracer, 256);
+    let reflectance = 0.04;
+    let expected = 10.0 * (1.0 - reflectance) / (1.0 + reflectance);
+    assert!((radiance - expected).abs() < 0.1, "{} {}", radiance, expected);
+}
+
+#[test]
+fn renders_single_pixel_wide_images()
+{
+    let mut ray_tracer = floor_and_light(1, 3);
+    for ray_number in 0..4 {
+        ray_tracer.render(ray_number);
+    }
+    assert_eq!(ray_tracer.output_image().len(), 3);
+    assert!(ray_tracer.output_image().iter().all(|pixel| pixel.iter().all(|c| c.is_finite())));
+}
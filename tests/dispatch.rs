@@ -0,0 +1,45 @@
+use metal_ray_tracing_rs::dispatch::*;
+
+// Runs the bounds check of the kernels for every thread of the dispatched grid and counts the pixels reached
+fn pixel_coverage(size: (usize, usize)) -> Vec<u32>
+{
+    let (groups_width, groups_height) = thread_groups_count(size);
+    let (group_width, group_height) = THREADS_PER_THREAD_GROUP;
+    let mut coverage = vec![0; size.0 * size.1];
+    for y in 0..groups_height * group_height {
+        for x in 0..groups_width * group_width {
+            if let Some(index) = ray_index((x, y), size) {
+                coverage[index] += 1;
+            }
+        }
+    }
+    coverage
+}
+
+#[test]
+fn rounds_thread_groups_up()
+{
+    assert_eq!(thread_groups_count((800, 600)), (100, 75));
+    assert_eq!(thread_groups_count((801, 599)), (101, 75));
+    assert_eq!(thread_groups_count((1, 1)), (1, 1));
+    assert_eq!(thread_groups_count((9, 16)), (2, 2));
+}
+
+#[test]
+fn every_pixel_is_rendered_once()
+{
+    for &size in [(801, 599), (800, 600), (2, 2), (7, 13), (17, 9)].iter() {
+        assert!(pixel_coverage(size).iter().all(|&count| count == 1), "{:?}", size);
+    }
+}
+
+#[test]
+fn threads_past_the_edges_have_no_pixel()
+{
+    let size = (801, 599);
+    assert_eq!(ray_index((800, 598), size), Some(801 * 599 - 1));
+    assert_eq!(ray_index((801, 0), size), None);
+    assert_eq!(ray_index((0, 599), size), None);
+    // The row stride is the image width, not the width of the grid
+    assert_eq!(ray_index((0, 1), size), Some(801));
+}
@@ -0,0 +1,143 @@
+use cgmath::vec3;
+use mersenne_twister::MT19937;
+use rand::{Rng, SeedableRng};
+use metal_ray_tracing_rs::camera::Camera;
+use metal_ray_tracing_rs::cpu::CpuRayTracer;
+use metal_ray_tracing_rs::emitters::*;
+use metal_ray_tracing_rs::scene::Scene;
+use metal_ray_tracing_rs::settings::RenderSettings;
+use metal_ray_tracing_rs::types::*;
+
+mod common;
+
+// A diffuse floor lit by a small bright white light and a large dim blue one
+fn two_lights() -> Scene
+{
+    let floor = Material {diffuse: [0.8, 0.8, 0.8], ..Material::default()};
+    let bright = Material {emissive: [40.0, 40.0, 40.0], ..Material::default()};
+    let dim = Material {emissive: [0.0, 0.0, 0.5], ..Material::default()};
+    let mut scene = common::empty_scene(vec![floor, bright, dim]);
+    common::add_quad(&mut scene, [[-4.0, 0.0, -4.0], [-4.0, 0.0, 4.0], [4.0, 0.0, 4.0], [4.0, 0.0, -4.0]], 0);
+    common::add_quad(&mut scene, [[-1.25, 2.0, -0.25], [-0.75, 2.0, -0.25], [-0.75, 2.0, 0.25], [-1.25, 2.0, 0.25]], 1);
+    common::add_quad(&mut scene, [[0.0, 2.0, -1.0], [2.0, 2.0, -1.0], [2.0, 2.0, 1.0], [0.0, 2.0, 1.0]], 2);
+    scene
+}
+
+// Probability of every outcome summed over the columns of the table
+fn outcome_probabilities(table: &[AliasEntry]) -> Vec<f32>
+{
+    let mut probabilities = vec![0.0; table.len()];
+    for (column, entry) in table.iter().enumerate() {
+        probabilities[column] += entry.probability / table.len() as f32;
+        probabilities[entry.alias as usize] += (1.0 - entry.probability) / table.len() as f32;
+    }
+    probabilities
+}
+
+#[test]
+fn alias_table_reproduces_the_weights()
+{
+    let mut rng: MT19937 = SeedableRng::from_seed(1u64);
+    for &count in [1, 2, 7, 100].iter() {
+        let mut weights: Vec<f32> = (0..count).map(|_| rng.next_f32() * 10.0).collect();
+        weights[0] = 0.0;
+        let total: f32 = weights.iter().sum();
+
+        let table = build_alias_table(&weights);
+        for (index, probability) in outcome_probabilities(&table).iter().enumerate() {
+            let expected = if count == 1 { 1.0 } else { weights[index] / total };
+            assert!((probability - expected).abs() < 1e-5, "{} of {}: {} {}", index, count, probability, expected);
+            assert!((table[index].pdf - expected).abs() < 1e-6);
+        }
+    }
+}
+
+#[test]
+fn alias_table_without_weights_is_uniform()
+{
+    let table = build_alias_table(&[0.0, 0.0, 0.0, 0.0]);
+    assert!(outcome_probabilities(&table).iter().all(|&probability| (probability - 0.25).abs() < 1e-6));
+    assert!(table.iter().all(|entry| entry.pdf == 0.25));
+}
+
+#[test]
+fn alias_table_samples_follow_the_weights()
+{
+    let weights = [1.0, 3.0, 0.0, 6.0];
+    let table = build_alias_table(&weights);
+    let count = 100_000;
+    let mut histogram = [0; 4];
+    for i in 0..count {
+        histogram[sample_alias_table(&table, (i as f32 + 0.5) / count as f32)] += 1;
+    }
+    for (&hits, &weight) in histogram.iter().zip(weights.iter()) {
+        assert!((hits as f32 / count as f32 - weight / 10.0).abs() < 1e-3, "{:?}", histogram);
+    }
+    assert_eq!(sample_alias_table(&table, 0.0), sample_alias_table(&table, 1e-9));
+    assert_ne!(sample_alias_table(&table, 0.999_999), 2);
+}
+
+#[test]
+fn emitters_are_weighted_by_power()
+{
+    let scene = two_lights();
+    let emitters = EmitterDistribution::new(&scene);
+    assert_eq!(emitters.alias_table.len(), 4);
+    assert_eq!(emitters.triangle_emitters, vec![NO_EMITTER, NO_EMITTER, 0, 1, 2, 3]);
+
+    // Area times luminance of the two lights
+    let bright = 0.25 * 40.0;
+    let dim = 4.0 * 0.0722 * 0.5;
+    let total = bright + dim;
+    let point = vec3(0.0, 0.0, 0.0);
+    let normal = vec3(0.0, 1.0, 0.0);
+    assert!((emitters.pdf(EmitterSelection::Power, point, normal, 2) - 0.5 * bright / total).abs() < 1e-6);
+    assert!((emitters.pdf(EmitterSelection::Power, point, normal, 5) - 0.5 * dim / total).abs() < 1e-6);
+    assert_eq!(emitters.pdf(EmitterSelection::Power, point, normal, 0), 0.0);
+}
+
+#[test]
+fn power_weighted_sampling_converges()
+{
+    // Emitters hit by BSDF samples must be weighted with the same selection probability next event estimation uses.
+    // BSDF samples rarely hit the small bright light, so they need many samples to converge.
+    let render = |light_sampling| {
+        let settings = RenderSettings {light_sampling, max_depth: 2, seed: 3, emitter_selection: EmitterSelection::Power, ..RenderSettings::default()};
+        let mut ray_tracer = CpuRayTracer::new(two_lights(), 16, 12, &settings);
+        ray_tracer.set_camera(&Camera::new(vec3(0.0, 3.0, 3.0), vec3(0.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0), 60.0, 4.0 / 3.0));
+        for ray_number in 0..4096 {
+            ray_tracer.render(ray_number);
+        }
+        let image = ray_tracer.output_image();
+        let sum = image.iter().fold([0.0; 3], |sum, pixel| [sum[0] + pixel[0], sum[1] + pixel[1], sum[2] + pixel[2]]);
+        [sum[0] / image.len() as f32, sum[1] / image.len() as f32, sum[2] / image.len() as f32]
+    };
+
+    let reference = render(LightSampling::Emitter);
+    for &light_sampling in [LightSampling::Bsdf, LightSampling::MisBalance, LightSampling::MisPower].iter() {
+        let radiance = render(light_sampling);
+        for i in 0..3 {
+            assert!((radiance[i] - reference[i]).abs() < 0.04 * reference[i], "{:?}: {:?} {:?}", light_sampling, radiance, reference);
+        }
+    }
+}
+
+#[test]
+fn buffer_data_of_environment_lit_scenes_is_not_empty()
+{
+    let mut scene = common::empty_scene(vec![Material::default()]);
+    scene.environment = Some(metal_ray_tracing_rs::environment::EnvironmentMap::constant([1.0, 1.0, 1.0]));
+    let emitters = EmitterDistribution::new(&scene);
+    let data = emitters.buffer_data(&scene);
+    assert_eq!((data.emitter_triangles.len(), data.alias_table.len(), data.triangle_emitters.len()), (1, 1, 1));
+    assert_eq!((data.light_tree_nodes.len(), data.emitter_trails.len(), data.lights.len(), data.light_table.len()), (1, 1, 1, 1));
+    assert_eq!(data.triangle_emitters[0], NO_EMITTER);
+
+    // Scenes with emitters keep their arrays as they are
+    let scene = two_lights();
+    let emitters = EmitterDistribution::new(&scene);
+    let data = emitters.buffer_data(&scene);
+    assert_eq!(data.alias_table, emitters.alias_table);
+    assert_eq!(data.light_tree_nodes, emitters.light_tree.nodes);
+    assert_eq!(data.emitter_triangles.len(), scene.emitter_triangles.len());
+}
@@ -0,0 +1,195 @@
+use cgmath::*;
+use mersenne_twister::MT19937;
+use rand::{Rng, SeedableRng};
+use std::f32::consts::PI;
+use metal_ray_tracing_rs::camera::Camera;
+use metal_ray_tracing_rs::cpu::CpuRayTracer;
+use metal_ray_tracing_rs::environment::*;
+use metal_ray_tracing_rs::settings::RenderSettings;
+use metal_ray_tracing_rs::types::*;
+
+mod common;
+
+const STRATEGIES: [LightSampling; 4] = [LightSampling::Emitter, LightSampling::Bsdf, LightSampling::MisBalance, LightSampling::MisPower];
+
+// A blue gradient towards the top with a small bright sun
+fn sky_with_sun(width: usize, height: usize) -> EnvironmentMap
+{
+    let mut pixels = Vec::new();
+    for row in 0..height {
+        for column in 0..width {
+            let t = row as f32 / height as f32;
+            let sun = column == width / 3 && row == height / 4;
+            pixels.push(if sun { [5000.0, 4500.0, 4000.0] } else { [0.3 + 0.2 * t, 0.5 + 0.2 * t, 1.0 - 0.3 * t] });
+        }
+    }
+    EnvironmentMap::new(width, height, pixels)
+}
+
+fn uniform_sphere(rng: &mut MT19937) -> Vector3<f32>
+{
+    let z = 1.0 - 2.0 * rng.next_f32();
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * rng.next_f32();
+    vec3(r * phi.cos(), r * phi.sin(), z)
+}
+
+#[test]
+fn directions_map_to_the_equirectangular_image()
+{
+    let centre = direction_to_uv(vec3(0.0, 0.0, -1.0));
+    assert!((centre - vec2(0.5, 0.5)).magnitude() < 1e-6);
+    assert!(direction_to_uv(vec3(0.0, 1.0, 0.0)).y.abs() < 1e-6);
+    assert!((direction_to_uv(vec3(1.0, 0.0, 0.0)) - vec2(0.75, 0.5)).magnitude() < 1e-6);
+
+    let mut rng: MT19937 = SeedableRng::from_seed(1u64);
+    for _ in 0..1000 {
+        let direction = uniform_sphere(&mut rng);
+        assert!((uv_to_direction(direction_to_uv(direction)) - direction).magnitude() < 1e-4);
+    }
+}
+
+#[test]
+fn pdf_integrates_to_one()
+{
+    // Over a grid finer than the pixels of the image
+    let environment = sky_with_sun(64, 32);
+    let (columns, rows) = (4 * 64, 4 * 32);
+    let mut integral = 0.0;
+    for row in 0..rows {
+        for column in 0..columns {
+            let uv = vec2((column as f32 + 0.5) / columns as f32, (row as f32 + 0.5) / rows as f32);
+            let sin_theta = (PI * uv.y).sin();
+            integral += environment.pdf(uv_to_direction(uv)) * 2.0 * PI * PI * sin_theta / (columns * rows) as f32;
+        }
+    }
+    assert!((integral - 1.0).abs() < 1e-3, "{}", integral);
+
+    // Over uniform directions, without a sun too small to be found by them
+    let mut rng: MT19937 = SeedableRng::from_seed(2u64);
+    let gradient = EnvironmentMap::new(2, 2, vec![[1.0, 1.0, 1.0], [2.0, 2.0, 2.0], [0.5, 0.5, 0.5], [0.1, 0.2, 0.3]]);
+    for environment in [gradient, EnvironmentMap::constant([1.0, 2.0, 3.0])].iter() {
+        let count = 200_000;
+        let mut integral = 0.0;
+        for _ in 0..count {
+            integral += environment.pdf(uniform_sphere(&mut rng)) * 4.0 * PI / count as f32;
+        }
+        assert!((integral - 1.0).abs() < 0.02, "{}", integral);
+    }
+}
+
+#[test]
+fn samples_follow_the_pdf()
+{
+    let environment = sky_with_sun(16, 8);
+    let mut rng: MT19937 = SeedableRng::from_seed(3u64);
+    let count = 100_000;
+    let mut histogram = vec![0; 16 * 8];
+    for _ in 0..count {
+        let (direction, pdf) = environment.sample(vec2(rng.next_f32(), rng.next_f32())).unwrap();
+        assert!((pdf - environment.pdf(direction)).abs() <= 1e-3 * pdf, "{:?}: {} {}", direction, pdf, environment.pdf(direction));
+
+        let uv = direction_to_uv(direction);
+        histogram[(uv.x * 16.0) as usize + 16 * (uv.y * 8.0) as usize] += 1;
+    }
+    for (hits, pdf) in histogram.iter().zip(environment.pixel_pdfs.iter()) {
+        let expected = pdf / (16.0 * 8.0);
+        assert!((*hits as f32 / count as f32 - expected).abs() < 0.01 * expected.max(0.1), "{} {}", hits, expected);
+    }
+}
+
+#[test]
+fn black_environments_are_not_sampled()
+{
+    let environment = EnvironmentMap::constant([0.0; 3]);
+    assert!(environment.is_black());
+    assert!(environment.sample(vec2(0.5, 0.5)).is_none());
+    assert_eq!(environment.pdf(vec3(0.0, 0.0, 1.0)), 0.0);
+}
+
+#[test]
+fn loads_hdr_and_exr_images()
+{
+    let (width, height) = (8, 4);
+    let data: Vec<f32> = (0..width * height * 3).map(|i| 0.25 * i as f32 + 0.5).collect();
+    let image = image::Rgb32FImage::from_raw(width as u32, height as u32, data.clone()).unwrap();
+    for (extension, tolerance) in [("hdr", 0.01), ("exr", 1e-6)].iter() {
+        let path = std::env::temp_dir().join(format!("metal-ray-tracing-rs-environment.{}", extension));
+        image.save(&path).unwrap();
+        let environment = EnvironmentMap::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((environment.width, environment.height), (width, height));
+        for (pixel, expected) in environment.pixels.iter().zip(data.chunks(3)) {
+            for i in 0..3 {
+                assert!((pixel[i] - expected[i]).abs() <= tolerance * expected[i], "{}: {:?} {:?}", extension, pixel, expected);
+            }
+        }
+    }
+
+    let error = EnvironmentMap::load(std::path::Path::new("missing.hdr")).unwrap_err();
+    assert!(error.to_string().starts_with("failed to load environment map: "), "{}", error);
+}
+
+// A large diffuse floor below the environment seen from above
+fn render_floor(environment: EnvironmentMap, light_sampling: LightSampling, seed: u64, samples: usize) -> Vec<[f32; 4]>
+{
+    let floor = Material {diffuse: [0.5, 0.5, 0.5], ..Material::default()};
+    let mut scene = common::empty_scene(vec![floor]);
+    common::add_quad(&mut scene, [[-1000.0, 0.0, -1000.0], [-1000.0, 0.0, 1000.0], [1000.0, 0.0, 1000.0], [1000.0, 0.0, -1000.0]], 0);
+    scene.environment = Some(environment);
+
+    let settings = RenderSettings {seed, light_sampling, max_depth: 2, ..RenderSettings::default()};
+    let mut ray_tracer = CpuRayTracer::new(scene, 16, 12, &settings);
+    ray_tracer.set_camera(&Camera::new(vec3(0.0, 1.0, 0.0), vec3(0.0, 0.0, -0.5), vec3(0.0, 1.0, 0.0), 60.0, 4.0 / 3.0));
+    for ray_number in 0..samples {
+        ray_tracer.render(ray_number);
+    }
+    ray_tracer.output_image().to_vec()
+}
+
+fn mean(image: &[[f32; 4]]) -> Vector3<f32>
+{
+    image.iter().fold(vec3(0.0, 0.0, 0.0), |sum, pixel| sum + vec3(pixel[0], pixel[1], pixel[2])) / image.len() as f32
+}
+
+fn rmse(image: &[[f32; 4]], reference: &[[f32; 4]]) -> f32
+{
+    let squared_error: f32 = image.iter().zip(reference.iter())
+        .map(|(a, b)| (0..3).map(|i| (a[i] - b[i]) * (a[i] - b[i])).sum::<f32>())
+        .sum();
+    (squared_error / image.len() as f32).sqrt()
+}
+
+#[test]
+fn constant_sky_lights_a_diffuse_floor()
+{
+    // The floor reflects its albedo times the radiance of the sky covering its hemisphere
+    for &light_sampling in STRATEGIES.iter() {
+        let radiance = mean(&render_floor(EnvironmentMap::constant([1.0, 2.0, 4.0]), light_sampling, 1, 256));
+        let expected = vec3(0.5, 1.0, 2.0);
+        for i in 0..3 {
+            assert!((radiance[i] - expected[i]).abs() < 0.02 * expected[i], "{:?}: {:?}", light_sampling, radiance);
+        }
+    }
+}
+
+#[test]
+fn importance_sampling_finds_the_sun()
+{
+    // BSDF samples rarely hit the sun, their mean is left to converge in constant_sky_lights_a_diffuse_floor
+    let reference = render_floor(sky_with_sun(64, 32), LightSampling::MisPower, 100, 1024);
+    for &light_sampling in [LightSampling::Emitter, LightSampling::MisBalance].iter() {
+        let radiance = mean(&render_floor(sky_with_sun(64, 32), light_sampling, 1, 256));
+        for i in 0..3 {
+            assert!((radiance[i] - mean(&reference)[i]).abs() < 0.03 * mean(&reference)[i], "{:?}: {:?} {:?}", light_sampling, radiance, mean(&reference));
+        }
+    }
+
+    let mean_rmse = |light_sampling| (0..8).map(|seed| rmse(&render_floor(sky_with_sun(64, 32), light_sampling, seed, 4), &reference)).sum::<f32>() / 8.0;
+    let emitter = mean_rmse(LightSampling::Emitter);
+    let bsdf = mean_rmse(LightSampling::Bsdf);
+    let mis = mean_rmse(LightSampling::MisPower);
+    assert!(emitter < 0.25 * bsdf, "{} {}", emitter, bsdf);
+    assert!(mis < 1.25 * emitter, "{} {}", mis, emitter);
+}
@@ -0,0 +1,83 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use metal_ray_tracing_rs::frames::*;
+
+#[test]
+fn semaphore_blocks_until_signalled()
+{
+    let semaphore = Arc::new(Semaphore::new(1));
+    semaphore.wait();
+
+    let (sender, receiver) = mpsc::channel();
+    let waiting = semaphore.clone();
+    let waiter = thread::spawn(move || {
+        waiting.wait();
+        sender.send(()).unwrap();
+    });
+    assert!(receiver.recv_timeout(Duration::from_millis(50)).is_err());
+    semaphore.signal();
+    receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    waiter.join().unwrap();
+}
+
+#[test]
+fn frames_are_used_in_turn()
+{
+    let mut ring = FrameRing::new(vec!['a', 'b', 'c']);
+    let mut frames = Vec::new();
+    for _ in 0..6 {
+        ring.next_frame().signal();
+        frames.push(*ring.current());
+    }
+    assert_eq!(frames, vec!['a', 'b', 'c', 'a', 'b', 'c']);
+}
+
+#[test]
+fn frames_in_flight_are_not_reused()
+{
+    // A GPU thread completes the frames in order after a delay and marks them unused again before signalling
+    let mut ring = FrameRing::new((0..MAX_FRAMES_IN_FLIGHT).map(|_| Arc::new(AtomicBool::new(false))).collect());
+    let (sender, receiver) = mpsc::channel::<(Arc<AtomicBool>, Arc<Semaphore>)>();
+    let gpu = thread::spawn(move || {
+        for (in_flight, available) in receiver {
+            thread::sleep(Duration::from_millis(2));
+            in_flight.store(false, Ordering::SeqCst);
+            available.signal();
+        }
+    });
+
+    for _ in 0..4 * MAX_FRAMES_IN_FLIGHT {
+        let available = ring.next_frame();
+        let in_flight = ring.current().clone();
+        assert!(!in_flight.swap(true, Ordering::SeqCst), "frame {} is still in flight", ring.current_index());
+        sender.send((in_flight, available)).unwrap();
+    }
+    drop(sender);
+    gpu.join().unwrap();
+}
+
+#[test]
+fn idle_waits_for_every_frame_in_flight()
+{
+    let mut ring = FrameRing::new(vec![(); MAX_FRAMES_IN_FLIGHT]);
+    let in_flight: Vec<Arc<Semaphore>> = (0..2).map(|_| ring.next_frame()).collect();
+
+    let (sender, receiver) = mpsc::channel();
+    let waiter = thread::spawn(move || {
+        ring.wait_until_idle();
+        sender.send(()).unwrap();
+        // Every frame can be taken again afterwards
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            ring.next_frame();
+        }
+    });
+    for available in in_flight {
+        assert!(receiver.recv_timeout(Duration::from_millis(50)).is_err());
+        available.signal();
+    }
+    receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    waiter.join().unwrap();
+}
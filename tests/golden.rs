@@ -0,0 +1,154 @@
+use cgmath::vec3;
+use metal_ray_tracing_rs::camera::Camera;
+use metal_ray_tracing_rs::compare::*;
+use metal_ray_tracing_rs::cpu::CpuRayTracer;
+use metal_ray_tracing_rs::environment::EnvironmentMap;
+use metal_ray_tracing_rs::output::*;
+use metal_ray_tracing_rs::scene::Scene;
+use metal_ray_tracing_rs::settings::RenderSettings;
+use metal_ray_tracing_rs::types::*;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+mod common;
+
+// Renders of small canonical scenes compared with the converged reference images in tests/golden. The references
+// are rendered again with
+//     UPDATE_GOLDEN=1 cargo test --release --test golden
+// after a change that is meant to alter the images. When a render differs too much, the render, the reference and
+// a heatmap of the perceived error are written next to the other test output and the paths are in the message.
+
+const SIZE: usize = 32;
+const SAMPLES: usize = 64;
+const REFERENCE_SAMPLES: usize = 4096;
+// Side of the blocks averaged before the RMSE, which leaves the bias and little of the noise
+const BLOCK_SIZE: usize = 8;
+// Calibrated on the noise of renders with SAMPLES against the references, with a margin of about two
+const MAX_MEAN_FLIP: f32 = 0.05;
+const MAX_BLOCK_RMSE: f32 = 0.005;
+
+struct Difference
+{
+    mean_flip: f32,
+    block_rmse: f32,
+    errors: Vec<f32>
+}
+
+impl Difference
+{
+    fn is_acceptable(&self) -> bool
+    {
+        self.mean_flip <= MAX_MEAN_FLIP && self.block_rmse <= MAX_BLOCK_RMSE
+    }
+}
+
+fn difference(image: &[[f32; 4]], reference: &[[f32; 4]]) -> Difference
+{
+    let errors = flip(image, reference, SIZE, SIZE);
+    let mean_flip = errors.iter().sum::<f32>() / errors.len() as f32;
+    let block_rmse = rmse(&downsample(image, SIZE, SIZE, BLOCK_SIZE), &downsample(reference, SIZE, SIZE, BLOCK_SIZE));
+    Difference {mean_flip, block_rmse, errors}
+}
+
+fn render(scene: Scene, camera: &Camera, seed: u64, samples: usize) -> Vec<[f32; 4]>
+{
+    let settings = RenderSettings {seed, ..RenderSettings::default()};
+    let mut ray_tracer = CpuRayTracer::new(scene, SIZE, SIZE, &settings);
+    ray_tracer.set_camera(camera);
+    for ray_number in 0..samples {
+        ray_tracer.render(ray_number);
+    }
+    ray_tracer.output_image().to_vec()
+}
+
+fn reference_path(name: &str) -> PathBuf
+{
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden").join(format!("{}.pfm", name))
+}
+
+fn read_reference(name: &str) -> Vec<[f32; 4]>
+{
+    let path = reference_path(name);
+    let file = File::open(&path).unwrap_or_else(|error| {
+        panic!("cannot open {}: {}, render it with UPDATE_GOLDEN=1 cargo test --release --test golden", path.display(), error)
+    });
+    let (pixels, width, height) = read_pfm(&mut BufReader::new(file)).unwrap();
+    assert_eq!((width, height), (SIZE, SIZE), "{}", path.display());
+    pixels
+}
+
+fn check(name: &str, scene: fn() -> Scene, camera: Camera)
+{
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        let reference = render(scene(), &camera, 1, REFERENCE_SAMPLES);
+        write_image(&reference_path(name), &reference, SIZE, SIZE).unwrap();
+    }
+
+    let image = render(scene(), &camera, 0, SAMPLES);
+    let reference = read_reference(name);
+    let difference = difference(&image, &reference);
+    if !difference.is_acceptable() {
+        let directory = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+        std::fs::create_dir_all(&directory).unwrap();
+        let paths: Vec<PathBuf> = ["render.pfm", "render.png", "reference.png", "flip.png"].iter()
+            .map(|suffix| directory.join(format!("{}_{}", name, suffix))).collect();
+        write_image(&paths[0], &image, SIZE, SIZE).unwrap();
+        write_image(&paths[1], &image, SIZE, SIZE).unwrap();
+        write_image(&paths[2], &reference, SIZE, SIZE).unwrap();
+        write_image(&paths[3], &error_heatmap(&difference.errors), SIZE, SIZE).unwrap();
+        panic!("{} differs from its reference, mean FLIP {:.4} (at most {}), RMSE of {}x{} blocks {:.4} (at most {})\n\
+                images: {}\nif the change is intended, update the reference with UPDATE_GOLDEN=1 cargo test --release --test golden",
+               name, difference.mean_flip, MAX_MEAN_FLIP, BLOCK_SIZE, BLOCK_SIZE, difference.block_rmse, MAX_BLOCK_RMSE,
+               paths.iter().map(|path| path.display().to_string()).collect::<Vec<_>>().join(", "));
+    }
+}
+
+// A diffuse box under a constant white sky. Without other objects every face reflects the sky once, so the box
+// has the radiance of its albedo.
+fn furnace() -> Scene
+{
+    let mut scene = common::empty_scene(vec![Material {diffuse: [0.5, 0.5, 0.5], ..Material::default()}]);
+    common::add_box(&mut scene, [-0.5, -0.5, -0.5], [0.5, 0.5, 0.5], 0);
+    scene.environment = Some(EnvironmentMap::constant([1.0, 1.0, 1.0]));
+    scene
+}
+
+// A grey floor lit by a single square emitter facing down
+fn emitter_plane() -> Scene
+{
+    let floor = Material {diffuse: [0.5, 0.5, 0.5], ..Material::default()};
+    let light = Material {emissive: [4.0, 4.0, 4.0], ..Material::default()};
+    let mut scene = common::empty_scene(vec![floor, light]);
+    common::add_quad(&mut scene, [[-4.0, 0.0, -4.0], [-4.0, 0.0, 4.0], [4.0, 0.0, 4.0], [4.0, 0.0, -4.0]], 0);
+    common::add_quad(&mut scene, [[-0.5, 1.5, -0.5], [0.5, 1.5, -0.5], [0.5, 1.5, 0.5], [-0.5, 1.5, 0.5]], 1);
+    scene
+}
+
+#[test]
+fn cornell_box_matches_reference()
+{
+    check("cornell_box", common::cornell_box, Camera {aspect: 1.0, ..Camera::default()});
+}
+
+#[test]
+fn furnace_matches_reference()
+{
+    check("furnace", furnace, Camera::new(vec3(1.5, 1.2, 2.0), vec3(0.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0), 45.0, 1.0));
+}
+
+#[test]
+fn emitter_plane_matches_reference()
+{
+    check("emitter_plane", emitter_plane, Camera::new(vec3(0.0, 1.0, 3.5), vec3(0.0, 0.7, 0.0), vec3(0.0, 1.0, 0.0), 60.0, 1.0));
+}
+
+#[test]
+fn slightly_brighter_renders_fail()
+{
+    // A bias of five percent is well within the noise of single pixels, but not of the blocks
+    let camera = Camera {aspect: 1.0, ..Camera::default()};
+    let brighter: Vec<[f32; 4]> = render(common::cornell_box(), &camera, 0, SAMPLES).iter()
+        .map(|pixel| [1.05 * pixel[0], 1.05 * pixel[1], 1.05 * pixel[2], 1.0]).collect();
+    assert!(!difference(&brighter, &read_reference("cornell_box")).is_acceptable());
+}
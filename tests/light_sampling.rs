@@ -0,0 +1,103 @@
+use cgmath::vec3;
+use metal_ray_tracing_rs::camera::Camera;
+use metal_ray_tracing_rs::cpu::CpuRayTracer;
+use metal_ray_tracing_rs::scene::Scene;
+use metal_ray_tracing_rs::settings::RenderSettings;
+use metal_ray_tracing_rs::types::*;
+
+mod common;
+
+const STRATEGIES: [LightSampling; 4] = [LightSampling::Emitter, LightSampling::Bsdf, LightSampling::MisBalance, LightSampling::MisPower];
+
+// A glossy floor reflecting a square light above it towards the camera, the scene of Veach's MIS comparison
+fn glossy_plate(roughness: f32, light_size: f32) -> Scene
+{
+    let glossy = Material {material_type: MaterialType::Metal, specular: [0.9, 0.9, 0.9], roughness, ..Material::default()};
+    let light = Material {emissive: [1.0 / (light_size * light_size); 3], ..Material::default()};
+    let mut scene = common::empty_scene(vec![glossy, light]);
+    common::add_quad(&mut scene, [[-4.0, 0.0, -4.0], [-4.0, 0.0, 4.0], [4.0, 0.0, 4.0], [4.0, 0.0, -4.0]], 0);
+    let h = 0.5 * light_size;
+    common::add_quad(&mut scene, [[-h, 2.0, -h], [h, 2.0, -h], [h, 2.0, h], [-h, 2.0, h]], 1);
+    scene
+}
+
+// Direct light only, the camera looks at the reflection of the light in the plate
+fn render(scene: Scene, light_sampling: LightSampling, seed: u64, samples: usize) -> Vec<[f32; 4]>
+{
+    let settings = RenderSettings {seed, light_sampling, max_depth: 2, ..RenderSettings::default()};
+    let mut ray_tracer = CpuRayTracer::new(scene, 16, 12, &settings);
+    ray_tracer.set_camera(&Camera::new(vec3(0.0, 1.0, 2.0), vec3(0.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0), 60.0, 4.0 / 3.0));
+    for ray_number in 0..samples {
+        ray_tracer.render(ray_number);
+    }
+    ray_tracer.output_image().to_vec()
+}
+
+fn render_cornell_box(light_sampling: LightSampling) -> Vec<[f32; 4]>
+{
+    let settings = RenderSettings {light_sampling, ..RenderSettings::default()};
+    let mut ray_tracer = CpuRayTracer::new(common::cornell_box(), 16, 12, &settings);
+    for ray_number in 0..256 {
+        ray_tracer.render(ray_number);
+    }
+    ray_tracer.output_image().to_vec()
+}
+
+fn mean(image: &[[f32; 4]]) -> f32
+{
+    image.iter().map(|pixel| pixel[0] + pixel[1] + pixel[2]).sum::<f32>() / image.len() as f32
+}
+
+fn rmse(image: &[[f32; 4]], reference: &[[f32; 4]]) -> f32
+{
+    let squared_error: f32 = image.iter().zip(reference.iter())
+        .map(|(a, b)| (0..3).map(|i| (a[i] - b[i]) * (a[i] - b[i])).sum::<f32>())
+        .sum();
+    (squared_error / image.len() as f32).sqrt()
+}
+
+// Average error of low sample count renders against a converged reference
+fn mean_rmse(scene: fn() -> Scene, light_sampling: LightSampling, reference: &[[f32; 4]]) -> f32
+{
+    (0..8).map(|seed| rmse(&render(scene(), light_sampling, seed, 4), reference)).sum::<f32>() / 8.0
+}
+
+#[test]
+fn strategies_converge_to_the_same_image()
+{
+    let reference = mean(&render(glossy_plate(0.1, 0.5), LightSampling::MisPower, 100, 2048));
+    for &light_sampling in STRATEGIES.iter() {
+        let radiance = mean(&render(glossy_plate(0.1, 0.5), light_sampling, 1, 1024));
+        assert!((radiance - reference).abs() < 0.04 * reference, "{:?}: {} {}", light_sampling, radiance, reference);
+    }
+}
+
+#[test]
+fn multiple_importance_sampling_is_close_to_the_best_strategy()
+{
+    // A large light in a sharp reflection favours BSDF sampling, a small light on a rough surface emitter sampling
+    let scenes: [fn() -> Scene; 2] = [|| glossy_plate(0.01, 2.0), || glossy_plate(0.3, 0.1)];
+    for scene in scenes.iter() {
+        let reference = render(scene(), LightSampling::MisPower, 100, 1024);
+        let emitter = mean_rmse(*scene, LightSampling::Emitter, &reference);
+        let bsdf = mean_rmse(*scene, LightSampling::Bsdf, &reference);
+        let best = emitter.min(bsdf);
+        assert!(emitter.max(bsdf) > 4.0 * best, "{} {}", emitter, bsdf);
+
+        for &light_sampling in [LightSampling::MisBalance, LightSampling::MisPower].iter() {
+            let error = mean_rmse(*scene, light_sampling, &reference);
+            assert!(error < 1.25 * best, "{:?}: {} {} {}", light_sampling, error, emitter, bsdf);
+        }
+    }
+}
+
+#[test]
+fn emitters_hit_by_bsdf_samples_are_counted_once()
+{
+    // Without the MIS weight on emitter hits the diffuse Cornell box would be brighter than with emitter sampling alone
+    let emitter = mean(&render_cornell_box(LightSampling::Emitter));
+    for &light_sampling in [LightSampling::MisBalance, LightSampling::MisPower].iter() {
+        let radiance = mean(&render_cornell_box(light_sampling));
+        assert!((radiance - emitter).abs() < 0.02 * emitter, "{:?}: {} {}", light_sampling, radiance, emitter);
+    }
+}
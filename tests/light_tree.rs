@@ -0,0 +1,182 @@
+use cgmath::*;
+use mersenne_twister::MT19937;
+use rand::{Rng, SeedableRng};
+use metal_ray_tracing_rs::camera::Camera;
+use metal_ray_tracing_rs::cpu::CpuRayTracer;
+use metal_ray_tracing_rs::emitters::NO_EMITTER;
+use metal_ray_tracing_rs::light_tree::*;
+use metal_ray_tracing_rs::scene::Scene;
+use metal_ray_tracing_rs::settings::RenderSettings;
+use metal_ray_tracing_rs::types::*;
+
+mod common;
+
+// A large floor below a grid of 8 x 8 small lights of varying brightness, every other one facing up
+fn many_lights() -> Scene
+{
+    let floor = Material {diffuse: [0.8, 0.8, 0.8], ..Material::default()};
+    let dim = Material {emissive: [5.0, 5.0, 5.0], ..Material::default()};
+    let bright = Material {emissive: [20.0, 15.0, 10.0], ..Material::default()};
+    let mut scene = common::empty_scene(vec![floor, dim, bright]);
+    common::add_quad(&mut scene, [[-8.0, 0.0, -8.0], [-8.0, 0.0, 8.0], [8.0, 0.0, 8.0], [8.0, 0.0, -8.0]], 0);
+    for i in 0..8 {
+        for j in 0..8 {
+            let (x, z) = (-7.0 + 2.0 * i as f32, -7.0 + 2.0 * j as f32);
+            let material = if (i * 3 + j) % 5 == 0 { 2 } else { 1 };
+            let (h, y) = (0.1, 0.5);
+            if (i + j) % 2 == 0 {
+                common::add_quad(&mut scene, [[x - h, y, z - h], [x + h, y, z - h], [x + h, y, z + h], [x - h, y, z + h]], material);
+            } else {
+                common::add_quad(&mut scene, [[x - h, y, z - h], [x - h, y, z + h], [x + h, y, z + h], [x + h, y, z - h]], material);
+            }
+        }
+    }
+    scene
+}
+
+fn random_point(rng: &mut MT19937) -> Vector3<f32>
+{
+    vec3(16.0 * rng.next_f32() - 8.0, 2.0 * rng.next_f32(), 16.0 * rng.next_f32() - 8.0)
+}
+
+fn random_normal(rng: &mut MT19937) -> Vector3<f32>
+{
+    vec3(rng.next_f32() - 0.5, rng.next_f32() - 0.5, rng.next_f32() - 0.5).normalize()
+}
+
+#[test]
+fn trails_lead_to_the_leaf_of_every_emitter()
+{
+    let scene = many_lights();
+    let tree = LightTree::new(&scene);
+    assert_eq!(tree.nodes.len(), 2 * scene.emitter_triangles.len() - 1);
+
+    for (emitter_index, &trail) in tree.emitter_trails.iter().enumerate() {
+        let (mut node_index, mut trail) = (0, trail);
+        while tree.nodes[node_index].emitter_index == NO_EMITTER {
+            node_index = if trail & 1 == 0 { node_index + 1 } else { tree.nodes[node_index].second_child as usize };
+            trail >>= 1;
+        }
+        assert_eq!(tree.nodes[node_index].emitter_index, emitter_index as u32);
+    }
+}
+
+#[test]
+fn nodes_bound_their_emitters()
+{
+    let scene = many_lights();
+    let tree = LightTree::new(&scene);
+    for (emitter_index, &trail) in tree.emitter_trails.iter().enumerate() {
+        let [a, b, c] = scene.triangle_vertices(scene.emitter_triangles[emitter_index].primitive_index);
+        let normal = (b - a).cross(c - a).normalize();
+        let (mut node_index, mut trail) = (0, trail);
+        loop {
+            let node = &tree.nodes[node_index];
+            for vertex in [a, b, c].iter() {
+                for axis in 0..3 {
+                    assert!(node.bounds_min[axis] <= vertex[axis] && vertex[axis] <= node.bounds_max[axis]);
+                }
+            }
+            assert!(Vector3::from(node.axis).dot(normal) >= node.cos_theta_o - 1e-5, "{:?} {:?}", node, normal);
+            if node.emitter_index != NO_EMITTER {
+                break;
+            }
+            node_index = if trail & 1 == 0 { node_index + 1 } else { node.second_child as usize };
+            trail >>= 1;
+        }
+    }
+}
+
+#[test]
+fn probabilities_sum_to_one()
+{
+    let scene = many_lights();
+    let tree = LightTree::new(&scene);
+    let mut rng: MT19937 = SeedableRng::from_seed(1u64);
+    for _ in 0..100 {
+        let point = random_point(&mut rng);
+        let normal = random_normal(&mut rng);
+        let total: f32 = (0..scene.emitter_triangles.len()).map(|index| tree.pdf(point, normal, index)).sum();
+        assert!((total - 1.0).abs() < 1e-4, "{:?}: {}", point, total);
+    }
+}
+
+#[test]
+fn samples_match_the_pdf()
+{
+    let scene = many_lights();
+    let tree = LightTree::new(&scene);
+    let mut rng: MT19937 = SeedableRng::from_seed(2u64);
+    for _ in 0..10 {
+        let point = random_point(&mut rng);
+        let normal = random_normal(&mut rng);
+        let count = 20_000;
+        let mut histogram = vec![0; scene.emitter_triangles.len()];
+        for i in 0..count {
+            let (index, pdf) = tree.sample(point, normal, (i as f32 + 0.5) / count as f32).unwrap();
+            assert!((pdf - tree.pdf(point, normal, index)).abs() <= 1e-5 * pdf);
+            histogram[index] += 1;
+        }
+        for (index, &hits) in histogram.iter().enumerate() {
+            let expected = tree.pdf(point, normal, index);
+            assert!((hits as f32 / count as f32 - expected).abs() < 2e-3, "{}: {} {}", index, hits, expected);
+        }
+    }
+}
+
+#[test]
+fn emitters_facing_away_are_not_sampled()
+{
+    let scene = many_lights();
+    let tree = LightTree::new(&scene);
+    // Below the lights every other one faces away, above them the others
+    let below = vec3(0.5, 0.0, 0.5);
+    let above = vec3(0.5, 1.0, 0.5);
+    let up = vec3(0.0, 1.0, 0.0);
+    for (index, emitter) in scene.emitter_triangles.iter().enumerate() {
+        let [a, b, c] = scene.triangle_vertices(emitter.primitive_index);
+        let faces_down = (b - a).cross(c - a).y < 0.0;
+        assert_eq!(tree.pdf(below, up, index) > 0.0, faces_down);
+        assert_eq!(tree.pdf(above, up, index) > 0.0, !faces_down);
+    }
+}
+
+// Direct light of the floor below the lights seen from just above it
+fn render(emitter_selection: EmitterSelection, seed: u64, samples: usize) -> Vec<[f32; 4]>
+{
+    let settings = RenderSettings {seed, max_depth: 1, emitter_selection, ..RenderSettings::default()};
+    let mut ray_tracer = CpuRayTracer::new(many_lights(), 16, 12, &settings);
+    ray_tracer.set_camera(&Camera::new(vec3(0.0, 0.4, 6.0), vec3(0.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0), 60.0, 4.0 / 3.0));
+    for ray_number in 0..samples {
+        ray_tracer.render(ray_number);
+    }
+    ray_tracer.output_image().to_vec()
+}
+
+fn mean(image: &[[f32; 4]]) -> f32
+{
+    image.iter().map(|pixel| pixel[0] + pixel[1] + pixel[2]).sum::<f32>() / image.len() as f32
+}
+
+fn rmse(image: &[[f32; 4]], reference: &[[f32; 4]]) -> f32
+{
+    let squared_error: f32 = image.iter().zip(reference.iter())
+        .map(|(a, b)| (0..3).map(|i| (a[i] - b[i]) * (a[i] - b[i])).sum::<f32>())
+        .sum();
+    (squared_error / image.len() as f32).sqrt()
+}
+
+#[test]
+fn light_tree_reduces_variance()
+{
+    // Both converge to the same image, selecting by power takes many more samples
+    let reference = render(EmitterSelection::LightTree, 100, 1024);
+    let power_reference = mean(&render(EmitterSelection::Power, 101, 4096));
+    assert!((mean(&reference) - power_reference).abs() < 0.05 * power_reference, "{} {}", mean(&reference), power_reference);
+
+    let mean_rmse = |emitter_selection| (0..8).map(|seed| rmse(&render(emitter_selection, seed, 4), &reference)).sum::<f32>() / 8.0;
+    let power = mean_rmse(EmitterSelection::Power);
+    let tree = mean_rmse(EmitterSelection::LightTree);
+    assert!(tree < 0.5 * power, "{} {}", tree, power);
+}
+
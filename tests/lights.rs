@@ -0,0 +1,219 @@
+use cgmath::*;
+use mersenne_twister::MT19937;
+use rand::{Rng, SeedableRng};
+use std::f32::consts::PI;
+use metal_ray_tracing_rs::camera::Camera;
+use metal_ray_tracing_rs::cpu::CpuRayTracer;
+use metal_ray_tracing_rs::emitters::EmitterDistribution;
+use metal_ray_tracing_rs::environment::EnvironmentMap;
+use metal_ray_tracing_rs::lights;
+use metal_ray_tracing_rs::scene::*;
+use metal_ray_tracing_rs::settings::RenderSettings;
+use metal_ray_tracing_rs::types::*;
+
+mod common;
+
+const STRATEGIES: [LightSampling; 4] = [LightSampling::Emitter, LightSampling::Bsdf, LightSampling::MisBalance, LightSampling::MisPower];
+const ALBEDO: f32 = 0.5;
+
+fn point(position: [f32; 3], intensity: [f32; 3]) -> Light
+{
+    Light {light_type: LightType::Point, position, color: intensity, ..Light::default()}
+}
+
+fn directional(direction: [f32; 3], angular_radius: f32, irradiance: [f32; 3]) -> Light
+{
+    Light {light_type: LightType::Directional, direction: Vector3::from(direction).normalize().into(), cos_outer: angular_radius.to_radians().cos(), color: irradiance, ..Light::default()}
+}
+
+fn sphere(position: [f32; 3], radius: f32, radiance: [f32; 3]) -> Light
+{
+    Light {light_type: LightType::Sphere, position, radius, color: radiance, ..Light::default()}
+}
+
+#[test]
+fn parses_lights_from_the_scene_file()
+{
+    let source = "
+v 0 0 0
+light point 0 2 0 10 10 10
+light spot 1 2 3 0 -2 0 10 20 5 4 3
+# light sphere commented out
+light directional 0 -1 -1 0.25 3 3 3
+light sphere 0 1 0 0.5 1 2 3
+";
+    let lights = parse_lights(source).unwrap();
+    assert_eq!(lights.len(), 4);
+    assert_eq!(lights[0], point([0.0, 2.0, 0.0], [10.0; 3]));
+
+    assert_eq!(lights[1].light_type, LightType::Spot);
+    assert_eq!(lights[1].direction, [0.0, -1.0, 0.0]);
+    assert!((lights[1].cos_inner - 10f32.to_radians().cos()).abs() < 1e-6);
+    assert!((lights[1].cos_outer - 20f32.to_radians().cos()).abs() < 1e-6);
+    assert_eq!(lights[1].color, [5.0, 4.0, 3.0]);
+
+    assert_eq!(lights[2].light_type, LightType::Directional);
+    assert!((Vector3::from(lights[2].direction) - vec3(0.0, -1.0, -1.0).normalize()).magnitude() < 1e-6);
+    assert!((lights[2].cos_outer - 0.25f32.to_radians().cos()).abs() < 1e-6);
+
+    assert_eq!(lights[3], sphere([0.0, 1.0, 0.0], 0.5, [1.0, 2.0, 3.0]));
+}
+
+#[test]
+fn reports_malformed_lights()
+{
+    let sources = ["light point 0 2 0 1 1", "light area 0 0 0 1 1 1", "light spot 0 0 0 0 -1 0 30 20 1 1 1", "light directional 0 0 0 1 1 1 1",
+                   "light sphere 0 0 0 -1 1 1 1", "light point 0 0 0 1 -1 1", "light point 0 x 0 1 1 1"];
+    for source in sources.iter() {
+        let error = parse_lights(&format!("v 0 0 0\n{}\n", source)).unwrap_err();
+        assert_eq!(error.to_string(), format!("invalid light on line 2: '{}'", source));
+    }
+}
+
+#[test]
+fn loads_lights_with_the_scene()
+{
+    let path = std::env::temp_dir().join("metal-ray-tracing-rs-lights.obj");
+    std::fs::write(&path, "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\nlight sphere 0 1 0 0.5 1 1 1\n").unwrap();
+    let scene = Scene::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(scene.triangle_count(), 1);
+    assert_eq!(scene.lights, vec![sphere([0.0, 1.0, 0.0], 0.5, [1.0; 3])]);
+}
+
+#[test]
+fn sphere_samples_cover_the_visible_cap()
+{
+    let light = sphere([0.0, 2.0, 0.0], 0.5, [1.0; 3]);
+    let origin = vec3(0.3, 0.0, -0.2);
+    let mut rng: MT19937 = SeedableRng::from_seed(1u64);
+    for _ in 0..1000 {
+        let sample = lights::sample(&light, origin, vec2(rng.next_f32(), rng.next_f32())).unwrap();
+        assert!(!sample.delta);
+        assert!((sample.pdf - lights::pdf(&light, origin)).abs() < 1e-6 * sample.pdf);
+        let distance = lights::intersect_sphere(&light, origin, sample.direction).unwrap_or(sample.distance);
+        assert!((distance - sample.distance).abs() < 1e-3, "{} {}", distance, sample.distance);
+    }
+
+    // The density is one over the solid angle of the cap, found by counting uniform directions that hit it
+    let count = 400_000;
+    let hits = (0..count).filter(|_| {
+        let z = 1.0 - 2.0 * rng.next_f32();
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * PI * rng.next_f32();
+        lights::intersect_sphere(&light, origin, vec3(r * phi.cos(), z, r * phi.sin())).is_some()
+    }).count();
+    let solid_angle = 4.0 * PI * hits as f32 / count as f32;
+    assert!((solid_angle * lights::pdf(&light, origin) - 1.0).abs() < 0.03, "{}", solid_angle * lights::pdf(&light, origin));
+
+    // Nothing to sample from inside
+    assert!(lights::sample(&light, vec3(0.0, 2.1, 0.0), vec2(0.5, 0.5)).is_none());
+    assert_eq!(lights::pdf(&light, vec3(0.0, 2.1, 0.0)), 0.0);
+}
+
+#[test]
+fn spot_lights_fall_off_between_their_cones()
+{
+    let light = Light {light_type: LightType::Spot, position: [0.0, 1.0, 0.0], direction: [0.0, -1.0, 0.0], cos_inner: 10f32.to_radians().cos(),
+        cos_outer: 20f32.to_radians().cos(), color: [4.0; 3], ..Light::default()};
+    // Intensity towards the floor one unit below at the angle from the axis
+    let at_angle = |degrees: f32| {
+        let x = degrees.to_radians().tan();
+        lights::sample(&light, vec3(x, 0.0, 0.0), vec2(0.5, 0.5)).map_or(0.0, |sample| sample.radiance.x * (1.0 + x * x))
+    };
+    assert!((at_angle(0.0) - 4.0).abs() < 1e-5);
+    assert!((at_angle(9.0) - 4.0).abs() < 1e-4);
+    assert!(at_angle(15.0) > 0.0 && at_angle(15.0) < 4.0);
+    assert_eq!(at_angle(21.0), 0.0);
+}
+
+#[test]
+fn next_event_estimation_splits_between_kinds_of_light()
+{
+    let light = Material {emissive: [1.0; 3], ..Material::default()};
+    let mut scene = common::empty_scene(vec![light]);
+    common::add_quad(&mut scene, [[-1.0, 2.0, -1.0], [1.0, 2.0, -1.0], [1.0, 2.0, 1.0], [-1.0, 2.0, 1.0]], 0);
+    scene.lights = vec![point([0.0, 1.0, 0.0], [1.0; 3]), point([0.0, 1.0, 0.0], [3.0; 3]), point([0.0, 1.0, 0.0], [0.0; 3])];
+    scene.environment = Some(EnvironmentMap::constant([1.0; 3]));
+
+    let emitters = EmitterDistribution::new(&scene);
+    assert!((emitters.environment_probability - 1.0 / 3.0).abs() < 1e-6);
+    assert!((emitters.light_probability - 1.0 / 3.0).abs() < 1e-6);
+    assert!((emitters.triangle_probability() - 1.0 / 3.0).abs() < 1e-6);
+    let pdfs: Vec<f32> = emitters.light_table.iter().map(|entry| entry.pdf).collect();
+    assert!((pdfs[0] - 0.25).abs() < 1e-6 && (pdfs[1] - 0.75).abs() < 1e-6 && pdfs[2] == 0.0, "{:?}", pdfs);
+
+    scene.environment = None;
+    scene.emitter_triangles.clear();
+    let emitters = EmitterDistribution::new(&scene);
+    assert_eq!((emitters.environment_probability, emitters.light_probability), (0.0, 1.0));
+}
+
+// A large diffuse floor lit by the lights, seen at the origin through a narrow field of view
+fn render_floor(lights: Vec<Light>, light_sampling: LightSampling, samples: usize) -> Vector3<f32>
+{
+    let floor = Material {diffuse: [ALBEDO; 3], ..Material::default()};
+    let mut scene = common::empty_scene(vec![floor]);
+    common::add_quad(&mut scene, [[-100.0, 0.0, -100.0], [-100.0, 0.0, 100.0], [100.0, 0.0, 100.0], [100.0, 0.0, -100.0]], 0);
+    scene.lights = lights;
+
+    let settings = RenderSettings {seed: 1, light_sampling, max_depth: 2, ..RenderSettings::default()};
+    let mut ray_tracer = CpuRayTracer::new(scene, 8, 6, &settings);
+    ray_tracer.set_camera(&Camera::new(vec3(4.0, 1.0, 0.0), vec3(0.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0), 0.5, 4.0 / 3.0));
+    for ray_number in 0..samples {
+        ray_tracer.render(ray_number);
+    }
+    let image = ray_tracer.output_image();
+    image.iter().fold(vec3(0.0, 0.0, 0.0), |sum, pixel| sum + vec3(pixel[0], pixel[1], pixel[2])) / image.len() as f32
+}
+
+fn assert_close(radiance: Vector3<f32>, expected: Vector3<f32>, tolerance: f32, name: &str)
+{
+    for i in 0..3 {
+        assert!((radiance[i] - expected[i]).abs() <= tolerance * expected[i], "{}: {:?} {:?}", name, radiance, expected);
+    }
+}
+
+#[test]
+fn delta_lights_match_the_inverse_square_law()
+{
+    // Radiance of the floor is albedo / pi times the irradiance, which BSDF sampling cannot find
+    let radiance = render_floor(vec![point([0.0, 2.0, 0.0], [8.0, 4.0, 2.0])], LightSampling::MisPower, 4);
+    assert_close(radiance, vec3(8.0, 4.0, 2.0) * (ALBEDO / PI / 4.0), 1e-3, "point");
+
+    let radiance = render_floor(vec![directional([0.0, -1.0, -1.0], 0.0, [2.0, 2.0, 2.0])], LightSampling::MisPower, 4);
+    assert_close(radiance, vec3(2.0, 2.0, 2.0) * (ALBEDO / PI * 0.5f32.sqrt()), 1e-3, "directional");
+
+    assert_eq!(render_floor(vec![point([0.0, 2.0, 0.0], [8.0, 4.0, 2.0])], LightSampling::Bsdf, 4), vec3(0.0, 0.0, 0.0));
+}
+
+#[test]
+fn area_lights_converge_for_every_strategy()
+{
+    // A sphere of radiance L sends pi L sin^2 of its angular radius to a point facing its centre, the sun spread
+    // over a cone arrives with an average cosine of (1 + cos) / 2
+    let sun = directional([0.0, -1.0, 0.0], 20.0, [2.0, 2.0, 2.0]);
+    let sun_irradiance = 2.0 * 0.5 * (1.0 + 20f32.to_radians().cos());
+    for &light_sampling in STRATEGIES.iter() {
+        let radiance = render_floor(vec![sphere([0.0, 2.0, 0.0], 1.0, [4.0, 2.0, 1.0])], light_sampling, 2048);
+        assert_close(radiance, vec3(4.0, 2.0, 1.0) * (ALBEDO * 0.5 * 0.5), 0.03, &format!("sphere {:?}", light_sampling));
+
+        let radiance = render_floor(vec![sun], light_sampling, 2048);
+        assert_close(radiance, vec3(1.0, 1.0, 1.0) * (ALBEDO / PI * sun_irradiance), 0.03, &format!("sun {:?}", light_sampling));
+    }
+}
+
+#[test]
+fn spheres_are_visible_and_do_not_reflect()
+{
+    let light = sphere([0.0, 0.0, 0.0], 1.0, [3.0, 2.0, 1.0]);
+    let settings = RenderSettings {max_depth: 3, ..RenderSettings::default()};
+    let mut scene = common::empty_scene(vec![]);
+    scene.lights = vec![light];
+    let mut ray_tracer = CpuRayTracer::new(scene, 4, 4, &settings);
+    ray_tracer.set_camera(&Camera::new(vec3(0.0, 0.0, 5.0), vec3(0.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0), 5.0, 1.0));
+    ray_tracer.render(0);
+    for pixel in ray_tracer.output_image() {
+        assert_eq!(pixel[..3], [3.0, 2.0, 1.0]);
+    }
+}
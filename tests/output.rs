@@ -0,0 +1,79 @@
+use std::path::Path;
+use metal_ray_tracing_rs::output::*;
+
+// 2x2 image with a red bottom row and a white top row
+const PIXELS: [[f32; 4]; 4] = [[1.0, 0.0, 0.0, 1.0], [0.25, 0.0, 0.0, 1.0], [1.0, 1.0, 1.0, 1.0], [2.0, 2.0, 2.0, 1.0]];
+
+#[test]
+fn detects_format_from_extension()
+{
+    assert_eq!(ImageFormat::from_path(Path::new("render.png")), Some(ImageFormat::Png));
+    assert_eq!(ImageFormat::from_path(Path::new("render.PFM")), Some(ImageFormat::Pfm));
+    assert_eq!(ImageFormat::from_path(Path::new("out/render.exr")), Some(ImageFormat::Exr));
+    assert_eq!(ImageFormat::from_path(Path::new("render.jpg")), None);
+    assert_eq!(ImageFormat::from_path(Path::new("render")), None);
+}
+
+#[test]
+fn applies_srgb_transfer_function()
+{
+    assert_eq!(linear_to_srgb(0.0), 0);
+    assert_eq!(linear_to_srgb(1.0), 255);
+    assert_eq!(linear_to_srgb(0.2159), 128);
+    assert_eq!(linear_to_srgb(-1.0), 0);
+    assert_eq!(linear_to_srgb(10.0), 255);
+    assert_eq!(linear_to_srgb(f32::NAN), 0);
+}
+
+#[test]
+fn writes_pfm_bottom_up()
+{
+    let mut bytes = Vec::new();
+    write_pfm(&mut bytes, &PIXELS, 2, 2).unwrap();
+
+    let header = b"PF\n2 2\n-1.0\n";
+    assert_eq!(&bytes[..header.len()], header);
+    let values: Vec<f32> = bytes[header.len()..].chunks(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+    assert_eq!(values, vec![1.0, 0.0, 0.0, 0.25, 0.0, 0.0, 1.0, 1.0, 1.0, 2.0, 2.0, 2.0]);
+}
+
+#[test]
+fn reads_pfm_in_both_byte_orders()
+{
+    let mut bytes = Vec::new();
+    write_pfm(&mut bytes, &PIXELS, 2, 2).unwrap();
+    assert_eq!(read_pfm(&mut bytes.as_slice()).unwrap(), (PIXELS.to_vec(), 2, 2));
+
+    let mut big_endian = b"PF\n1 1\n1.0\n".to_vec();
+    for value in [0.5f32, 2.0, -1.0].iter() {
+        big_endian.extend_from_slice(&value.to_be_bytes());
+    }
+    assert_eq!(read_pfm(&mut big_endian.as_slice()).unwrap(), (vec![[0.5, 2.0, -1.0, 1.0]], 1, 1));
+
+    let error = read_pfm(&mut b"Pf\n1 1\n-1.0\n".as_ref()).unwrap_err();
+    assert_eq!(error.to_string(), "invalid pfm: expected a colour image starting with 'PF'");
+    assert!(read_pfm(&mut b"PF\n2 2\n-1.0\n\0\0\0\0".as_ref()).is_err());
+}
+
+#[test]
+fn writes_png_and_exr_top_down()
+{
+    let directory = std::env::temp_dir().join(format!("metal-ray-tracing-rs-output-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+
+    let png_path = directory.join("render.png");
+    write_image(&png_path, &PIXELS, 2, 2).unwrap();
+    let png = image::open(&png_path).unwrap().to_rgb8();
+    assert_eq!(png.get_pixel(0, 0).0, [255, 255, 255]);
+    assert_eq!(png.get_pixel(0, 1).0, [255, 0, 0]);
+    assert_eq!(png.get_pixel(1, 1).0, [linear_to_srgb(0.25), 0, 0]);
+
+    let exr_path = directory.join("render.exr");
+    write_image(&exr_path, &PIXELS, 2, 2).unwrap();
+    let exr = image::open(&exr_path).unwrap().to_rgba32f();
+    assert_eq!(exr.get_pixel(1, 0).0, [2.0, 2.0, 2.0, 1.0]);
+    assert_eq!(exr.get_pixel(1, 1).0, [0.25, 0.0, 0.0, 1.0]);
+
+    assert!(write_image(&directory.join("render.bmp"), &PIXELS, 2, 2).is_err());
+    std::fs::remove_dir_all(&directory).unwrap();
+}
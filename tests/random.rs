@@ -0,0 +1,130 @@
+use cgmath::*;
+use metal_ray_tracing_rs::camera::Camera;
+use metal_ray_tracing_rs::cpu::CpuRayTracer;
+use metal_ray_tracing_rs::environment::EnvironmentMap;
+use metal_ray_tracing_rs::random::*;
+use metal_ray_tracing_rs::settings::RenderSettings;
+use metal_ray_tracing_rs::types::*;
+
+mod common;
+
+fn pearson(a: &[f32], b: &[f32]) -> f32
+{
+    let n = a.len() as f32;
+    let (mean_a, mean_b) = (a.iter().sum::<f32>() / n, b.iter().sum::<f32>() / n);
+    let covariance: f32 = a.iter().zip(b.iter()).map(|(x, y)| (x - mean_a) * (y - mean_b)).sum();
+    let variance_a: f32 = a.iter().map(|x| (x - mean_a) * (x - mean_a)).sum();
+    let variance_b: f32 = b.iter().map(|y| (y - mean_b) * (y - mean_b)).sum();
+    covariance / (variance_a * variance_b).sqrt()
+}
+
+// Pearson's chi-squared statistic of the counts against equally likely bins
+fn chi_squared(counts: &[usize]) -> f32
+{
+    let expected = counts.iter().sum::<usize>() as f32 / counts.len() as f32;
+    counts.iter().map(|&count| (count as f32 - expected) * (count as f32 - expected) / expected).sum()
+}
+
+#[test]
+fn hash_is_deterministic_and_depends_on_every_input()
+{
+    assert_eq!(random3(7, (3, 4), 5, 6), random3(7, (3, 4), 5, 6));
+    let reference = random3(7, (3, 4), 5, 6);
+    for other in [random3(8, (3, 4), 5, 6), random3(7, (4, 4), 5, 6), random3(7, (3, 5), 5, 6), random3(7, (3, 4), 6, 6),
+                  random3(7, (3, 4), 5, 7)].iter() {
+        assert_ne!(reference, *other);
+    }
+    assert_ne!(hash_seed(0), hash_seed(1));
+    assert_ne!(hash_seed(1), hash_seed(1 << 32));
+
+    assert_eq!(to_unit_float(0), 0.0);
+    assert!(to_unit_float(u32::MAX) < 1.0);
+}
+
+#[test]
+fn numbers_are_uniform()
+{
+    // 64 bins have 63 degrees of freedom, the 99.9% quantile of chi-squared is about 104
+    let mut counts = [[0; 64]; 3];
+    for y in 0..64 {
+        for x in 0..64 {
+            for sample in 0..16 {
+                let u = random3(hash_seed(0), (x, y), sample, 0);
+                for i in 0..3 {
+                    counts[i][(u[i] * 64.0) as usize] += 1;
+                }
+            }
+        }
+    }
+    for dimension in counts.iter() {
+        assert!(chi_squared(dimension) < 104.0, "{}", chi_squared(dimension));
+    }
+}
+
+#[test]
+fn pixels_samples_and_dimensions_are_uncorrelated()
+{
+    // The old noise tile repeated every 16 pixels and within a frame every pixel of the tile shared the same sample
+    // Each pair compares the first number of a pixel's first sample with the number at an offset in pixels, samples,
+    // layers and components
+    let seed = hash_seed(0);
+    let count = 16384;
+    let offsets = [("neighbours", (1, 0, 0, 0, 0)), ("tile", (16, 0, 0, 0, 0)), ("rows", (0, 16, 0, 0, 0)), ("samples", (0, 0, 1, 0, 0)),
+                   ("layers", (0, 0, 0, 1, 0)), ("components", (0, 0, 0, 0, 1))];
+    // Three standard deviations of the correlation of independent samples
+    let bound = 3.0 / (count as f32).sqrt();
+    for (name, (dx, dy, sample, layer, component)) in offsets.iter() {
+        let (a, b): (Vec<f32>, Vec<f32>) = (0..count).map(|i| {
+            let (x, y) = (i % 128, i / 128);
+            (random3(seed, (x, y), 0, 0).x, random3(seed, (x + dx, y + dy), *sample, *layer)[*component])
+        }).unzip();
+        let correlation = pearson(&a, &b);
+        assert!(correlation.abs() < bound, "{}: {}", name, correlation);
+    }
+}
+
+#[test]
+fn neighbouring_pixels_are_jointly_uniform()
+{
+    // 16 x 16 bins have 255 degrees of freedom, the 99.9% quantile of chi-squared is about 330
+    let seed = hash_seed(3);
+    let mut counts = vec![0; 16 * 16];
+    for y in 0..128 {
+        for x in 0..128 {
+            for sample in 0..4 {
+                let a = random3(seed, (x, y), sample, 2).x;
+                let b = random3(seed, (x + 1, y), sample, 2).x;
+                counts[(a * 16.0) as usize + 16 * (b * 16.0) as usize] += 1;
+            }
+        }
+    }
+    assert!(chi_squared(&counts) < 330.0, "{}", chi_squared(&counts));
+}
+
+#[test]
+fn rendered_pixels_are_independent()
+{
+    // A single sample of a constant sky over a diffuse floor, every pixel sees the same expected radiance
+    let floor = Material {diffuse: [0.5, 0.5, 0.5], ..Material::default()};
+    let mut scene = common::empty_scene(vec![floor]);
+    common::add_quad(&mut scene, [[-1000.0, 0.0, -1000.0], [-1000.0, 0.0, 1000.0], [1000.0, 0.0, 1000.0], [1000.0, 0.0, -1000.0]], 0);
+    scene.environment = Some(EnvironmentMap::constant([1.0, 1.0, 1.0]));
+    let settings = RenderSettings {sampler: SamplerType::Independent, light_sampling: LightSampling::Emitter, max_depth: 1, ..RenderSettings::default()};
+    let (width, height) = (128, 64);
+    let mut ray_tracer = CpuRayTracer::new(scene, width, height, &settings);
+    ray_tracer.set_camera(&Camera::new(vec3(0.0, 1.0, 0.0), vec3(0.0, 0.0, -0.01), vec3(0.0, 0.0, -1.0), 30.0, 2.0));
+    ray_tracer.render(0);
+    let image = ray_tracer.output_image();
+
+    let (mut a, mut b, mut c) = (Vec::new(), Vec::new(), Vec::new());
+    for y in 0..height {
+        for x in 0..width - 16 {
+            a.push(image[x + y * width][0]);
+            b.push(image[x + 1 + y * width][0]);
+            c.push(image[x + 16 + y * width][0]);
+        }
+    }
+    let bound = 3.0 / (a.len() as f32).sqrt();
+    assert!(pearson(&a, &b).abs() < bound, "{}", pearson(&a, &b));
+    assert!(pearson(&a, &c).abs() < bound, "{}", pearson(&a, &c));
+}
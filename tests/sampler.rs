@@ -0,0 +1,197 @@
+use cgmath::*;
+use metal_ray_tracing_rs::camera::Camera;
+use metal_ray_tracing_rs::cpu::CpuRayTracer;
+use metal_ray_tracing_rs::environment::EnvironmentMap;
+use metal_ray_tracing_rs::random::hash_seed;
+use metal_ray_tracing_rs::sampler::*;
+use metal_ray_tracing_rs::settings::RenderSettings;
+use metal_ray_tracing_rs::types::*;
+
+mod common;
+
+fn samplers(seed: u64) -> Vec<(SamplerType, Box<dyn Sampler>)>
+{
+    let seed = hash_seed(seed);
+    vec![(SamplerType::Independent, Box::new(IndependentSampler { seed })), (SamplerType::Sobol, Box::new(SobolSampler { seed })),
+         (SamplerType::BlueNoise, Box::new(BlueNoiseSampler { seed }))]
+}
+
+// Largest difference between the fraction of points in a box anchored at the origin and its area, over the boxes
+// with corners on a 64 x 64 grid
+fn star_discrepancy(points: &[Vector2<f32>]) -> f32
+{
+    let mut discrepancy: f32 = 0.0;
+    for i in 1..=64 {
+        for j in 1..=64 {
+            let corner = vec2(i as f32 / 64.0, j as f32 / 64.0);
+            let inside = points.iter().filter(|point| point.x < corner.x && point.y < corner.y).count();
+            discrepancy = discrepancy.max((inside as f32 / points.len() as f32 - corner.x * corner.y).abs());
+        }
+    }
+    discrepancy
+}
+
+type Integrand = fn(Vector3<f32>) -> f32;
+
+// Error of the sample means of 'function' over the first 'count' samples, with the pixels as independent trials
+fn integration_rmse(sampler: &dyn Sampler, function: Integrand, integral: f32, count: u32) -> f32
+{
+    let mut squared_error = 0.0;
+    for pixel in 0..256 {
+        let estimate = (0..count).map(|sample| function(sampler.sample3((pixel % 16, pixel / 16), sample, 2))).sum::<f32>() / count as f32;
+        squared_error += (estimate - integral) * (estimate - integral);
+    }
+    (squared_error / 256.0).sqrt()
+}
+
+fn pearson(a: &[f32], b: &[f32]) -> f32
+{
+    let n = a.len() as f32;
+    let (mean_a, mean_b) = (a.iter().sum::<f32>() / n, b.iter().sum::<f32>() / n);
+    let covariance: f32 = a.iter().zip(b.iter()).map(|(x, y)| (x - mean_a) * (y - mean_b)).sum();
+    let variance_a: f32 = a.iter().map(|x| (x - mean_a) * (x - mean_a)).sum();
+    let variance_b: f32 = b.iter().map(|y| (y - mean_b) * (y - mean_b)).sum();
+    covariance / (variance_a * variance_b).sqrt()
+}
+
+#[test]
+fn samples_are_deterministic_and_in_the_unit_interval()
+{
+    for (sampler_type, sampler) in samplers(5).iter() {
+        for pixel in 0..64 {
+            for sample in 0..64 {
+                let coordinates = (pixel % 8, pixel / 8);
+                let value = sampler.sample3(coordinates, sample, 7);
+                assert!((0..3).all(|i| value[i] >= 0.0 && value[i] < 1.0), "{:?}: {:?}", sampler_type, value);
+                assert_eq!(value, sampler.sample3(coordinates, sample, 7));
+                assert_eq!(value, sample3(*sampler_type, hash_seed(5), coordinates, sample, 7));
+            }
+        }
+        assert_ne!(sampler.sample3((0, 0), 0, 0), samplers(6).iter().find(|(other, _)| other == sampler_type).unwrap().1.sample3((0, 0), 0, 0));
+    }
+}
+
+#[test]
+fn sobol_samples_stratify_elementary_intervals()
+{
+    // Every power of two of samples has one sample in each interval of that size, whatever the shape of the interval
+    let sampler = SobolSampler { seed: hash_seed(1) };
+    for &log_count in [2, 4, 6, 8].iter() {
+        let count = 1 << log_count;
+        for &(coordinates, layer) in [((0, 0), 0), ((3, 7), 1), ((100, 2), 9)].iter() {
+            let samples: Vec<Vector3<f32>> = (0..count).map(|sample| sampler.sample3(coordinates, sample, layer)).collect();
+            for log_columns in 0..=log_count {
+                let (columns, rows) = (1 << log_columns, 1 << (log_count - log_columns));
+                let mut counts = vec![0; count as usize];
+                for sample in samples.iter() {
+                    counts[(sample.x * columns as f32) as usize + columns * (sample.y * rows as f32) as usize] += 1;
+                }
+                assert!(counts.iter().all(|&count| count == 1), "{} samples in {} x {} intervals: {:?}", count, columns, rows, counts);
+            }
+
+            let mut counts = vec![0; count as usize];
+            for sample in samples.iter() {
+                counts[(sample.z * count as f32) as usize] += 1;
+            }
+            assert!(counts.iter().all(|&count| count == 1), "third dimension: {:?}", counts);
+        }
+    }
+}
+
+#[test]
+fn low_discrepancy_samplers_have_lower_discrepancy()
+{
+    let mut discrepancies = Vec::new();
+    for (sampler_type, sampler) in samplers(2).iter() {
+        let mean = (0..16).map(|pixel| {
+            let points: Vec<Vector2<f32>> = (0..256).map(|sample| sampler.sample3((pixel, 3), sample, 4).truncate()).collect();
+            star_discrepancy(&points)
+        }).sum::<f32>() / 16.0;
+        discrepancies.push((*sampler_type, mean));
+    }
+    // Random points have a discrepancy of about 1 / sqrt(N), the low discrepancy sequences of about log(N) / N
+    let independent = discrepancies[0].1;
+    assert!(independent > 0.03 && independent < 0.1, "{:?}", discrepancies);
+    assert!(discrepancies[1].1 < 0.25 * independent, "{:?}", discrepancies);
+    assert!(discrepancies[2].1 < 0.5 * independent, "{:?}", discrepancies);
+}
+
+#[test]
+fn low_discrepancy_samplers_integrate_known_functions_better()
+{
+    let functions: [(&str, Integrand, f32); 3] = [
+        ("product", |u| 8.0 * u.x * u.y * u.z, 1.0),
+        ("cosine", |u| (std::f32::consts::PI * u.x).sin() * (2.0 * u.y + u.z), 2.0 / std::f32::consts::PI * 1.5),
+        ("disk", |u| if u.x * u.x + u.y * u.y < 1.0 { 1.0 } else { 0.0 }, std::f32::consts::FRAC_PI_4)];
+    for (name, function, integral) in functions.iter() {
+        let errors: Vec<f32> = samplers(3).iter().map(|(_, sampler)| integration_rmse(sampler.as_ref(), *function, *integral, 64)).collect();
+        assert!(errors[1] < 0.5 * errors[0], "{}: {:?}", name, errors);
+        assert!(errors[2] < 0.75 * errors[0], "{}: {:?}", name, errors);
+
+        // More samples help the sequences more than independent numbers
+        let more: Vec<f32> = samplers(3).iter().map(|(_, sampler)| integration_rmse(sampler.as_ref(), *function, *integral, 256)).collect();
+        assert!(more[1] / errors[1] < more[0] / errors[0], "{}: {:?} {:?}", name, errors, more);
+    }
+}
+
+#[test]
+fn layers_and_dimensions_are_uncorrelated()
+{
+    for (sampler_type, sampler) in samplers(4).iter() {
+        let (mut first, mut next_layer, mut next_dimension) = (Vec::new(), Vec::new(), Vec::new());
+        for pixel in 0..256 * 256 {
+            for sample in 0..16 {
+                let value = sampler.sample3((pixel % 256, pixel / 256), sample, 2);
+                first.push(value.x);
+                next_dimension.push(value.y);
+                next_layer.push(sampler.sample3((pixel % 256, pixel / 256), sample, 3).x);
+            }
+        }
+        // Within a tile of the blue noise sampler the layers of a sample are shifted copies of each other, so only the
+        // tiles and samples count as independent trials
+        let trials = (256 * 256 / (BLUE_NOISE_TILE_SIZE * BLUE_NOISE_TILE_SIZE) * 16) as f32;
+        let bound = 3.0 / trials.sqrt();
+        assert!(pearson(&first, &next_layer).abs() < bound, "{:?}: {}", sampler_type, pearson(&first, &next_layer));
+        assert!(pearson(&first, &next_dimension).abs() < bound, "{:?}: {}", sampler_type, pearson(&first, &next_dimension));
+    }
+}
+
+#[test]
+fn blue_noise_spreads_neighbouring_pixels()
+{
+    // The mean of the first sample over 4 x 4 blocks of pixels varies much less than for independent numbers
+    let block_variance = |sampler: &dyn Sampler| {
+        let means: Vec<f32> = (0..64).map(|block| {
+            let corner = (4 * (block % 8), 4 * (block / 8));
+            (0..16).map(|i| sampler.sample3((corner.0 + i % 4, corner.1 + i / 4), 0, 5).x).sum::<f32>() / 16.0
+        }).collect();
+        means.iter().map(|mean| (mean - 0.5) * (mean - 0.5)).sum::<f32>() / means.len() as f32
+    };
+    let variances: Vec<f32> = samplers(7).iter().map(|(_, sampler)| block_variance(sampler.as_ref())).collect();
+    assert!(variances[2] < 0.25 * variances[0], "{:?}", variances);
+}
+
+#[test]
+fn renders_converge_faster_with_low_discrepancy_samplers()
+{
+    // Next event estimation of a constant sky over a diffuse floor, the exact radiance of the floor is its albedo
+    let rmse = |sampler| {
+        let floor = Material {diffuse: [0.5, 0.5, 0.5], ..Material::default()};
+        let mut scene = common::empty_scene(vec![floor]);
+        common::add_quad(&mut scene, [[-1000.0, 0.0, -1000.0], [-1000.0, 0.0, 1000.0], [1000.0, 0.0, 1000.0], [1000.0, 0.0, -1000.0]], 0);
+        scene.environment = Some(EnvironmentMap::constant([1.0, 1.0, 1.0]));
+        let settings = RenderSettings {sampler, light_sampling: LightSampling::Emitter, max_depth: 1, ..RenderSettings::default()};
+        let mut ray_tracer = CpuRayTracer::new(scene, 16, 16, &settings);
+        ray_tracer.set_camera(&Camera::new(vec3(0.0, 1.0, 0.0), vec3(0.0, 0.0, -0.01), vec3(0.0, 0.0, -1.0), 30.0, 1.0));
+        for ray_number in 0..64 {
+            ray_tracer.render(ray_number);
+        }
+        let image = ray_tracer.output_image();
+        (image.iter().map(|pixel| (pixel[0] - 0.5) * (pixel[0] - 0.5)).sum::<f32>() / image.len() as f32).sqrt()
+    };
+    let independent = rmse(SamplerType::Independent);
+    let sobol = rmse(SamplerType::Sobol);
+    let blue_noise = rmse(SamplerType::BlueNoise);
+    assert!(sobol < 0.5 * independent, "{} {}", sobol, independent);
+    assert!(blue_noise < 0.75 * independent, "{} {}", blue_noise, independent);
+}
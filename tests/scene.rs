@@ -0,0 +1,142 @@
+use std::io::Cursor;
+use std::path::Path;
+use metal_ray_tracing_rs::scene::*;
+use metal_ray_tracing_rs::types::MaterialType;
+
+const OBJ: &str = "
+mtllib box.mtl
+o unassigned
+v 0 0 0
+v 1 0 0
+v 0 1 0
+f 1 2 3
+o floor
+v -1 0 -1
+v 1 0 -1
+v 1 0 1
+v -1 0 1
+usemtl white
+f 4 6 5
+f 4 7 6
+o light
+v -0.5 2 -0.5
+v 0.5 2 -0.5
+v 0.5 2 0.5
+usemtl light
+f 8 9 10
+";
+
+const MTL: &str = "
+newmtl white
+Kd 0.7 0.7 0.7
+Ke 0 0 0
+
+newmtl light
+Kd 0 0 0
+Ke 10 9 8
+";
+
+fn load(obj: &str, mtl: &'static str) -> Result<Scene, SceneError>
+{
+    let (models, materials) = tobj::load_obj_buf(&mut Cursor::new(obj), |_: &Path| tobj::load_mtl_buf(&mut Cursor::new(mtl)))?;
+    Scene::from_models(&models, &materials)
+}
+
+#[test]
+fn flattens_meshes_into_a_single_triangle_list()
+{
+    let scene = load(OBJ, MTL).unwrap();
+
+    assert_eq!(scene.triangle_count(), 4);
+    assert_eq!(scene.vertices.len(), 3 * 10);
+    assert_eq!(scene.triangles.len(), 4);
+    assert!(scene.indices.iter().all(|&i| (i as usize) < scene.vertices.len() / 3));
+
+    // The light triangle refers to the vertices of the last mesh
+    let light = scene.triangle_vertices(3);
+    assert_eq!(light[0], cgmath::vec3(-0.5, 2.0, -0.5));
+
+    // Two materials from the MTL file and a default one for the mesh without
+    assert_eq!(scene.materials.len(), 3);
+    assert_eq!(scene.materials[scene.triangles[1].material_index as usize].diffuse, [0.7, 0.7, 0.7]);
+    assert_eq!(scene.triangles[0].material_index, 2);
+}
+
+#[test]
+fn extracts_emitters_with_areas()
+{
+    let scene = load(OBJ, MTL).unwrap();
+
+    // 'Ke 0 0 0' does not make the white material an emitter
+    assert_eq!(scene.emitter_triangles.len(), 1);
+    let emitter = scene.emitter_triangles[0];
+    assert_eq!(emitter.primitive_index, 3);
+    assert_eq!(emitter.emissive, [10.0, 9.0, 8.0]);
+    assert!((emitter.area - 0.5).abs() < 1e-6);
+    assert!((scene.emitter_total_area - 0.5).abs() < 1e-6);
+}
+
+#[test]
+fn reports_malformed_emissive_values()
+{
+    let error = load(OBJ, "newmtl white\nKd 1 1 1\nKe 1 x 1\n").err().unwrap();
+    assert_eq!(error.to_string(), "material 'white' has an invalid Ke value '1 x 1'");
+}
+
+#[test]
+fn reads_specular_materials()
+{
+    let mtl = "
+newmtl white
+Kd 0.7 0.7 0.7
+illum 2
+
+newmtl gold
+Ks 1 0.78 0.34
+Ns 98
+illum 3
+
+newmtl glass
+Ni 1.45
+illum 7
+
+newmtl brushed
+Ks 0.9 0.9 0.9
+Pr 0.3
+illum 3
+
+newmtl mirror
+Ks 0.9 0.9 0.9
+illum 5
+
+newmtl plastic
+Ks 0.04 0.04 0.04
+illum 2
+";
+    let scene = load(OBJ, mtl).unwrap();
+
+    assert_eq!(scene.materials[0].material_type, MaterialType::Diffuse);
+    assert_eq!(scene.materials[1].material_type, MaterialType::Metal);
+    assert_eq!(scene.materials[1].specular, [1.0, 0.78, 0.34]);
+    assert!((scene.materials[1].roughness - 0.02f32.sqrt()).abs() < 1e-6);
+    assert_eq!(scene.materials[2].material_type, MaterialType::Dielectric);
+    assert_eq!(scene.materials[2].ior, 1.45);
+    assert!((scene.materials[3].roughness - 0.09).abs() < 1e-6);
+    // Without Ns the reflective illumination models are mirrors, the others are rough
+    assert_eq!((scene.materials[4].material_type, scene.materials[4].roughness), (MaterialType::Metal, 0.0));
+    assert_eq!(scene.materials[5].roughness, 1.0);
+
+    let error = load(OBJ, "newmtl glass\nNi 0\nillum 7\n").err().unwrap();
+    assert_eq!(error.to_string(), "material 'glass' has an invalid Ni value '0'");
+    let error = load(OBJ, "newmtl white\nPr 2\n").err().unwrap();
+    assert_eq!(error.to_string(), "material 'white' has an invalid Pr value '2'");
+}
+
+#[test]
+fn reports_missing_files()
+{
+    match Scene::load(Path::new("does/not/exist.obj")) {
+        Err(SceneError::Load(_)) => (),
+        _ => panic!("expected a load error")
+    }
+}
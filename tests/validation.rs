@@ -0,0 +1,214 @@
+use cgmath::*;
+use std::f32::consts::PI;
+use metal_ray_tracing_rs::camera::Camera;
+use metal_ray_tracing_rs::compare::downsample;
+use metal_ray_tracing_rs::cpu::*;
+use metal_ray_tracing_rs::emitters::*;
+use metal_ray_tracing_rs::environment::EnvironmentMap;
+use metal_ray_tracing_rs::random::{hash_seed, random3};
+use metal_ray_tracing_rs::scene::Scene;
+use metal_ray_tracing_rs::settings::RenderSettings;
+use metal_ray_tracing_rs::types::*;
+
+mod common;
+
+// Scenes with a known exact solution. The renders must converge to it for every light sampling strategy, or the
+// estimator in handleIntersections is biased.
+
+const STRATEGIES: [LightSampling; 4] = [LightSampling::Emitter, LightSampling::Bsdf, LightSampling::MisBalance, LightSampling::MisPower];
+
+fn render(scene: Scene, settings: &RenderSettings, camera: &Camera, size: usize, samples: usize) -> Vec<[f32; 4]>
+{
+    let mut ray_tracer = CpuRayTracer::new(scene, size, size, settings);
+    ray_tracer.set_camera(camera);
+    for ray_number in 0..samples {
+        ray_tracer.render(ray_number);
+    }
+    ray_tracer.output_image().to_vec()
+}
+
+fn mean(image: &[[f32; 4]]) -> Vector3<f32>
+{
+    image.iter().fold(Vector3::zero(), |sum, pixel| sum + vec3(pixel[0], pixel[1], pixel[2])) / image.len() as f32
+}
+
+// An albedo one sphere under a constant sky of one
+fn white_furnace() -> Scene
+{
+    let mut scene = common::empty_scene(vec![Material {diffuse: [1.0, 1.0, 1.0], ..Material::default()}]);
+    common::add_sphere(&mut scene, [0.0, 0.0, 0.0], 1.0, 16, 32, 0);
+    scene.environment = Some(EnvironmentMap::constant([1.0, 1.0, 1.0]));
+    scene
+}
+
+// The inside of a cube whose walls all emit 'emission' and reflect 'albedo' of the light, everywhere the radiance is
+// the sum of the emission after any number of bounces, emission / (1 - albedo)
+fn emitting_cube(emission: f32, albedo: f32) -> Scene
+{
+    let wall = Material {diffuse: [albedo; 3], emissive: [emission; 3], ..Material::default()};
+    let mut scene = common::empty_scene(vec![wall]);
+    // Quads facing the inside of the cube [-1, 1]^3
+    common::add_quad(&mut scene, [[-1.0, -1.0, -1.0], [-1.0, -1.0, 1.0], [1.0, -1.0, 1.0], [1.0, -1.0, -1.0]], 0);
+    common::add_quad(&mut scene, [[-1.0, 1.0, -1.0], [1.0, 1.0, -1.0], [1.0, 1.0, 1.0], [-1.0, 1.0, 1.0]], 0);
+    common::add_quad(&mut scene, [[-1.0, -1.0, -1.0], [-1.0, 1.0, -1.0], [-1.0, 1.0, 1.0], [-1.0, -1.0, 1.0]], 0);
+    common::add_quad(&mut scene, [[1.0, -1.0, -1.0], [1.0, -1.0, 1.0], [1.0, 1.0, 1.0], [1.0, 1.0, -1.0]], 0);
+    common::add_quad(&mut scene, [[-1.0, -1.0, -1.0], [1.0, -1.0, -1.0], [1.0, 1.0, -1.0], [-1.0, 1.0, -1.0]], 0);
+    common::add_quad(&mut scene, [[-1.0, -1.0, 1.0], [-1.0, 1.0, 1.0], [1.0, 1.0, 1.0], [1.0, -1.0, 1.0]], 0);
+    scene
+}
+
+// Light of a Lambertian emitter of unit radiance covering [0, a] x [0, b] at height h over a point at the origin
+// facing it, the form factor from a differential area to a parallel rectangle above its corner
+fn corner_irradiance(a: f32, b: f32, h: f32) -> f32
+{
+    let (x, y) = (a / h, b / h);
+    let (sx, sy) = ((1.0 + x * x).sqrt(), (1.0 + y * y).sqrt());
+    (x / sx * (y / sx).atan() + y / sy * (x / sy).atan()) / (2.0 * PI)
+}
+
+// The same for a rectangle [x0, x1] x [z0, z1] anywhere at height h, as a signed sum of rectangles at the origin
+fn rectangle_irradiance(x: [f32; 2], z: [f32; 2], h: f32) -> f32
+{
+    let corner = |x: f32, z: f32| x.signum() * z.signum() * corner_irradiance(x.abs(), z.abs(), h);
+    corner(x[1], z[1]) - corner(x[0], z[1]) - corner(x[1], z[0]) + corner(x[0], z[0])
+}
+
+// Solid angle of a triangle seen from the origin (Van Oosterom and Strackee 1983)
+fn triangle_solid_angle(a: Vector3<f32>, b: Vector3<f32>, c: Vector3<f32>) -> f32
+{
+    let (la, lb, lc) = (a.magnitude(), b.magnitude(), c.magnitude());
+    let numerator = a.dot(b.cross(c)).abs();
+    let denominator = la * lb * lc + a.dot(b) * lc + a.dot(c) * lb + b.dot(c) * la;
+    2.0 * numerator.atan2(denominator)
+}
+
+#[test]
+fn white_furnace_has_the_radiance_of_the_sky()
+{
+    let camera = Camera::new(vec3(0.0, 0.0, 3.0), vec3(0.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0), 45.0, 1.0);
+    for &light_sampling in STRATEGIES.iter() {
+        for &sampler in [SamplerType::Independent, SamplerType::Sobol].iter() {
+            let settings = RenderSettings {light_sampling, sampler, ..RenderSettings::default()};
+            let image = render(white_furnace(), &settings, &camera, 16, 256);
+            let radiance = mean(&image);
+            assert!((0..3).all(|i| (radiance[i] - 1.0).abs() < 0.02), "{:?} {:?}: {:?}", light_sampling, sampler, radiance);
+            // Also the blocks on the silhouette of the sphere, where rays graze the surface
+            for block in downsample(&image, 16, 16, 4).iter() {
+                assert!((block[0] - 1.0).abs() < 0.1, "{:?} {:?}: {:?}", light_sampling, sampler, block);
+            }
+        }
+    }
+}
+
+#[test]
+fn emitting_cube_converges_to_the_geometric_series()
+{
+    // Russian roulette ends the paths without bias, the depth limit leaves out 0.5^17 of the light
+    let camera = Camera::new(vec3(0.0, 0.0, 0.5), vec3(0.3, -0.2, -1.0), vec3(0.0, 1.0, 0.0), 90.0, 1.0);
+    for &light_sampling in STRATEGIES.iter() {
+        for &emitter_selection in [EmitterSelection::Power, EmitterSelection::LightTree].iter() {
+            let settings = RenderSettings {light_sampling, emitter_selection, max_depth: 16, ..RenderSettings::default()};
+            let radiance = mean(&render(emitting_cube(0.5, 0.5), &settings, &camera, 8, 256));
+            assert!((0..3).all(|i| (radiance[i] - 1.0).abs() < 0.02), "{:?} {:?}: {:?}", light_sampling, emitter_selection, radiance);
+        }
+    }
+}
+
+#[test]
+fn diffuse_plane_under_a_quad_light_has_the_exact_radiance()
+{
+    // The camera looks straight down at a tiny patch of the floor, which reflects albedo / pi of the irradiance
+    let (x, z, height, albedo) = ([-0.5, 0.5], [-0.25, 0.75], 1.0, 0.8);
+    let scene = || {
+        let floor = Material {diffuse: [albedo; 3], ..Material::default()};
+        let light = Material {emissive: [1.0, 1.0, 1.0], ..Material::default()};
+        let mut scene = common::empty_scene(vec![floor, light]);
+        common::add_quad(&mut scene, [[-10.0, 0.0, -10.0], [-10.0, 0.0, 10.0], [10.0, 0.0, 10.0], [10.0, 0.0, -10.0]], 0);
+        common::add_quad(&mut scene, [[x[0], height, z[0]], [x[1], height, z[0]], [x[1], height, z[1]], [x[0], height, z[1]]], 1);
+        scene
+    };
+    for &point in [vec3(0.0, 0.0, 0.0), vec3(0.3, 0.0, 0.6), vec3(1.2, 0.0, -0.4)].iter() {
+        let expected = albedo * rectangle_irradiance([x[0] - point.x, x[1] - point.x], [z[0] - point.z, z[1] - point.z], height);
+        let camera = Camera::new(point + vec3(0.0, 0.5, 0.0), point, vec3(0.0, 0.0, -1.0), 1.0, 1.0);
+        for &light_sampling in STRATEGIES.iter() {
+            let settings = RenderSettings {light_sampling, ..RenderSettings::default()};
+            let radiance = mean(&render(scene(), &settings, &camera, 4, 1024)).x;
+            assert!((radiance - expected).abs() < 0.01 * expected, "{:?} at {:?}: {} {}", light_sampling, point, radiance, expected);
+        }
+    }
+}
+
+#[test]
+fn emitter_samples_cover_the_solid_angle_of_the_triangle()
+{
+    // The mean of the inverse density over uniform samples of the triangle is the solid angle it covers
+    let light = Material {emissive: [1.0, 1.0, 1.0], ..Material::default()};
+    let mut scene = common::empty_scene(vec![light]);
+    common::add_triangle(&mut scene, [[-1.0, 2.0, -0.5], [1.5, 1.5, -1.0], [0.5, 2.5, 1.0]], 0);
+    let emitter = scene.emitter_triangles[0];
+    let [a, b, c] = scene.triangle_vertices(0);
+
+    for &point in [vec3(0.0, 0.0, 0.0), vec3(2.0, 1.0, 3.0), vec3(-0.2, 1.9, 0.1)].iter() {
+        let count = 256;
+        let mut inverse_pdf = 0.0;
+        for i in 0..count * count {
+            let u = vec2(((i % count) as f32 + 0.5) / count as f32, ((i / count) as f32 + 0.5) / count as f32);
+            let sample = sample_triangle(&scene, &emitter, point, u).unwrap();
+            inverse_pdf += 1.0 / sample.pdf / (count * count) as f32;
+        }
+        let solid_angle = triangle_solid_angle(a - point, b - point, c - point);
+        assert!((inverse_pdf - solid_angle).abs() < 1e-3 * solid_angle, "{:?}: {} {}", point, inverse_pdf, solid_angle);
+    }
+
+    // The back of the triangle does not emit
+    assert!(sample_triangle(&scene, &emitter, vec3(0.0, 4.0, 0.0), vec2(0.5, 0.5)).is_none());
+}
+
+#[test]
+fn bsdf_hits_and_emitter_samples_agree_on_the_density()
+{
+    // The density handleIntersections gives emitters hit by a ray integrates to one over the directions, and within
+    // the triangle it is the density of the emitter sample towards the same point
+    let light = Material {emissive: [1.0, 1.0, 1.0], ..Material::default()};
+    let mut scene = common::empty_scene(vec![light]);
+    common::add_quad(&mut scene, [[-1.0, 1.0, -1.0], [1.0, 1.0, -1.0], [1.0, 1.0, 1.0], [-1.0, 1.0, 1.0]], 0);
+    let bvh = Bvh::new(&scene.vertices, &scene.indices);
+    let emitters = EmitterDistribution::new(&scene);
+    let point = vec3(0.3, 0.0, -0.2);
+
+    let count = 1 << 20;
+    let mut integral = 0.0;
+    for sample in 0..count {
+        // Uniform directions over the upper hemisphere
+        let u = random3(hash_seed(0), (0, 0), sample, 0);
+        let (cos_theta, phi) = (u.x, 2.0 * PI * u.y);
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let direction = vec3(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin());
+        let ray = Ray {origin: point.into(), min_distance: EPSILON, direction: direction.into(), max_distance: f32::INFINITY, color: [0.0; 3],
+            throughput: [1.0; 3], bsdf_pdf: 0.0, normal: [0.0; 3], sample_index: 0};
+        let intersection = bvh.intersect_nearest(&ray);
+        if intersection.distance < 0.0 {
+            continue;
+        }
+        let [a, b, c] = scene.triangle_vertices(intersection.primitive_index);
+        let normal = (b - a).cross(c - a).normalize();
+        let area = 0.5 * (b - a).cross(c - a).magnitude();
+        let pdf = point_sample_pdf(area, intersection.distance, -direction.dot(normal));
+        let triangle_pdf = emitters.pdf(EmitterSelection::Power, point, vec3(0.0, 1.0, 0.0), intersection.primitive_index);
+        integral += triangle_pdf * pdf * 2.0 * PI / count as f32;
+
+        if sample % 1024 == 0 {
+            // Points on the triangle are barycentric weights of its corners, find the random numbers that sample it
+            let hit = point + intersection.distance * direction;
+            let emitter = scene.emitter_triangles.iter().find(|emitter| emitter.primitive_index == intersection.primitive_index).unwrap();
+            let weights = intersection.coordinates;
+            let (w0, w1) = (weights[0], weights[1]);
+            let r1 = 1.0 - w0;
+            let u = vec2(r1 * r1, if r1 > 0.0 { (1.0 - w0 - w1) / r1 } else { 0.0 });
+            let light_sample = sample_triangle(&scene, emitter, point, u).unwrap();
+            assert!(light_sample.direction.dot(direction) > 0.9999, "{:?} {:?} {:?}", light_sample.direction, direction, hit);
+            assert!((light_sample.pdf - pdf).abs() < 1e-3 * pdf, "{} {}", light_sample.pdf, pdf);
+        }
+    }
+    assert!((integral - 1.0).abs() < 0.01, "{}", integral);
+}